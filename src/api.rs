@@ -3,7 +3,7 @@
 // purpose: OpenAI-compatible API proxy mode for SQ
 //          Triages prompts: cache → local ollama → upstream provider
 //
-// Usage: sq api <config.json> [port]
+// Usage: sq api <config.json> [port] [--bind <addr>]...
 // Non-breaking addition — existing `sq route` tenant proxy is unchanged.
 //
 // v0.6.0
@@ -11,11 +11,12 @@
 
 use serde::{Deserialize, Serialize};
 use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
+use std::net::TcpStream;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 use crate::cache::PromptCache;
+use crate::socket::{Listener, Stream};
 use crate::triage::{self, Tier, FeedbackLoop};
 
 // -----------------------------------------------------------------------------------------------------------
@@ -73,10 +74,7 @@ struct ChatMessage {
 
 #[derive(Debug, Deserialize)]
 struct ChatRequest {
-    model: Option<String>,
-    messages: Vec<ChatMessage>,
-    #[serde(default)]
-    max_tokens: Option<u32>,
+    messages: Vec<ChatMessage>, // the rest of the request is forwarded untouched
 }
 
 #[derive(Debug, Serialize)]
@@ -134,7 +132,7 @@ fn make_chat_response(content: &str, model: &str) -> String {
 // HTTP helpers (reuse patterns from main.rs / router.rs)
 // -----------------------------------------------------------------------------------------------------------
 
-fn read_request(stream: &mut TcpStream) -> Result<(String, String), Box<dyn std::error::Error>> {
+fn read_request(stream: &mut Stream) -> Result<(String, String), Box<dyn std::error::Error>> {
     let mut buf = vec![0u8; 65536];
     let mut total = 0;
 
@@ -188,7 +186,7 @@ fn extract_content_length(header: &str) -> usize {
     0
 }
 
-fn send_json_response(stream: &mut TcpStream, status: u16, body: &str) {
+fn send_json_response(stream: &mut Stream, status: u16, body: &str) {
     let status_text = match status {
         200 => "OK",
         400 => "Bad Request",
@@ -205,7 +203,7 @@ fn send_json_response(stream: &mut TcpStream, status: u16, body: &str) {
     let _ = stream.write_all(response.as_bytes());
 }
 
-fn send_cors_preflight(stream: &mut TcpStream) {
+fn send_cors_preflight(stream: &mut Stream) {
     let response = "HTTP/1.1 204 No Content\r\n\
         Access-Control-Allow-Origin: *\r\n\
         Access-Control-Allow-Methods: GET, POST, OPTIONS\r\n\
@@ -330,7 +328,7 @@ fn validate_request(body: &str) -> Option<String> {
 // Main API proxy server
 // -----------------------------------------------------------------------------------------------------------
 
-pub fn run_api(config_path: &str, listen_port: u16) -> Result<(), Box<dyn std::error::Error>> {
+pub fn run_api(config_path: &str, listeners: Vec<Listener>) -> Result<(), Box<dyn std::error::Error>> {
     let config = load_api_config(config_path)?;
     let cache = Arc::new(Mutex::new(PromptCache::new(config.cache_max_entries, config.cache_ttl_secs)));
     let feedback = Arc::new(Mutex::new(FeedbackLoop::new(config.feedback_window)));
@@ -340,160 +338,152 @@ pub fn run_api(config_path: &str, listen_port: u16) -> Result<(), Box<dyn std::e
     println!("║             SQ API Proxy v0.6.0                         ║");
     println!("╚══════════════════════════════════════════════════════════╝");
     println!();
-    println!("Listening on: 0.0.0.0:{}", listen_port);
+    println!("Listening on: {}", listeners.iter().map(|l| l.to_string()).collect::<Vec<_>>().join(", "));
     println!("Local model:  {} @ {}", config.local_model, config.local_url);
     println!("Upstream:     {}", config.upstream_url);
     println!("Cache:        {} entries, {} sec TTL", config.cache_max_entries, config.cache_ttl_secs);
     println!();
 
-    let listener = TcpListener::bind(format!("0.0.0.0:{}", listen_port))?;
+    crate::socket::serve(listeners, move |mut client| {
+        let config = Arc::clone(&config);
+        let cache = Arc::clone(&cache);
+        let feedback = Arc::clone(&feedback);
 
-    for stream in listener.incoming() {
-        match stream {
-            Ok(mut client) => {
-                let config = Arc::clone(&config);
-                let cache = Arc::clone(&cache);
-                let feedback = Arc::clone(&feedback);
+        std::thread::spawn(move || {
+            let _ = client.set_read_timeout(Some(Duration::from_secs(30)));
+            let _ = client.set_write_timeout(Some(Duration::from_secs(30)));
 
-                std::thread::spawn(move || {
-                    let _ = client.set_read_timeout(Some(Duration::from_secs(30)));
-                    let _ = client.set_write_timeout(Some(Duration::from_secs(30)));
+            let (header, body) = match read_request(&mut client) {
+                Ok(r) => r,
+                Err(_) => return,
+            };
 
-                    let (header, body) = match read_request(&mut client) {
-                        Ok(r) => r,
-                        Err(_) => return,
-                    };
-
-                    // CORS preflight
-                    if header.starts_with("OPTIONS ") {
-                        send_cors_preflight(&mut client);
-                        return;
-                    }
-
-                    // Stats endpoint
-                    if header.starts_with("GET /stats") {
-                        let c = cache.lock().unwrap();
-                        let (hits, misses, size) = c.stats();
-                        let fl = feedback.lock().unwrap();
-                        let stats = serde_json::json!({
-                            "cache_hits": hits,
-                            "cache_misses": misses,
-                            "cache_size": size,
-                            "cache_hit_rate": c.hit_rate(),
-                            "local_failure_rate": fl.failure_rate(),
-                        });
-                        send_json_response(&mut client, 200, &stats.to_string());
-                        return;
-                    }
-
-                    // Only handle POST /v1/chat/completions
-                    if !header.starts_with("POST ") {
-                        send_json_response(&mut client, 400, r#"{"error":"Only POST /v1/chat/completions supported"}"#);
-                        return;
-                    }
+            // CORS preflight
+            if header.starts_with("OPTIONS ") {
+                send_cors_preflight(&mut client);
+                return;
+            }
 
-                    // Validate request has messages
-                    let request_body = match validate_request(&body) {
-                        Some(b) => b,
-                        None => {
-                            send_json_response(&mut client, 400, r#"{"error":"Invalid request or no messages"}"#);
-                            return;
-                        }
-                    };
+            // Stats endpoint
+            if header.starts_with("GET /stats") {
+                let c = cache.lock().unwrap();
+                let (hits, misses, size) = c.stats();
+                let fl = feedback.lock().unwrap();
+                let stats = serde_json::json!({
+                    "cache_hits": hits,
+                    "cache_misses": misses,
+                    "cache_size": size,
+                    "cache_hit_rate": c.hit_rate(),
+                    "local_failure_rate": fl.failure_rate(),
+                });
+                send_json_response(&mut client, 200, &stats.to_string());
+                return;
+            }
 
-                    // Extract last user message for triage scoring + cache key
-                    let prompt = match extract_last_user_message(&body) {
-                        Some(p) => p,
-                        None => {
-                            // No user message — pass through to upstream as-is
-                            match proxy_to_upstream(&config, &request_body) {
-                                Ok(content) => {
-                                    let resp = make_chat_response(&content, &config.upstream_model);
-                                    send_json_response(&mut client, 200, &resp);
-                                }
-                                Err(e) => {
-                                    let err = serde_json::json!({"error": format!("{}", e)});
-                                    send_json_response(&mut client, 500, &err.to_string());
-                                }
-                            }
-                            return;
-                        }
-                    };
+            // Only handle POST /v1/chat/completions
+            if !header.starts_with("POST ") {
+                send_json_response(&mut client, 400, r#"{"error":"Only POST /v1/chat/completions supported"}"#);
+                return;
+            }
 
-                    // 1. Check static patterns (only for single-turn)
-                    if let Some(static_resp) = PromptCache::check_static(&prompt) {
-                        let req: Option<ChatRequest> = serde_json::from_str(&body).ok();
-                        let is_single_turn = req.map(|r| r.messages.len() <= 1).unwrap_or(false);
-                        if is_single_turn {
-                            let resp = make_chat_response(static_resp, "sq-cache");
+            // Validate request has messages
+            let request_body = match validate_request(&body) {
+                Some(b) => b,
+                None => {
+                    send_json_response(&mut client, 400, r#"{"error":"Invalid request or no messages"}"#);
+                    return;
+                }
+            };
+
+            // Extract last user message for triage scoring + cache key
+            let prompt = match extract_last_user_message(&body) {
+                Some(p) => p,
+                None => {
+                    // No user message — pass through to upstream as-is
+                    match proxy_to_upstream(&config, &request_body) {
+                        Ok(content) => {
+                            let resp = make_chat_response(&content, &config.upstream_model);
                             send_json_response(&mut client, 200, &resp);
-                            return;
                         }
-                    }
-
-                    // 2. Check cache (keyed on last user message — only for short conversations)
-                    {
-                        let mut c = cache.lock().unwrap();
-                        if let Some(cached) = c.get(&prompt) {
-                            let resp = make_chat_response(&cached, "sq-cache");
-                            send_json_response(&mut client, 200, &resp);
-                            return;
+                        Err(e) => {
+                            let err = serde_json::json!({"error": format!("{}", e)});
+                            send_json_response(&mut client, 500, &err.to_string());
                         }
                     }
+                    return;
+                }
+            };
+
+            // 1. Check static patterns (only for single-turn)
+            if let Some(static_resp) = PromptCache::check_static(&prompt) {
+                let req: Option<ChatRequest> = serde_json::from_str(&body).ok();
+                let is_single_turn = req.map(|r| r.messages.len() <= 1).unwrap_or(false);
+                if is_single_turn {
+                    let resp = make_chat_response(static_resp, "sq-cache");
+                    send_json_response(&mut client, 200, &resp);
+                    return;
+                }
+            }
 
-                    // 3. Triage (score based on last user message)
-                    let should_escalate = feedback.lock().unwrap().should_escalate(config.escalation_threshold);
-                    let mut decision = triage::evaluate(&prompt, config.signal_threshold);
+            // 2. Check cache (keyed on last user message — only for short conversations)
+            {
+                let mut c = cache.lock().unwrap();
+                if let Some(cached) = c.get(&prompt) {
+                    let resp = make_chat_response(&cached, "sq-cache");
+                    send_json_response(&mut client, 200, &resp);
+                    return;
+                }
+            }
 
-                    // Auto-escalate if local model is failing too much
-                    if decision.tier == Tier::Local && should_escalate {
-                        decision.tier = Tier::Upstream;
-                        decision.reason = format!("escalated: {}", decision.reason);
-                    }
+            // 3. Triage (score based on last user message)
+            let should_escalate = feedback.lock().unwrap().should_escalate(config.escalation_threshold);
+            let mut decision = triage::evaluate(&prompt, config.signal_threshold);
 
-                    println!("[triage] {} → {:?} ({})", &prompt[..prompt.len().min(60)], decision.tier, decision.reason);
-
-                    // 4. Dispatch — full message history forwarded to backend
-                    let result = match decision.tier {
-                        Tier::Cache => unreachable!(), // handled above
-                        Tier::Local => {
-                            match proxy_to_local(&config, &request_body) {
-                                Ok(resp) => {
-                                    feedback.lock().unwrap().record(true);
-                                    Ok(resp)
-                                }
-                                Err(e) => {
-                                    feedback.lock().unwrap().record(false);
-                                    eprintln!("[local error] {} — escalating to upstream", e);
-                                    proxy_to_upstream(&config, &request_body)
-                                }
-                            }
-                        }
-                        Tier::Upstream => proxy_to_upstream(&config, &request_body),
-                    };
+            // Auto-escalate if local model is failing too much
+            if decision.tier == Tier::Local && should_escalate {
+                decision.tier = Tier::Upstream;
+                decision.reason = format!("escalated: {}", decision.reason);
+            }
 
-                    match result {
-                        Ok(content) => {
-                            // Cache the response (keyed on last user message)
-                            cache.lock().unwrap().set(&prompt, &content);
-                            let model = if decision.tier == Tier::Local {
-                                config.local_model.as_str()
-                            } else {
-                                config.upstream_model.as_str()
-                            };
-                            let resp = make_chat_response(&content, model);
-                            send_json_response(&mut client, 200, &resp);
+            println!("[triage] {} → {:?} ({})", &prompt[..prompt.len().min(60)], decision.tier, decision.reason);
+
+            // 4. Dispatch — full message history forwarded to backend
+            let result = match decision.tier {
+                Tier::Local => {
+                    match proxy_to_local(&config, &request_body) {
+                        Ok(resp) => {
+                            feedback.lock().unwrap().record(true);
+                            Ok(resp)
                         }
                         Err(e) => {
-                            let err = serde_json::json!({"error": format!("{}", e)});
-                            send_json_response(&mut client, 500, &err.to_string());
+                            feedback.lock().unwrap().record(false);
+                            eprintln!("[local error] {} — escalating to upstream", e);
+                            proxy_to_upstream(&config, &request_body)
                         }
                     }
-                });
+                }
+                Tier::Upstream => proxy_to_upstream(&config, &request_body),
+            };
+
+            match result {
+                Ok(content) => {
+                    // Cache the response (keyed on last user message)
+                    cache.lock().unwrap().set(&prompt, &content);
+                    let model = if decision.tier == Tier::Local {
+                        config.local_model.as_str()
+                    } else {
+                        config.upstream_model.as_str()
+                    };
+                    let resp = make_chat_response(&content, model);
+                    send_json_response(&mut client, 200, &resp);
+                }
+                Err(e) => {
+                    let err = serde_json::json!({"error": format!("{}", e)});
+                    send_json_response(&mut client, 500, &err.to_string());
+                }
             }
-            Err(e) => eprintln!("Connection error: {}", e),
-        }
-    }
+        });
+    });
 
     Ok(())
 }
@@ -0,0 +1,238 @@
+//------------------------------------------------------------------------------------------------------------
+// file: conflict.rs
+// purpose: decides who wins when a pulled scroll differs from ours, and keeps the log of real conflicts
+//
+// Using the versions from versions.rs:
+//   - the peer's version descends from ours   → we're behind; take theirs (not a conflict)
+//   - our version descends from the peer's    → the peer is behind; keep ours (not a conflict)
+//   - neither                                 → both sides edited concurrently; mesh.conflict_strategy decides
+//
+// Strategies:
+//   last-writer-wins  the higher (stamp, node) version wins
+//   priority-wins     the writer with the better (lower) priority wins - peers use their PeerConfig.priority,
+//                     this node node.priority; unknown writers rank last. Ties fall back to last-writer-wins
+//   keep-both         last-writer-wins at the coordinate, and the losing scroll is written to the next free
+//                     scroll after it (the sibling), as a new local edit
+//
+// Every conflict is appended to `<file>.conflicts` (JSON lines) and served by GET /api/v2/mesh/conflicts.
+// Under the first two strategies the record holds the losing scroll, so nothing is lost for good.
+//------------------------------------------------------------------------------------------------------------
+
+use crate::mesh::MeshConfig;
+use crate::phext;
+use crate::store::ScrollStore;
+use crate::versions::ScrollVersion;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictStrategy {
+    #[default]
+    LastWriterWins,
+    PriorityWins,
+    KeepBoth,
+}
+
+impl ConflictStrategy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConflictStrategy::LastWriterWins => "last-writer-wins",
+            ConflictStrategy::PriorityWins => "priority-wins",
+            ConflictStrategy::KeepBoth => "keep-both",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resolution {
+    TakeRemote,
+    KeepLocal,
+    Conflict { remote_wins: bool },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConflictRecord {
+    pub time: u64,
+    pub coordinate: String,
+    pub peer: String,
+    pub strategy: ConflictStrategy,
+    pub winner: String, // "local" or "remote"
+    pub local: ScrollVersion,
+    pub remote: ScrollVersion,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sibling: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lost: Option<String>,
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// priority: a writer's priority under priority-wins (lower wins)
+// -----------------------------------------------------------------------------------------------------------
+pub fn priority(config: &MeshConfig, node: &str) -> u8 {
+    if node == config.node.id {
+        return config.node.priority;
+    }
+    config.outbound.peers.iter().find(|p| p.id == node).map(|p| p.priority).unwrap_or(u8::MAX)
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// resolve: what to do with a pulled scroll whose text differs from ours
+// -----------------------------------------------------------------------------------------------------------
+pub fn resolve(local: &ScrollVersion, remote: &ScrollVersion, strategy: ConflictStrategy, config: &MeshConfig) -> Resolution {
+    if local.version != remote.version {
+        if remote.descends_from(local) {
+            return Resolution::TakeRemote;
+        }
+        if local.descends_from(remote) {
+            return Resolution::KeepLocal;
+        }
+    }
+    // equal versions with different text have no shared history either (e.g. two unversioned copies);
+    // on a full tie the pulled scroll wins, as it did before versioning
+    let newer = remote.version >= local.version;
+    let remote_wins = match strategy {
+        ConflictStrategy::LastWriterWins | ConflictStrategy::KeepBoth => newer,
+        ConflictStrategy::PriorityWins => {
+            let ours = priority(config, &local.version.node);
+            let theirs = priority(config, &remote.version.node);
+            if ours == theirs { newer } else { theirs < ours }
+        }
+    };
+    Resolution::Conflict { remote_wins }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// sibling: the first empty scroll after `coordinate` that isn't `taken`, for keep-both's losing copy
+// -----------------------------------------------------------------------------------------------------------
+pub fn sibling<F: Fn(&phext::Coordinate) -> bool>(coordinate: phext::Coordinate, store: &ScrollStore, taken: F) -> phext::Coordinate {
+    let mut candidate = coordinate;
+    loop {
+        candidate.x.scroll += 1;
+        if !store.contains_key(&candidate) && !taken(&candidate) {
+            return candidate;
+        }
+    }
+}
+
+pub fn log_path(phext_path: &str) -> String {
+    format!("{}.conflicts", phext_path)
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// record: appends conflicts to the phext's conflict log
+// -----------------------------------------------------------------------------------------------------------
+pub fn record(phext_path: &str, records: &[ConflictRecord]) {
+    if records.is_empty() {
+        return;
+    }
+    let path = log_path(phext_path);
+    let mut buffer = String::new();
+    for record in records {
+        buffer.push_str(&serde_json::to_string(record).unwrap_or_default());
+        buffer.push('\n');
+    }
+    let result = std::fs::OpenOptions::new().create(true).append(true).open(&path)
+        .and_then(|mut file| file.write_all(buffer.as_bytes()));
+    if let Err(e) = result {
+        eprintln!("Warning: Failed to record {} conflicts in {}: {}", records.len(), path, e);
+    }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// report: the newest `limit` conflicts of a phext, oldest first, as a JSON array
+// -----------------------------------------------------------------------------------------------------------
+pub fn report(phext_path: &str, limit: usize) -> String {
+    let content = std::fs::read_to_string(log_path(phext_path)).unwrap_or_default();
+    let records: Vec<ConflictRecord> = content.lines().filter_map(|line| serde_json::from_str(line).ok()).collect();
+    let start = records.len().saturating_sub(limit);
+    serde_json::to_string_pretty(&records[start..]).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::PeerConfig;
+    use crate::versions::Version;
+
+    fn version(stamp: u64, node: &str) -> ScrollVersion {
+        ScrollVersion { version: Version { stamp, node: node.to_string() }, history: Vec::new(), deleted: false }
+    }
+
+    fn config() -> MeshConfig {
+        let mut config = MeshConfig::default();
+        config.node.id = "local".to_string();
+        config.node.priority = 5;
+        config.outbound.peers.push(PeerConfig {
+            id: "primary".to_string(),
+            name: "Primary".to_string(),
+            host: "127.0.0.1".to_string(),
+            port: 1337,
+            auth_key: String::new(),
+            inbound_key: String::new(),
+            coordinate: "1.1.1/1.1.1/1.1.1".to_string(),
+            priority: 1,
+            include: Vec::new(),
+            exclude: Vec::new(),
+        });
+        config
+    }
+
+    #[test]
+    fn test_descendants_are_not_conflicts() {
+        let config = config();
+        let base = version(10, "primary");
+        let ours = base.successor(20, "local");
+        let theirs = base.successor(15, "primary");
+        assert_eq!(resolve(&base, &theirs, ConflictStrategy::LastWriterWins, &config), Resolution::TakeRemote);
+        assert_eq!(resolve(&ours, &base, ConflictStrategy::LastWriterWins, &config), Resolution::KeepLocal);
+        assert_eq!(resolve(&ours, &ours.successor(30, "primary"), ConflictStrategy::PriorityWins, &config), Resolution::TakeRemote);
+    }
+
+    #[test]
+    fn test_strategies() {
+        let config = config();
+        let base = version(10, "primary");
+        let ours = base.successor(20, "local");
+        let theirs = base.successor(15, "primary");
+        assert_eq!(resolve(&ours, &theirs, ConflictStrategy::LastWriterWins, &config), Resolution::Conflict { remote_wins: false });
+        assert_eq!(resolve(&ours, &theirs, ConflictStrategy::KeepBoth, &config), Resolution::Conflict { remote_wins: false });
+        assert_eq!(resolve(&ours, &theirs, ConflictStrategy::PriorityWins, &config), Resolution::Conflict { remote_wins: true });
+
+        // unknown writers rank last, ties fall back to last-writer-wins
+        let stranger = base.successor(30, "stranger");
+        assert_eq!(resolve(&ours, &stranger, ConflictStrategy::PriorityWins, &config), Resolution::Conflict { remote_wins: false });
+        let unversioned = ScrollVersion::default();
+        assert_eq!(resolve(&unversioned, &unversioned, ConflictStrategy::LastWriterWins, &config), Resolution::Conflict { remote_wins: true });
+    }
+
+    #[test]
+    fn test_sibling_skips_taken_scrolls() {
+        let mut store = ScrollStore::default();
+        store.insert(phext::to_coordinate("1.1.1/1.1.1/1.1.2"), "x".to_string());
+        let taken = phext::to_coordinate("1.1.1/1.1.1/1.1.3");
+        let found = sibling(phext::to_coordinate("1.1.1/1.1.1/1.1.1"), &store, |c| *c == taken);
+        assert_eq!(found.to_string(), "1.1.1/1.1.1/1.1.4");
+    }
+
+    #[test]
+    fn test_log_keeps_newest() {
+        let dir = crate::tests::TempDir::new("conflicts");
+        let phext = dir.path("log.phext");
+        let records: Vec<ConflictRecord> = (0..3).map(|i| ConflictRecord {
+            time: i,
+            coordinate: "1.1.1/1.1.1/1.1.1".to_string(),
+            peer: "primary".to_string(),
+            strategy: ConflictStrategy::KeepBoth,
+            winner: "local".to_string(),
+            local: version(i, "local"),
+            remote: version(i, "primary"),
+            sibling: Some("1.1.1/1.1.1/1.1.2".to_string()),
+            lost: None,
+        }).collect();
+        record(&phext, &records);
+        let listed: Vec<ConflictRecord> = serde_json::from_str(&report(&phext, 2)).unwrap();
+        assert_eq!(listed.iter().map(|r| r.time).collect::<Vec<_>>(), vec![1, 2]);
+        assert!(report(&phext, 2).contains("\"strategy\": \"keep-both\""));
+    }
+}
@@ -0,0 +1,197 @@
+//------------------------------------------------------------------------------------------------------------
+// file: health.rs
+// purpose: mesh peer health monitor - probes every outbound peer and keeps the status /api/v2/mesh/status reports
+//
+// Each peer is probed with an authenticated GET /api/v2/version every health_check_interval_seconds, all peers
+// at once so one unreachable peer doesn't delay the others. A probe succeeds on HTTP 200; anything else
+// (refused, timeout, 401, ...) counts as a failure. The sync engine
+// (replication.rs) records the outcome of its rounds here too, so one report covers both.
+// Times are unix seconds.
+//------------------------------------------------------------------------------------------------------------
+
+use crate::http;
+use crate::mesh::MeshConfig;
+use serde::{Deserialize, Serialize};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const PROBE_TIMEOUT_SECS: u64 = 5;
+
+static STATUS: OnceLock<Mutex<MeshStatus>> = OnceLock::new();
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PeerHealth {
+    pub id: String,
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+    pub priority: u8,
+    pub healthy: bool,
+    pub latency_ms: Option<u64>,
+    pub last_check: Option<u64>,
+    pub last_success: Option<u64>,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+    pub last_sync: Option<u64>,
+    pub last_sync_scrolls: usize,
+    pub last_sync_error: Option<String>,
+    #[serde(default)]
+    pub conflicts: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MeshStatus {
+    pub enabled: bool,
+    pub node_id: String,
+    pub node_name: String,
+    pub health_check_interval_seconds: u64,
+    pub peers: Vec<PeerHealth>,
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn with_peer<F: FnOnce(&mut PeerHealth)>(peer_id: &str, update: F) {
+    if let Some(status) = STATUS.get() {
+        let mut status = status.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(peer) = status.peers.iter_mut().find(|p| p.id == peer_id) {
+            update(peer);
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// record_probe: folds one probe result into the peer's health
+// -----------------------------------------------------------------------------------------------------------
+fn record_probe(peer: &mut PeerHealth, result: Result<Duration, String>, now: u64) {
+    peer.last_check = Some(now);
+    match result {
+        Ok(latency) => {
+            peer.healthy = true;
+            peer.latency_ms = Some(latency.as_millis() as u64);
+            peer.last_success = Some(now);
+            peer.consecutive_failures = 0;
+            peer.last_error = None;
+        }
+        Err(e) => {
+            peer.healthy = false;
+            peer.latency_ms = None;
+            peer.consecutive_failures += 1;
+            peer.last_error = Some(e);
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// record_sync: called by the sync engine after every round with a peer
+// -----------------------------------------------------------------------------------------------------------
+pub fn record_sync(peer_id: &str, result: &Result<usize, String>) {
+    with_peer(peer_id, |peer| {
+        peer.last_sync = Some(unix_now());
+        match result {
+            Ok(scrolls) => {
+                peer.last_sync_scrolls = *scrolls;
+                peer.last_sync_error = None;
+            }
+            Err(e) => peer.last_sync_error = Some(e.clone()),
+        }
+    });
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// record_conflicts: counts conflicting edits found while pulling from a peer (details in <phext>.conflicts)
+// -----------------------------------------------------------------------------------------------------------
+pub fn record_conflicts(peer_id: &str, conflicts: usize) {
+    if conflicts > 0 {
+        with_peer(peer_id, |peer| peer.conflicts += conflicts as u64);
+    }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// status: a snapshot for /api/v2/mesh/status (enabled = false outside mesh mode)
+// -----------------------------------------------------------------------------------------------------------
+pub fn status() -> MeshStatus {
+    match STATUS.get() {
+        Some(status) => status.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone(),
+        None => MeshStatus::default(),
+    }
+}
+
+fn probe(host: &str, port: u16, auth_key: &str) -> Result<Duration, String> {
+    let started = Instant::now();
+    let authorization = format!("Bearer {}", auth_key);
+    let (code, _) = http::request(host, port, "GET", "/api/v2/version",
+        &[("Authorization", authorization.as_str())], b"", Duration::from_secs(PROBE_TIMEOUT_SECS))
+        .map_err(|e| e.to_string())?;
+    if code != 200 {
+        return Err(format!("HTTP {}", code));
+    }
+    Ok(started.elapsed())
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// start: registers the mesh's peers and starts probing them (once per process)
+// -----------------------------------------------------------------------------------------------------------
+pub fn start(config: &MeshConfig) {
+    let peers = if config.outbound.enabled { config.outbound.peers.clone() } else { Vec::new() };
+    let initial = MeshStatus {
+        enabled: true,
+        node_id: config.node.id.clone(),
+        node_name: config.node.name.clone(),
+        health_check_interval_seconds: config.mesh.health_check_interval_seconds,
+        peers: peers.iter().map(|p| PeerHealth {
+            id: p.id.clone(),
+            name: p.name.clone(),
+            host: p.host.clone(),
+            port: p.port,
+            priority: p.priority,
+            ..Default::default()
+        }).collect(),
+    };
+    if STATUS.set(Mutex::new(initial)).is_err() || peers.is_empty() {
+        return;
+    }
+
+    let interval = Duration::from_secs(config.mesh.health_check_interval_seconds.max(1));
+    std::thread::spawn(move || {
+        loop {
+            std::thread::scope(|scope| {
+                for peer in &peers {
+                    scope.spawn(move || {
+                        let result = probe(&peer.host, peer.port, &peer.auth_key);
+                        if let Err(ref e) = result {
+                            eprintln!("Warning: mesh peer {} failed its health check: {}", peer.id, e);
+                        }
+                        let now = unix_now();
+                        with_peer(&peer.id, |health| record_probe(health, result, now));
+                    });
+                }
+            });
+            std::thread::sleep(interval);
+        }
+    });
+}
+
+#[cfg(test)]
+mod health_tests {
+    use super::*;
+
+    #[test]
+    fn test_record_probe_tracks_failures() {
+        let mut peer = PeerHealth::default();
+        record_probe(&mut peer, Err("Connection refused".to_string()), 100);
+        record_probe(&mut peer, Err("Connection refused".to_string()), 160);
+        assert!(!peer.healthy);
+        assert_eq!(peer.consecutive_failures, 2);
+        assert_eq!(peer.last_success, None);
+        assert_eq!(peer.last_check, Some(160));
+
+        record_probe(&mut peer, Ok(Duration::from_millis(12)), 220);
+        assert!(peer.healthy);
+        assert_eq!(peer.consecutive_failures, 0);
+        assert_eq!(peer.latency_ms, Some(12));
+        assert_eq!(peer.last_success, Some(220));
+        assert_eq!(peer.last_error, None);
+    }
+}
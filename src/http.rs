@@ -0,0 +1,302 @@
+//------------------------------------------------------------------------------------------------------------
+// file: http.rs
+// purpose: HTTP/1.1 message framing shared by the REST servers and the router
+//
+// Connections are persistent: a client may send any number of requests (pipelined or not) on one socket.
+// MessageReader keeps whatever it reads past the end of one message for the next, so pipelined requests
+// are never lost. A connection ends when:
+//   - the client asks for it (Connection: close, or HTTP/1.0 without Connection: keep-alive)
+//   - MAX_REQUESTS_PER_CONNECTION requests have been served (the last response says Connection: close)
+//   - the socket sits idle past its read timeout (READ_TIMEOUT_SECS in the servers)
+//------------------------------------------------------------------------------------------------------------
+
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+pub const MAX_REQUESTS_PER_CONNECTION: usize = 1000;
+pub const MAX_RESPONSE_SIZE: usize = 256 * 1024 * 1024; // bodies we accept from mesh peers and router backends
+const IDLE_POLL_MS: u64 = 250; // how often an idle persistent connection re-checks whether it should yield
+
+pub struct HttpRequest {
+    pub header: String,
+    pub content: Vec<u8>,
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// request_line: the method, target and version of a request, or None unless its first line is exactly
+//   `METHOD SP target SP HTTP/1.x` - a looser parse lets a proxy and a backend disagree about the query
+// -----------------------------------------------------------------------------------------------------------
+pub fn request_line(header: &str) -> Option<(&str, &str, &str)> {
+    let mut parts = header.split("\r\n").next()?.split(' ');
+    let (method, target, version) = (parts.next()?, parts.next()?, parts.next()?);
+    let valid = parts.next().is_none()
+        && !method.is_empty() && method.bytes().all(|b| b.is_ascii_uppercase())
+        && target.starts_with('/') && target.bytes().all(|b| b.is_ascii_graphic())
+        && (version == "HTTP/1.1" || version == "HTTP/1.0");
+    if valid { Some((method, target, version)) } else { None }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// MessageReader: reads Content-Length framed messages (requests, or our own backends' responses)
+// -----------------------------------------------------------------------------------------------------------
+pub struct MessageReader {
+    pending: Vec<u8>,
+    max_header: usize,
+    max_body: usize,
+}
+
+impl MessageReader {
+    pub fn new(max_header: usize, max_body: usize) -> MessageReader {
+        MessageReader { pending: Vec::new(), max_header, max_body }
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // idle_timeout: true when `e` is a read timeout while waiting for a new message (a normal keep-alive end)
+    // -------------------------------------------------------------------------------------------------------
+    pub fn idle_timeout(&self, e: &std::io::Error) -> bool {
+        self.pending.is_empty() &&
+            (e.kind() == std::io::ErrorKind::WouldBlock || e.kind() == std::io::ErrorKind::TimedOut)
+    }
+
+    pub fn has_buffered(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    fn fill<S: Read>(&mut self, stream: &mut S) -> std::io::Result<usize> {
+        let mut temp = [0u8; 8192];
+        let n = stream.read(&mut temp)?;
+        self.pending.extend_from_slice(&temp[..n]);
+        Ok(n)
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // next_message: the next complete message, or None when the peer closed cleanly between messages
+    //   errors mean the connection is unusable: a read timeout (see idle_timeout), a torn message, or a
+    //   limit violation (InvalidData - the caller should answer 413)
+    // -------------------------------------------------------------------------------------------------------
+    pub fn next_message<S: Read>(&mut self, stream: &mut S) -> std::io::Result<Option<HttpRequest>> {
+        let header_end = loop {
+            if let Some(pos) = self.pending.windows(4).position(|w| w == b"\r\n\r\n") {
+                break pos + 4;
+            }
+            if self.pending.len() > self.max_header {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "header too large"));
+            }
+            let between_messages = self.pending.is_empty();
+            match self.fill(stream) {
+                Ok(0) if between_messages => return Ok(None),
+                Ok(0) => return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "connection closed mid-header")),
+                Ok(_) => {}
+                Err(e) => return Err(e),
+            }
+        };
+
+        let header = String::from_utf8_lossy(&self.pending[..header_end]).to_string();
+        let content_length = content_length(&header);
+        let message_end = match header_end.checked_add(content_length) {
+            Some(end) if content_length <= self.max_body => end,
+            _ => return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("body too large: {} bytes (max {})", content_length, self.max_body),
+            )),
+        };
+
+        while self.pending.len() < message_end {
+            if self.fill(stream)? == 0 {
+                return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "connection closed mid-body"));
+            }
+        }
+
+        let content = self.pending[header_end..message_end].to_vec();
+        self.pending.drain(..message_end);
+        Ok(Some(HttpRequest { header, content }))
+    }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// wait_for_request: blocks until an idle persistent connection has data, returning false if the connection
+// should close instead - the peer hung up, `idle` elapsed, or `yield_now` says someone else needs the worker
+// -----------------------------------------------------------------------------------------------------------
+pub fn wait_for_request<F: Fn() -> bool>(stream: &crate::socket::Stream, idle: Duration, yield_now: F) -> bool {
+    let restore = stream.read_timeout().unwrap_or(None);
+    let _ = stream.set_read_timeout(Some(Duration::from_millis(IDLE_POLL_MS)));
+    let started = Instant::now();
+    let mut probe = [0u8; 1];
+    let ready = loop {
+        match stream.peek(&mut probe) {
+            Ok(0) => break false,
+            Ok(_) => break true,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock || e.kind() == std::io::ErrorKind::TimedOut => {
+                if started.elapsed() >= idle || yield_now() {
+                    break false;
+                }
+            }
+            Err(_) => break false,
+        }
+    };
+    let _ = stream.set_read_timeout(restore);
+    ready
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// request: a one-shot client exchange (Connection: close), used to talk to mesh peers
+//   returns the response status code and body; `timeout` bounds the connect and every read/write
+// -----------------------------------------------------------------------------------------------------------
+pub fn request(
+    host: &str,
+    port: u16,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+    timeout: Duration,
+) -> std::io::Result<(u16, Vec<u8>)> {
+    let address = (host, port).to_socket_addrs()?.next()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, format!("cannot resolve {}", host)))?;
+    let mut stream = TcpStream::connect_timeout(&address, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let mut message = format!("{} {} HTTP/1.1\r\nHost: {}:{}\r\nConnection: close\r\nContent-Length: {}\r\n", method, path, host, port, body.len());
+    for (name, value) in headers {
+        message.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    message.push_str("\r\n");
+    let mut bytes = message.into_bytes();
+    bytes.extend_from_slice(body);
+    stream.write_all(&bytes)?;
+
+    let response = MessageReader::new(65_536, MAX_RESPONSE_SIZE).next_message(&mut stream)?
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "no response"))?;
+    let status = response.header.split_whitespace().nth(1).and_then(|code| code.parse().ok())
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "malformed status line"))?;
+    Ok((status, response.content))
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// header_value: case-insensitive lookup of a single header (the request/status line is skipped)
+// -----------------------------------------------------------------------------------------------------------
+pub fn header_value<'a>(header: &'a str, name: &str) -> Option<&'a str> {
+    header.split("\r\n").skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim())
+}
+
+pub fn content_length(header: &str) -> usize {
+    header_value(header, "content-length").and_then(|v| v.parse().ok()).unwrap_or(0)
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// keep_alive: whether the peer wants the connection kept open after this message
+//   HTTP/1.1 defaults to persistent; HTTP/1.0 must opt in
+// -----------------------------------------------------------------------------------------------------------
+pub fn keep_alive(header: &str) -> bool {
+    let first_line = header.split("\r\n").next().unwrap_or_default();
+    let http10 = first_line.contains("HTTP/1.0");
+    let tokens: Vec<String> = header_value(header, "connection")
+        .map(|v| v.split(',').map(|t| t.trim().to_ascii_lowercase()).collect())
+        .unwrap_or_default();
+    if tokens.iter().any(|t| t == "close") {
+        return false;
+    }
+    !http10 || tokens.iter().any(|t| t == "keep-alive")
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// connection_headers: the Connection (and Keep-Alive) response headers, each ending in \r\n
+// -----------------------------------------------------------------------------------------------------------
+pub fn connection_headers(keep_alive: bool, idle_secs: u64) -> String {
+    if keep_alive {
+        format!("Connection: keep-alive\r\nKeep-Alive: timeout={}, max={}\r\n", idle_secs, MAX_REQUESTS_PER_CONNECTION)
+    } else {
+        "Connection: close\r\n".to_string()
+    }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// preflight_response: the CORS preflight answer shared by every listener
+// -----------------------------------------------------------------------------------------------------------
+pub fn preflight_response(keep_alive: bool, idle_secs: u64) -> String {
+    format!(
+        "HTTP/1.1 204 No Content\r\n\
+         Access-Control-Allow-Origin: *\r\n\
+         Access-Control-Allow-Methods: GET, POST, OPTIONS\r\n\
+         Access-Control-Allow-Headers: Authorization, Content-Type\r\n\
+         Access-Control-Max-Age: 86400\r\n\
+         {}\r\n",
+        connection_headers(keep_alive, idle_secs)
+    )
+}
+
+#[cfg(test)]
+mod http_tests {
+    use super::*;
+
+    #[test]
+    fn test_pipelined_requests() {
+        let wire = b"GET /a HTTP/1.1\r\nHost: x\r\n\r\nPOST /b HTTP/1.1\r\nContent-Length: 5\r\n\r\nhelloGET /c HTTP/1.1\r\n\r\n";
+        let mut stream: &[u8] = wire;
+        let mut reader = MessageReader::new(1024, 1024);
+        let first = reader.next_message(&mut stream).unwrap().unwrap();
+        assert!(first.header.starts_with("GET /a"));
+        let second = reader.next_message(&mut stream).unwrap().unwrap();
+        assert_eq!(second.content, b"hello");
+        let third = reader.next_message(&mut stream).unwrap().unwrap();
+        assert!(third.header.starts_with("GET /c"));
+        assert!(reader.next_message(&mut stream).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_limits_and_torn_messages() {
+        let mut stream: &[u8] = b"POST / HTTP/1.1\r\nContent-Length: 99\r\n\r\n";
+        let err = MessageReader::new(1024, 10).next_message(&mut stream).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        // a length that would overflow is refused even without a limit
+        let mut stream: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 18446744073709551615\r\n\r\n";
+        let err = MessageReader::new(1024, usize::MAX).next_message(&mut stream).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        let mut stream: &[u8] = b"POST / HTTP/1.1\r\nContent-Length: 9\r\n\r\nshort";
+        assert!(MessageReader::new(1024, 1024).next_message(&mut stream).is_err());
+
+        let mut stream: &[u8] = b"GET / HTTP/1.1\r\nHost";
+        assert!(MessageReader::new(1024, 1024).next_message(&mut stream).is_err());
+    }
+
+    #[test]
+    fn test_peer_responses_are_capped() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = MessageReader::new(1024, 1024).next_message(&mut stream);
+            let header = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", MAX_RESPONSE_SIZE + 1);
+            let _ = stream.write_all(header.as_bytes());
+        });
+        let err = request("127.0.0.1", port, "GET", "/", &[], b"", Duration::from_secs(5)).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_keep_alive_negotiation() {
+        assert!(keep_alive("GET / HTTP/1.1\r\nHost: x\r\n\r\n"));
+        assert!(!keep_alive("GET / HTTP/1.1\r\nConnection: close\r\n\r\n"));
+        assert!(!keep_alive("GET / HTTP/1.0\r\n\r\n"));
+        assert!(keep_alive("GET / HTTP/1.0\r\nconnection: Keep-Alive\r\n\r\n"));
+        assert_eq!(content_length("POST / HTTP/1.1\r\ncontent-length: 12\r\n\r\n"), 12);
+    }
+
+    #[test]
+    fn test_request_line_is_strict() {
+        assert_eq!(request_line("GET /api/v2/get?p=a HTTP/1.1\r\nHost: x\r\n\r\n"), Some(("GET", "/api/v2/get?p=a", "HTTP/1.1")));
+        assert_eq!(request_line("POST / HTTP/1.0\r\n\r\n"), Some(("POST", "/", "HTTP/1.0")));
+        for line in ["GET /api/v2/get?p=manual HTTP/1.1&p=secret HTTP/1.1", "GET /a  HTTP/1.1", "GET /a HTTP/2.0",
+                     "GET /a", "get /a HTTP/1.1", "GET a HTTP/1.1", "GET /a\tb HTTP/1.1", " GET /a HTTP/1.1", ""] {
+            assert_eq!(request_line(&format!("{}\r\n\r\n", line)), None, "{}", line);
+        }
+    }
+}
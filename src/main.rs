@@ -3,7 +3,7 @@
 // purpose: provides primary program logic for sq - determining daemon mode vs listening mode
 //
 // v0.5.3 - Memory pressure fixes
-//   - Fixed: unbounded thread spawning (now capped at MAX_CONCURRENT_CONNECTIONS)
+//   - Fixed: unbounded thread spawning (connections now run on a bounded worker pool, see pool.rs)
 //   - Fixed: no read/write timeout on TCP streams (slowloris OOM vector)
 //   - Fixed: Content-Length trusted unconditionally (now capped at MAX_BODY_SIZE)
 //   - Fixed: fetch_source double-allocation on truncation (now uses in-place truncate)
@@ -20,20 +20,18 @@
 //   - Removed: debug printlns (WTF, Algo)
 //------------------------------------------------------------------------------------------------------------
 
+
 use libphext::phext;
 use raw_sync::{events::*, Timeout};
 use shared_memory::*;
 use std::env;
 use std::fs;
 use std::path::Path;
-use std::net::TcpListener;
-use std::net::TcpStream;
-use std::io::Read;
 use std::io::Write;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex, RwLock};
-use std::sync::atomic::{AtomicUsize, AtomicBool, Ordering};
 use std::sync::mpsc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
 
 mod sq;
@@ -44,6 +42,20 @@ mod config;
 mod cache;
 mod triage;
 mod api;
+mod wal;
+mod persist;
+mod store;
+mod search;
+mod http;
+mod pool;
+mod resident;
+mod replication;
+mod health;
+mod versions;
+mod conflict;
+mod merkle;
+mod supervisor;
+mod socket;
 
 const SHARED_SEGMENT_SIZE: usize = 1024*1024*1024; // 1 GB limit
 const MAX_BUFFER_SIZE: usize = SHARED_SEGMENT_SIZE/2;
@@ -56,25 +68,233 @@ const WORK_NAME: &str = ".sq/work";
 // -----------------------------------------------------------------------------------------------------------
 // Memory-pressure guardrails
 // -----------------------------------------------------------------------------------------------------------
-const MAX_CONCURRENT_CONNECTIONS: usize = 512;     // ~4 GB stack ceiling at 8 MB/thread
+const WORKER_THREADS: usize = 64;                  // connections served at once (~512 MB stack at 8 MB/thread)
+const WORKER_QUEUE_DEPTH: usize = 256;             // accepted connections waiting for a worker before 503
 const READ_TIMEOUT_SECS: u64 = 32;                 // kill idle/slowloris connections
 const WRITE_TIMEOUT_SECS: u64 = 32;
 pub const MAX_BODY_SIZE: usize = 64 * 1024 * 1024; // 64 MB per request body
+const RESIDENT_BUDGET_MB: usize = 256;             // phexts kept in memory by sq host (see resident.rs)
+
+// -----------------------------------------------------------------------------------------------------------
+// Write-ahead log tuning (see wal.rs)
+// -----------------------------------------------------------------------------------------------------------
+const WAL_TICK_MS: u64 = 1000;                      // batch fsync cadence
+const WAL_COMPACT_INTERVAL_SECS: u64 = 30;          // fold a non-empty log into the phext this often
+const WAL_COMPACT_BYTES: u64 = 16 * 1024 * 1024;    // ...or sooner once the log grows past this
+
+// -----------------------------------------------------------------------------------------------------------
+// The single-tenant host's settings and resident phexts, shared by every connection
+// -----------------------------------------------------------------------------------------------------------
+struct Host {
+    residents: Arc<resident::ResidentSet>,
+    auth_key: Option<String>,
+    data_dir: Option<String>,
+    tenant_map: Option<Arc<HashMap<String, config::TenantConfig>>>,
+    durability: wal::Durability,
+}
 
-static ACTIVE_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+// Per-tenant in-memory state for the multi-tenant server: phext_path → ServerState
+type TenantStates = Mutex<HashMap<String, Arc<RwLock<ServerState>>>>;
 
 // -----------------------------------------------------------------------------------------------------------
 // Shared state for the HTTP listener, protected by a mutex for thread safety
 // -----------------------------------------------------------------------------------------------------------
 struct ServerState {
     loaded_phext: String,
-    loaded_map: HashMap<phext::Coordinate, String>,
+    loaded_map: store::ScrollStore,
+    wal: Option<wal::WriteAheadLog>,
+    versions: Option<versions::VersionTable>, // mesh mode only
+    compaction: Arc<Mutex<()>>, // held by compact_state from rotating the log until the snapshot is written
+}
+
+impl ServerState {
+    fn new() -> ServerState {
+        ServerState {
+            loaded_phext: String::new(),
+            loaded_map: Default::default(),
+            wal: None,
+            versions: None,
+            compaction: Arc::new(Mutex::new(())),
+        }
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // load: (re-)reads a phext from disk, replays its write-ahead log, and opens the log for appending
+    // -------------------------------------------------------------------------------------------------------
+    fn load(&mut self, phext: &str, durability: wal::Durability) {
+        if let Some(ref mut log) = self.wal {
+            if let Err(e) = log.sync() {
+                eprintln!("Warning: Failed to sync write-ahead log for {}: {}", self.loaded_phext, e);
+            }
+        }
+        self.loaded_map = fetch_source(phext.to_string());
+        self.loaded_phext = phext.to_string();
+        self.wal = match wal::WriteAheadLog::open(phext, durability) {
+            Ok(log) => Some(log),
+            Err(e) => {
+                eprintln!("Warning: Failed to open write-ahead log for {} (falling back to full rewrites): {}", phext, e);
+                None
+            }
+        };
+        self.versions = versions::node().map(|_| versions::VersionTable::open(phext));
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // persist: records the current contents of `coordinate` after a local mutation
+    //   falls back to rewriting the whole phext when no log could be opened
+    // -------------------------------------------------------------------------------------------------------
+    fn persist(&mut self, coordinate: phext::Coordinate) -> std::io::Result<()> {
+        if let Some(ref mut versions) = self.versions {
+            versions.stamp(coordinate, self.loaded_map.contains_key(&coordinate));
+        }
+        self.log(coordinate)
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // persist_pulled: records a scroll pulled from a mesh peer, keeping the peer's version
+    // -------------------------------------------------------------------------------------------------------
+    fn persist_pulled(&mut self, coordinate: phext::Coordinate, version: versions::ScrollVersion) -> std::io::Result<()> {
+        if let Some(ref mut versions) = self.versions {
+            versions.adopt(coordinate, version);
+        }
+        self.log(coordinate)
+    }
+
+    fn log(&mut self, coordinate: phext::Coordinate) -> std::io::Result<()> {
+        match self.wal {
+            Some(ref mut log) => log.append(coordinate, self.loaded_map.get(&coordinate).map(|s| s.as_str())),
+            None => persist::write_atomic(&self.loaded_phext, self.loaded_map.implode()),
+        }
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // version_report: the versions of the newline-separated `coordinates` (/api/v2/mesh/versions)
+    // -------------------------------------------------------------------------------------------------------
+    fn version_report(&self, coordinates: &str) -> String {
+        let coordinates: Vec<phext::Coordinate> = coordinates.lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+            .map(phext::to_coordinate)
+            .collect();
+        match self.versions {
+            Some(ref versions) => versions.report(&coordinates),
+            None => "{}".to_string(),
+        }
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // checksums: per-scroll checksums, taken before a bulk mutation so persist_changes can find what moved
+    // -------------------------------------------------------------------------------------------------------
+    fn checksums(&self) -> HashMap<phext::Coordinate, String> {
+        self.loaded_map.iter()
+            .map(|(c, _)| (*c, self.loaded_map.scroll_checksum(c).unwrap_or_default().to_string()))
+            .collect()
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // persist_changes: logs every scroll that was added, changed or removed since `before` was taken
+    // -------------------------------------------------------------------------------------------------------
+    fn persist_changes(&mut self, before: HashMap<phext::Coordinate, String>) -> std::io::Result<()> {
+        let mut changed: Vec<phext::Coordinate> = before.keys()
+            .filter(|c| !self.loaded_map.contains_key(c))
+            .copied()
+            .collect();
+        for (c, _) in self.loaded_map.iter() {
+            if before.get(c).map(|sum| sum.as_str()) != self.loaded_map.scroll_checksum(c) {
+                changed.push(*c);
+            }
+        }
+        if self.wal.is_none() {
+            if let Some(ref mut versions) = self.versions {
+                for c in &changed {
+                    versions.stamp(*c, self.loaded_map.contains_key(c));
+                }
+            }
+            return persist::write_atomic(&self.loaded_phext, self.loaded_map.implode());
+        }
+        for c in changed {
+            self.persist(c)?;
+        }
+        Ok(())
+    }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// Folds a phext's write-ahead log back into the main file
+//   the log is rotated under the lock; the (potentially large) file write happens after it is released
+//   one compaction per phext at a time (the compactor and an eviction may both try): an older snapshot must
+//   never be renamed over a newer one once the newer one's log is gone
+// -----------------------------------------------------------------------------------------------------------
+fn compact_state(state: &Arc<RwLock<ServerState>>, force: bool) {
+    let compaction = Arc::clone(&state.read().unwrap_or_else(|poisoned| poisoned.into_inner()).compaction);
+    let _compacting = compaction.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let (phext, buffer) = {
+        let mut state = state.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let log = match state.wal {
+            Some(ref mut log) => log,
+            None => return,
+        };
+        if log.is_empty() || (!force && log.len() < WAL_COMPACT_BYTES) {
+            return;
+        }
+        if let Err(e) = log.rotate() {
+            eprintln!("Warning: Failed to rotate write-ahead log for {}: {}", state.loaded_phext, e);
+            return;
+        }
+        let phext = state.loaded_phext.clone();
+        if let Some(ref mut versions) = state.versions {
+            if let Err(e) = versions.compact() {
+                eprintln!("Warning: Failed to compact version table for {}: {}", phext, e);
+            }
+        }
+        (state.loaded_phext.clone(), state.loaded_map.implode())
+    };
+
+    match persist::write_atomic(&phext, &buffer) {
+        Ok(_) => {
+            if let Err(e) = wal::finish_compaction(&phext) {
+                eprintln!("Warning: Failed to remove compacted log for {}: {}", phext, e);
+            }
+        }
+        Err(e) => eprintln!("Warning: Compaction of {} failed (log retained): {}", phext, e),
+    }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// Background write-ahead log maintenance: batch fsyncs and periodic compaction
+//   `states` returns every currently-resident phext
+// -----------------------------------------------------------------------------------------------------------
+fn spawn_wal_compactor<F>(states: F)
+where
+    F: Fn() -> Vec<Arc<RwLock<ServerState>>> + Send + 'static,
+{
+    std::thread::spawn(move || {
+        let ticks_per_compaction = (WAL_COMPACT_INTERVAL_SECS * 1000 / WAL_TICK_MS).max(1);
+        let mut tick: u64 = 0;
+        loop {
+            std::thread::sleep(Duration::from_millis(WAL_TICK_MS));
+            tick += 1;
+            let force = tick.is_multiple_of(ticks_per_compaction);
+            for state in states() {
+                {
+                    let mut state = state.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+                    let phext = state.loaded_phext.clone();
+                    if let Some(ref mut log) = state.wal {
+                        if let Err(e) = log.sync() {
+                            eprintln!("Warning: Failed to sync write-ahead log for {}: {}", phext, e);
+                        }
+                    }
+                }
+                compact_state(&state, force);
+            }
+        }
+    });
 }
 
 // -----------------------------------------------------------------------------------------------------------
 // Extracts a named header value from an HTTP request header block
 // -----------------------------------------------------------------------------------------------------------
-fn extract_header<'a>(header: &'a str, name: &str) -> Option<String> {
+fn extract_header(header: &str, name: &str) -> Option<String> {
     let lower_name = name.to_lowercase();
     for line in header.lines() {
         let lower_line = line.to_lowercase();
@@ -87,6 +307,19 @@ fn extract_header<'a>(header: &'a str, name: &str) -> Option<String> {
     None
 }
 
+// -----------------------------------------------------------------------------------------------------------
+// Extracts the API key from the Authorization header (with or without the "Bearer " prefix)
+// -----------------------------------------------------------------------------------------------------------
+fn bearer_token(header: &str) -> Option<String> {
+    let provided = extract_header(header, "authorization")?;
+    let provided = provided.trim();
+    if provided.to_lowercase().starts_with("bearer ") {
+        Some(provided[7..].trim().to_string())
+    } else {
+        Some(provided.to_string())
+    }
+}
+
 // -----------------------------------------------------------------------------------------------------------
 // Validates an API key against the expected key for this tenant instance
 // Returns true if auth is disabled (no key configured) or if key matches
@@ -94,27 +327,14 @@ fn extract_header<'a>(header: &'a str, name: &str) -> Option<String> {
 fn validate_auth(header: &str, expected_key: &Option<String>) -> bool {
     match expected_key {
         None => true,
-        Some(key) => {
-            match extract_header(header, "authorization") {
-                Some(provided) => {
-                    let provided = provided.trim();
-                    let token = if provided.to_lowercase().starts_with("bearer ") {
-                        provided[7..].trim()
-                    } else {
-                        provided
-                    };
-                    token == key
-                }
-                None => false,
-            }
-        }
+        Some(key) => bearer_token(header).as_ref() == Some(key),
     }
 }
 
 // -----------------------------------------------------------------------------------------------------------
-// Sends an HTTP response with status code, CORS headers, and body
+// Sends an HTTP response with status code, CORS headers, connection headers, and body
 // -----------------------------------------------------------------------------------------------------------
-fn send_response(stream: &mut TcpStream, status: u16, body: &str) {
+fn send_response(stream: &mut socket::Stream, status: u16, body: &str, keep_alive: bool) {
     let status_text = match status {
         200 => "OK",
         204 => "No Content",
@@ -128,8 +348,8 @@ fn send_response(stream: &mut TcpStream, status: u16, body: &str) {
         _ => "OK",
     };
     let response = format!(
-        "HTTP/1.1 {} {}\r\nAccess-Control-Allow-Origin: *\r\nContent-Length: {}\r\n\r\n{}",
-        status, status_text, body.len(), body
+        "HTTP/1.1 {} {}\r\nAccess-Control-Allow-Origin: *\r\n{}Content-Length: {}\r\n\r\n{}",
+        status, status_text, http::connection_headers(keep_alive, READ_TIMEOUT_SECS), body.len(), body
     );
     let _ = stream.write_all(response.as_bytes());
 }
@@ -160,9 +380,9 @@ pub enum HashAlgorithm {
 // -----------------------------------------------------------------------------------------------------------
 // Loads + explodes a source phext from disk into memory
 // -----------------------------------------------------------------------------------------------------------
-fn fetch_source(filename: String) -> HashMap::<phext::Coordinate, String> {
+fn fetch_source(filename: String) -> store::ScrollStore {
     let exists = std::path::Path::new(&filename).exists();
-    if exists == false {
+    if !exists {
         // Ensure parent directory exists before creating the file
         if let Some(parent) = std::path::Path::new(&filename).parent() {
             if let Err(e) = std::fs::create_dir_all(parent) {
@@ -185,7 +405,12 @@ fn fetch_source(filename: String) -> HashMap::<phext::Coordinate, String> {
         // in-place truncation: avoids allocating a second 512 MB string
         buffer.truncate(MAX_BUFFER_SIZE);
     }
-    return phext::explode(&buffer);
+    let mut map = store::ScrollStore::from(phext::explode(&buffer));
+    let replayed = wal::replay(&filename, &mut map);
+    if replayed > 0 {
+        println!("Replayed {} write-ahead log records for {}", replayed, filename);
+    }
+    map
 }
 
 // -----------------------------------------------------------------------------------------------------------
@@ -211,7 +436,7 @@ fn create_work_segment() -> Result<Shmem, ShmemError> {
 }
 
 fn is_basic_or_share(command: String) -> bool {
-    return command == "share" || command == "basic";
+    command == "share" || command == "basic"
 }
 
 // -----------------------------------------------------------------------------------------------------------
@@ -229,12 +454,112 @@ fn is_mutation(command: &str) -> bool {
     command == "push" || command == "slurp"
 }
 
+// -----------------------------------------------------------------------------------------------------------
+// Returns true if this REST command may rewrite any number of scrolls
+// -----------------------------------------------------------------------------------------------------------
+fn is_bulk_mutation(command: &str) -> bool {
+    command == "json-import"
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// Returns true if this REST command can share the loaded phext with other readers (see shared_query)
+// -----------------------------------------------------------------------------------------------------------
+fn is_read_only(command: &str) -> bool {
+    !is_mutation(command) && !is_bulk_mutation(command)
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// Runs a read-only command against the resident phext
+//   a mesh peer's `scope` limits what it sees: delta and merkle requests already carry it (see delta_query),
+//   versions are only reported for coordinates in scope, and every other read runs on the store's cached
+//   view of the scrolls in scope (ScrollStore::view)
+// -----------------------------------------------------------------------------------------------------------
+fn read_query(state: &ServerState, request: &sq::Request, scope: Option<&sq::CoordinateFilter>) -> String {
+    if request.command == "versions" {
+        return match scope {
+            Some(scope) => {
+                let in_scope: Vec<&str> = request.update.lines()
+                    .filter(|line| scope.matches(&phext::to_coordinate(line.trim())))
+                    .collect();
+                state.version_report(&in_scope.join("\n"))
+            }
+            None => state.version_report(&request.update),
+        };
+    }
+    let scoped = match scope {
+        Some(scope) if request.command != "delta" && request.command != "merkle" => Some(state.loaded_map.view(scope)),
+        _ => None,
+    };
+    let mut output = String::new();
+    let _ = sq::query(request, &mut output, scoped.as_deref().unwrap_or(&state.loaded_map));
+    output
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// Runs a read-only command under the shared read lock, so reads don't wait on each other
+//   returns None when the request's source isn't the resident phext - the caller loads it under the write
+//   lock instead
+// -----------------------------------------------------------------------------------------------------------
+fn shared_query(state: &RwLock<ServerState>, request: &sq::Request, scope: Option<&sq::CoordinateFilter>) -> Option<String> {
+    let state = state.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if state.loaded_phext != request.source {
+        return None;
+    }
+    Some(read_query(&state, request, scope))
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// bind_listeners: TCP listeners on each `--bind <addr>` (all interfaces on `port` if there are none)
+//   and/or the unix socket named by `--socket <path>`
+// -----------------------------------------------------------------------------------------------------------
+fn bind_listeners(port: Option<u16>, args: &[String]) -> Result<Vec<socket::Listener>, Box<dyn std::error::Error>> {
+    let mut addresses = Vec::new();
+    for (idx, _) in args.iter().enumerate().filter(|(_, s)| *s == "--bind") {
+        match args.get(idx + 1).filter(|bind| !bind.starts_with("--")) {
+            Some(bind) => addresses.push(socket::listen_address(bind, port)?),
+            None => {
+                eprintln!("Error: --bind requires an address argument");
+                std::process::exit(1);
+            }
+        }
+    }
+    if let (true, Some(port)) = (addresses.is_empty(), port) {
+        addresses.push(std::net::SocketAddr::from(([0, 0, 0, 0], port)));
+    }
+    let mut listeners = Vec::new();
+    for address in addresses {
+        listeners.push(socket::Listener::bind_tcp(address).map_err(|e| format!("cannot listen on {}: {}", address, e))?);
+    }
+    if let Some(idx) = args.iter().position(|s| s == "--socket") {
+        match args.get(idx + 1).filter(|path| !path.starts_with("--")) {
+            Some(path) => listeners.push(socket::Listener::bind_unix(path)
+                .map_err(|e| format!("cannot listen on {}: {}", path, e))?),
+            None => {
+                eprintln!("Error: --socket requires a path argument");
+                std::process::exit(1);
+            }
+        }
+    }
+    Ok(listeners)
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// default_listen_port: the [listen-port] after the config path, else `default` - unless only a socket was asked for
+// -----------------------------------------------------------------------------------------------------------
+fn default_listen_port(args: &[String], default: u16) -> Option<u16> {
+    match args.get(3).and_then(|s| s.parse().ok()) {
+        Some(port) => Some(port),
+        None if args.iter().any(|s| s == "--socket") && !args.iter().any(|s| s == "--bind") => None,
+        None => Some(default),
+    }
+}
+
 // -----------------------------------------------------------------------------------------------------------
 // sq program loop
 // -----------------------------------------------------------------------------------------------------------
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let sq_exists = std::path::Path::new(".sq").exists();
-    if sq_exists == false {
+    if !sq_exists {
         let _ = std::fs::create_dir(".sq");
     }
 
@@ -243,57 +568,97 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let exists = std::path::Path::new(&phext_or_port).exists();
     let is_port_number = phext_or_port.parse::<u16>().is_ok();
 
+    // --backup: keep <file>.bak with the previous generation whenever a server rewrites a phext
+    if (command == "host" || is_basic_or_share(command.clone())) && env::args().any(|a| a == "--backup") {
+        persist::set_keep_backup(true);
+    }
+
     // -----------------------------------------------------------------------
     // Local commands: handle without IPC (fixes Windows "Failed to open event" crash)
     // -----------------------------------------------------------------------
     if is_local_command(&command) {
         let mut scroll = String::new();
-        let mut empty_map: HashMap<phext::Coordinate, String> = Default::default();
-        let _ = sq::process(
-            0, String::new(), &mut scroll, command.clone(),
-            &mut empty_map, phext::to_coordinate("1.1.1/1.1.1/1.1.1"),
-            String::new(), String::new(), HashAlgorithm::Xor, 100,
-        );
+        let mut empty_map = store::ScrollStore::new();
+        let request = sq::Request {
+            connection_id: 0, source: String::new(), command: command.clone(),
+            coordinate: phext::to_coordinate("1.1.1/1.1.1/1.1.1"),
+            update: String::new(), filename: String::new(), algorithm: HashAlgorithm::Xor, limit: 100,
+        };
+        let _ = sq::process(&request, &mut scroll, &mut empty_map);
         println!("{}", scroll);
         return Ok(());
     }
 
-    // API proxy command: sq api <config.json> [listen-port]
+    // API proxy command: sq api <config.json> [listen-port] [--bind <addr>]...
     if command == "api" {
         let config_path = env::args().nth(2).unwrap_or("api-config.json".to_string());
-        let listen_port: u16 = env::args().nth(3)
-            .and_then(|s| s.parse().ok())
-            .unwrap_or(8100);
+        let args: Vec<String> = env::args().collect();
+        let listen_port = default_listen_port(&args, 8100);
 
-        return api::run_api(&config_path, listen_port);
+        return api::run_api(&config_path, bind_listeners(listen_port, &args)?);
     }
 
-    // Route command: sq route <config.json> <listen-port>
+    // Mesh command: sq mesh <init|add-peer|remove-peer|show|status> ... [--config mesh.json]
+    if command == "mesh" {
+        let args: Vec<String> = env::args().skip(2).collect();
+        return mesh::run_mesh_command(&args);
+    }
+
+    // Route command: sq route <config.json> [listen-port] [--bind <addr>]... [--socket <path>] [--watch <seconds>]
     if command == "route" {
         let config_path = env::args().nth(2).unwrap_or("router-config.json".to_string());
-        let listen_port: u16 = env::args().nth(3)
-            .and_then(|s| s.parse().ok())
-            .unwrap_or(1337);
+        let args: Vec<String> = env::args().collect();
+        let listen_port = default_listen_port(&args, 1337);
+        let mut watch = None;
+        if let Some(idx) = args.iter().position(|s| s == "--watch") {
+            match args.get(idx + 1).and_then(|v| v.parse::<u64>().ok()).filter(|secs| *secs > 0) {
+                Some(secs) => watch = Some(Duration::from_secs(secs)),
+                None => {
+                    eprintln!("Error: --watch expects a number of seconds");
+                    std::process::exit(1);
+                }
+            }
+        }
         
-        return router::run_router(&config_path, listen_port);
+        let listeners = bind_listeners(listen_port, &args)?;
+        return router::run_router(&config_path, listeners, watch);
     }
 
     // -----------------------------------------------------------------------
     // Listening mode: REST API server with bounded thread pool
     // -----------------------------------------------------------------------
-    if command == "host" && exists == false && phext_or_port.len() > 0 && is_port_number {
-        let port = phext_or_port;
+    let listen_flags_only = phext_or_port.starts_with("--") && env::args().any(|a| a == "--socket" || a == "--bind");
+    if command == "host" && ((!exists && !phext_or_port.is_empty() && is_port_number) || listen_flags_only) {
+        let port = if listen_flags_only { String::new() } else { phext_or_port };
 
         // Parse optional auth, data-dir, mesh-config, and config arguments
         // Usage: sq host <port> [--config <tenants.json>] OR [--key <pmb-v1-...>] [--data-dir <path>] [--mesh-config <path>]
+        //        [--durability <none|batch|always>] [--max-resident <MB>] [--bind <addr>]... [--socket <path>]
+        //        sq host --socket <path> [...] listens on the unix socket only; sq host --bind <addr:port> [...] needs no <port>
         let args: Vec<String> = env::args().collect();
+
+        // Listen on the port (or the --bind addresses), the unix socket, or both
+        let listeners = bind_listeners(port.parse().ok(), &args)?;
+        let listening = listeners.iter().map(|l| l.to_string()).collect::<Vec<_>>().join(", ");
+
+        // Write-ahead log durability applies to both single- and multi-tenant mode
+        let mut durability = wal::Durability::Batch;
+        if let Some(idx) = args.iter().position(|s| s == "--durability") {
+            match args.get(idx + 1).and_then(|v| wal::Durability::parse(v)) {
+                Some(d) => durability = d,
+                None => {
+                    eprintln!("Error: --durability expects one of: none, batch, always");
+                    std::process::exit(1);
+                }
+            }
+        }
         
         // Check for --config (multi-tenant mode)
         let config_idx = args.iter().position(|s| s == "--config");
         if let Some(idx) = config_idx {
             if idx + 1 < args.len() {
                 let config_path = &args[idx + 1];
-                return run_multi_tenant_server(&port, config_path);
+                return run_multi_tenant_server(listeners, config_path, durability);
             } else {
                 eprintln!("Error: --config requires a path argument");
                 eprintln!("Usage: sq host <port> --config <tenants.json>");
@@ -306,41 +671,45 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         let mut data_dir: Option<String> = None;
         let mut mesh_config_path: Option<String> = None;
         let mut tenant_config_path: Option<String> = None;
-        let mut i = 3;
+        let mut resident_budget_mb = RESIDENT_BUDGET_MB;
+        let mut i = if listen_flags_only { 2 } else { 3 };
         while i < args.len() {
             match args[i].as_str() {
-                "--key" => {
-                    if i + 1 < args.len() {
-                        auth_key = Some(args[i + 1].clone());
-                        i += 2;
-                    } else { i += 1; }
+                "--key" if i + 1 < args.len() => {
+                    auth_key = Some(args[i + 1].clone());
+                    i += 2;
                 }
-                "--data-dir" => {
-                    if i + 1 < args.len() {
-                        let dir = args[i + 1].clone();
-                        let _ = std::fs::create_dir_all(&dir);
-                        data_dir = Some(dir);
-                        i += 2;
-                    } else { i += 1; }
+                "--data-dir" if i + 1 < args.len() => {
+                    let dir = args[i + 1].clone();
+                    let _ = std::fs::create_dir_all(&dir);
+                    data_dir = Some(dir);
+                    i += 2;
                 }
-                "--mesh-config" => {
-                    if i + 1 < args.len() {
-                        mesh_config_path = Some(args[i + 1].clone());
-                        i += 2;
-                    } else { i += 1; }
+                "--mesh-config" if i + 1 < args.len() => {
+                    mesh_config_path = Some(args[i + 1].clone());
+                    i += 2;
                 }
-                "--config" => {
-                    if i + 1 < args.len() {
-                        tenant_config_path = Some(args[i + 1].clone());
-                        i += 2;
-                    } else { i += 1; }
+                "--config" if i + 1 < args.len() => {
+                    tenant_config_path = Some(args[i + 1].clone());
+                    i += 2;
+                }
+                "--socket" | "--bind" => { i += 2; }
+                "--max-resident" => {
+                    match args.get(i + 1).and_then(|v| v.parse::<usize>().ok()) {
+                        Some(mb) => resident_budget_mb = mb,
+                        None => {
+                            eprintln!("Error: --max-resident expects a size in MB");
+                            std::process::exit(1);
+                        }
+                    }
+                    i += 2;
                 }
                 _ => { i += 1; }
             }
         }
 
         // Load mesh config if provided
-        let _mesh_config: Option<mesh::MeshConfig> = match mesh_config_path {
+        let mesh_config: Option<mesh::MeshConfig> = match mesh_config_path {
             Some(path) => {
                 match mesh::load_mesh_config(&path) {
                     Ok(config) => {
@@ -408,55 +777,45 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
         }
 
-        let listener = TcpListener::bind(format!("0.0.0.0:{}", port)).unwrap();
-        println!("SQ v{} listening on port {} (max {} concurrent connections)...",
-            env!("CARGO_PKG_VERSION"), port, MAX_CONCURRENT_CONNECTIONS);
-
-        let state = Arc::new(Mutex::new(ServerState {
-            loaded_phext: String::new(),
-            loaded_map: Default::default(),
-        }));
+        let pool = pool::start(WORKER_THREADS, WORKER_QUEUE_DEPTH);
+        println!("SQ v{} listening on {} ({} workers, queue depth {})...",
+            env!("CARGO_PKG_VERSION"), listening, pool.workers(), pool.queue_depth());
+        println!("Write-ahead log durability: {}", durability.name());
+        println!("Resident phext budget: {} MB", resident_budget_mb);
 
-        let mut connection_id: u64 = 0;
-        for stream in listener.incoming() {
-            match stream {
-                Ok(stream) => {
-                    // --- Guard: reject when at capacity ---
-                    let current = ACTIVE_CONNECTIONS.load(Ordering::Relaxed);
-                    if current >= MAX_CONCURRENT_CONNECTIONS {
-                        eprintln!("[!] Connection limit reached ({}/{}), rejecting",
-                            current, MAX_CONCURRENT_CONNECTIONS);
-                        let mut s = stream;
-                        send_response(&mut s, 503, "Service Unavailable: connection limit reached");
-                        continue;
-                    }
+        let residents = Arc::new(resident::ResidentSet::new(resident_budget_mb * 1024 * 1024));
+        {
+            let residents = Arc::clone(&residents);
+            spawn_wal_compactor(move || residents.states());
+        }
+        if let Some(config) = mesh_config {
+            versions::enable(&config.node.id);
+            health::start(&config);
+            replication::spawn(config, data_dir.clone(), Arc::clone(&residents), durability);
+        }
 
-                    // --- Set timeouts to prevent idle threads from piling up ---
-                    if let Err(e) = stream.set_read_timeout(Some(Duration::from_secs(READ_TIMEOUT_SECS))) {
-                        eprintln!("[!] Failed to set read timeout: {}", e);
-                    }
-                    if let Err(e) = stream.set_write_timeout(Some(Duration::from_secs(WRITE_TIMEOUT_SECS))) {
-                        eprintln!("[!] Failed to set write timeout: {}", e);
-                    }
+        let host = Arc::new(Host { residents, auth_key, data_dir, tenant_map, durability });
+        let connection_id = AtomicU64::new(0);
+        socket::serve(listeners, move |stream| {
+            // --- Set timeouts to prevent idle threads from piling up ---
+            if let Err(e) = stream.set_read_timeout(Some(Duration::from_secs(READ_TIMEOUT_SECS))) {
+                eprintln!("[!] Failed to set read timeout: {}", e);
+            }
+            if let Err(e) = stream.set_write_timeout(Some(Duration::from_secs(WRITE_TIMEOUT_SECS))) {
+                eprintln!("[!] Failed to set write timeout: {}", e);
+            }
 
-                    connection_id += 1;
-                    ACTIVE_CONNECTIONS.fetch_add(1, Ordering::Relaxed);
-                    let state = Arc::clone(&state);
-                    let auth_key = auth_key.clone();
-                    let data_dir = data_dir.clone();
-                    let tenants = tenant_map.clone();
-                    let cid = connection_id;
-                    std::thread::spawn(move || {
-                        handle_tcp_connection(state, cid, stream, &auth_key, &data_dir, &tenants);
-                        ACTIVE_CONNECTIONS.fetch_sub(1, Ordering::Relaxed);
-                    });
-                }
-                Err(e) => {
-                    eprintln!("Accept error: {}", e);
-                    continue;
-                }
+            let cid = connection_id.fetch_add(1, Ordering::SeqCst) + 1;
+            let host = Arc::clone(&host);
+            // --- Guard: reject when the worker queue is full ---
+            let queued = pool.try_execute(stream, move |stream| {
+                handle_tcp_connection(&host, cid, stream);
+            });
+            if let Err(mut stream) = queued {
+                eprintln!("[!] Worker queue full ({} waiting), rejecting", pool.queue_length());
+                send_response(&mut stream, 503, "Service Unavailable: server busy", false);
             }
-        }
+        });
         return Ok(());
     }
 
@@ -467,23 +826,19 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         recreate_sq_work_files();
     }
 
-    let (shmem, wkmem) = loop {
-        let shmem: Shmem = match create_shared_segment() {
-            Ok(s) => { s }
-            Err(ShmemError::LinkExists) => { ShmemConf::new().flink(SHARED_NAME).open()? }
-            Err(e) => { return Err(Box::new(e)); }
-        };
-        let wkmem: Shmem = match create_work_segment() {
-            Ok(w) => { w }
-            Err(ShmemError::LinkExists) => { ShmemConf::new().flink(WORK_NAME).open()? }
-            Err(e) => { return Err(Box::new(e)); }
-        };
-
-        break (shmem, wkmem);
+    let shmem: Shmem = match create_shared_segment() {
+        Ok(s) => { s }
+        Err(ShmemError::LinkExists) => { ShmemConf::new().flink(SHARED_NAME).open()? }
+        Err(e) => { return Err(Box::new(e)); }
+    };
+    let wkmem: Shmem = match create_work_segment() {
+        Ok(w) => { w }
+        Err(ShmemError::LinkExists) => { ShmemConf::new().flink(WORK_NAME).open()? }
+        Err(e) => { return Err(Box::new(e)); }
     };
 
-    if shmem.is_owner() && is_basic_or_share(command) { return server(shmem, wkmem); }
-    else { return client(shmem, wkmem); }
+    if shmem.is_owner() && is_basic_or_share(command) { server(shmem, wkmem) }
+    else { client(shmem, wkmem) }
 }
 
 // -----------------------------------------------------------------------------------------------------------
@@ -499,7 +854,7 @@ fn fetch_message(shmem: *mut u8, start: usize) -> String {
             return String::new();
         }
         let unparsed = std::slice::from_raw_parts(shmem.add(start+length_size), length);
-        return String::from_utf8_unchecked(unparsed.to_vec()).to_string();
+        String::from_utf8_unchecked(unparsed.to_vec()).to_string()
     }
 }
 
@@ -525,7 +880,7 @@ fn url_decode(encoded: &str) -> String {
         .decode_utf8_lossy()
         .to_string();
 
-    return stage2;
+    stage2
 }
 
 // -----------------------------------------------------------------------------------------------------------
@@ -544,160 +899,137 @@ fn parse_query_string(query: &str) -> HashMap<String, String> {
         }
     }
 
-    return result;
+    result
 }
 
 // -----------------------------------------------------------------------------------------------------------
-// minimal HTTP parsing
+// builds the select-range / select-prefix / search argument string (see sq::Selection) from REST parameters
+//   `c` supplies the range start or the prefix pattern; q/prefix/to/after/limit/format pass through
 // -----------------------------------------------------------------------------------------------------------
-fn request_parse(request: &HttpRequest) -> Option<HashMap<String, String>> {
-    let mut result = HashMap::new();
-    let content = String::from_utf8_lossy(&request.content).to_string();
-    let lines = request.header.split("\r\n");
-    for line in lines {
-        let mut parts = line.splitn(2, '?');
-        if let (Some(key), Some(value)) = (parts.next(), parts.next()) {
-            if key.contains("favicon.ico") { return None; }
-            result = parse_query_string(value.strip_suffix(" HTTP/1.1").unwrap_or(value));
-        }
-        break;
+fn selection_query(parsed: &HashMap<String, String>, anchor: &str) -> String {
+    let encode = |v: &str| percent_encoding::utf8_percent_encode(v, percent_encoding::NON_ALPHANUMERIC).to_string();
+    let mut args: Vec<String> = Vec::new();
+    if let Some(c) = parsed.get("c") {
+        args.push(format!("{}={}", anchor, encode(c)));
     }
-    if content.len() > 0 {
-        result.insert("content".to_string(), content);
+    for key in ["q", "prefix", "to", "after", "limit", "format"] {
+        if let Some(value) = parsed.get(key) {
+            args.push(format!("{}={}", key, encode(value)));
+        }
     }
-
-    return Some(result);
+    args.join("&")
 }
 
-pub struct HttpRequest {
-    pub header: String,
-    pub content: Vec<u8>,
-}
-
-pub fn read_http_request(stream: &mut TcpStream) -> std::io::Result<HttpRequest> {
-    let mut buffer = Vec::new();
-    let mut temp = [0u8; 1024];
-    let header_end;
-    loop {
-        let n = stream.read(&mut temp)?;
-        if n == 0 {
-            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "connection closed"));
-        }
-        buffer.extend_from_slice(&temp[..n]);
-
-        if let Some(pos) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
-            header_end = pos + 4;
-            break;
-        }
-
-        // Guard: reject absurdly large headers
-        if buffer.len() > ABSURD_HEADER_SIZE {
-            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "header too large"));
-        }
+// -----------------------------------------------------------------------------------------------------------
+// Builds the delta/merkle request for sq::query: the include/exclude query parameters (comma-separated
+// patterns), narrowed by any filter lines in the body and by the requesting mesh peer's `scope`, as filter
+// lines, then the rest of the body
+// -----------------------------------------------------------------------------------------------------------
+fn delta_query(parsed: &HashMap<String, String>, scope: Option<&sq::CoordinateFilter>, body: &str) -> Result<String, String> {
+    let patterns = |key: &str| -> Vec<String> {
+        parsed.get(key)
+            .map(|v| v.split(',').map(|p| p.trim().to_string()).filter(|p| !p.is_empty()).collect())
+            .unwrap_or_default()
+    };
+    let mut filter = sq::CoordinateFilter::parse(&patterns("include"), &patterns("exclude"))?
+        .restrict(&sq::CoordinateFilter::from_lines(body)?);
+    if let Some(scope) = scope {
+        filter = filter.restrict(scope);
     }
+    let rest: Vec<&str> = body.lines().filter(|line| !sq::CoordinateFilter::is_filter_line(line)).collect();
+    Ok(format!("{}{}", filter.to_lines(), rest.join("\n")))
+}
 
-    let header_bytes = &buffer[..header_end];
-    let header_str = String::from_utf8_lossy(header_bytes).to_string();
-
-    let content_length = header_str
-        .lines()
-        .find(|line| line.to_ascii_lowercase().starts_with("content-length:"))
-        .and_then(|line| line.split(':').nth(1))
-        .and_then(|val| val.trim().parse::<usize>().ok())
-        .unwrap_or(0);
-
-    // Guard: reject bodies larger than MAX_BODY_SIZE
-    if content_length > MAX_BODY_SIZE {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            format!("body too large: {} bytes (max {})", content_length, MAX_BODY_SIZE),
-        ));
+// -----------------------------------------------------------------------------------------------------------
+// minimal HTTP parsing
+// -----------------------------------------------------------------------------------------------------------
+fn request_parse(request: &http::HttpRequest) -> Option<HashMap<String, String>> {
+    let mut result = HashMap::new();
+    let content = String::from_utf8_lossy(&request.content).to_string();
+    let (_, target, _) = http::request_line(&request.header)?;
+    if let Some((path, query)) = target.split_once('?') {
+        if path.contains("favicon.ico") { return None; }
+        result = parse_query_string(query);
     }
-
-    let mut content = buffer[header_end..].to_vec();
-    while content.len() < content_length {
-        let remaining = content_length - content.len();
-        let mut chunk = vec![0u8; remaining.min(1024)];
-        let n = stream.read(&mut chunk)?;
-        if n == 0 {
-            break;
-        }
-        content.extend_from_slice(&chunk[..n]);
+    if !content.is_empty() {
+        result.insert("content".to_string(), content);
     }
 
-    Ok(HttpRequest {
-        header: header_str,
-        content,
-    })
+    Some(result)
 }
 
 // -----------------------------------------------------------------------------------------------------------
-// TCP connection handler — catches panics so the server never dies from a bad request
+// TCP connection handler — serves requests until the client closes, idles out, or hits the request cap
+//   catches panics so the server never dies from a bad request
 // -----------------------------------------------------------------------------------------------------------
-fn handle_tcp_connection(
-    state: Arc<Mutex<ServerState>>,
-    connection_id: u64,
-    mut stream: TcpStream,
-    auth_key: &Option<String>,
-    data_dir: &Option<String>,
-    tenant_map: &Option<Arc<HashMap<String, config::TenantConfig>>>,
-) {
-    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-        handle_tcp_connection_inner(state, connection_id, &mut stream, auth_key, data_dir, tenant_map)
-    }));
-    if let Err(e) = result {
-        eprintln!("[#{}] panic: {:?}", connection_id, e);
-        send_response(&mut stream, 500, "Internal Server Error");
+fn handle_tcp_connection(host: &Host, connection_id: u64, mut stream: socket::Stream) {
+    let mut reader = http::MessageReader::new(ABSURD_HEADER_SIZE, MAX_BODY_SIZE);
+    for served in 1..=http::MAX_REQUESTS_PER_CONNECTION {
+        // Phase 1: Read request (no lock needed)
+        if served > 1 && !reader.has_buffered() &&
+            !http::wait_for_request(&stream, Duration::from_secs(READ_TIMEOUT_SECS), pool::has_backlog) {
+            return; // idle, or the worker is needed by a queued connection
+        }
+        let http_request = match reader.next_message(&mut stream) {
+            Ok(Some(req)) => req,
+            Ok(None) => return, // closed between requests
+            Err(e) if reader.idle_timeout(&e) => return,
+            Err(e) => {
+                // Distinguish between client misbehavior and normal timeouts
+                if e.kind() == std::io::ErrorKind::InvalidData {
+                    eprintln!("[#{}] rejected: {}", connection_id, e);
+                    send_response(&mut stream, 413, &format!("{}", e), false);
+                } else {
+                    eprintln!("[#{}] read error: {}", connection_id, e);
+                }
+                return;
+            }
+        };
+        let keep_alive = http::keep_alive(&http_request.header) && served < http::MAX_REQUESTS_PER_CONNECTION
+            && !pool::has_backlog();
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            handle_tcp_request(host, connection_id, &mut stream, &http_request, keep_alive)
+        }));
+        if let Err(e) = result {
+            eprintln!("[#{}] panic: {:?}", connection_id, e);
+            send_response(&mut stream, 500, "Internal Server Error", false);
+            return;
+        }
+        if !keep_alive {
+            return;
+        }
     }
 }
 
 // -----------------------------------------------------------------------------------------------------------
-// Inner connection handler — all the actual HTTP logic
+// Request handler — all the actual HTTP logic for one request on a (possibly persistent) connection
 // -----------------------------------------------------------------------------------------------------------
-fn handle_tcp_connection_inner(
-    state: Arc<Mutex<ServerState>>,
+fn handle_tcp_request(
+    host: &Host,
     connection_id: u64,
-    stream: &mut TcpStream,
-    auth_key: &Option<String>,
-    data_dir: &Option<String>,
-    tenant_map: &Option<Arc<HashMap<String, config::TenantConfig>>>,
+    stream: &mut socket::Stream,
+    http_request: &http::HttpRequest,
+    keep_alive: bool,
 ) {
-    // Phase 1: Read request (no lock needed)
-    let http_request = match read_http_request(stream) {
-        Ok(req) => req,
-        Err(e) => {
-            let kind = e.kind();
-            // Distinguish between client misbehavior and normal timeouts
-            if kind == std::io::ErrorKind::InvalidData {
-                eprintln!("[#{}] rejected: {}", connection_id, e);
-                send_response(stream, 413, &format!("{}", e));
-            } else {
-                eprintln!("[#{}] read error: {}", connection_id, e);
-            }
-            return;
-        }
-    };
+    let Host { residents, auth_key, data_dir, tenant_map, durability } = host;
+    let durability = *durability;
     let request = &http_request.header;
 
     // Handle CORS preflight
     if request.starts_with("OPTIONS ") {
-        let _ = stream.write_all(
-            b"HTTP/1.1 204 No Content\r\n\
-              Access-Control-Allow-Origin: *\r\n\
-              Access-Control-Allow-Methods: GET, POST, OPTIONS\r\n\
-              Access-Control-Allow-Headers: Authorization, Content-Type\r\n\
-              Access-Control-Max-Age: 86400\r\n\r\n"
-        );
+        let _ = stream.write_all(http::preflight_response(keep_alive, READ_TIMEOUT_SECS).as_bytes());
         return;
     }
 
-    if !request.starts_with("GET ") && !request.starts_with("POST ") {
-        send_response(stream, 400, "Bad Request");
+    if http::request_line(request).is_none() || (!request.starts_with("GET ") && !request.starts_with("POST ")) {
+        send_response(stream, 400, "Bad Request", keep_alive);
         return;
     }
 
     // Multi-tenant auth: resolve token → tenant data_dir, or fall back to single-key mode
     let resolved_data_dir: Option<String>;
+    let mut scope: Option<sq::CoordinateFilter> = None;
     if let Some(ref tenants) = tenant_map {
         // Multi-tenant mode: extract token and look up tenant
         let token = extract_header(request, "authorization")
@@ -712,22 +1044,48 @@ fn handle_tcp_connection_inner(
                 resolved_data_dir = Some(dir);
             }
             _ => {
-                send_response(stream, 401, "Unauthorized");
+                send_response(stream, 401, "Unauthorized", keep_alive);
                 return;
             }
         }
     } else {
-        // Single-tenant mode: use --key / --data-dir
-        if !validate_auth(request, auth_key) {
-            send_response(stream, 401, "Unauthorized");
+        // Single-tenant mode: use --key / --data-dir; mesh peers pull with their own keys (see replication.rs)
+        let token = bearer_token(request);
+        let peer = token.as_deref().map(replication::is_peer_key).unwrap_or(false);
+        if !peer && !validate_auth(request, auth_key) {
+            send_response(stream, 401, "Unauthorized", keep_alive);
             return;
         }
+        scope = replication::caller_scope(token.as_deref(), extract_header(request, "x-sq-node").as_deref());
         resolved_data_dir = data_dir.clone();
     }
 
+    // Mesh peers read scrolls in their scope, nothing about the host itself
+    if scope.is_some() && (request.starts_with("GET /api/v2/loaded") || request.starts_with("GET /api/v2/mesh/")) {
+        send_response(stream, 403, "Forbidden: not available to mesh peers", keep_alive);
+        return;
+    }
+
+    // Resident phexts (no phext parameter - a tenant only sees its own)
+    if request.starts_with("GET /api/v2/loaded") {
+        send_response(stream, 200, &residents.report(&resolved_data_dir), keep_alive);
+        return;
+    }
+
+    // Mesh peer health and sync status
+    if request.starts_with("GET /api/v2/mesh/status") {
+        let body = serde_json::to_string_pretty(&health::status()).unwrap_or_default();
+        send_response(stream, 200, &body, keep_alive);
+        return;
+    }
+
     // Phase 2: Parse request (no lock needed)
-    let parsed = match request_parse(&http_request) {
-        None => return, // favicon.ico etc
+    let parsed = match request_parse(http_request) {
+        None => {
+            // favicon.ico etc - still answered, so a persistent connection doesn't stall
+            send_response(stream, 404, "Not Found", keep_alive);
+            return;
+        }
         Some(x) => x,
     };
 
@@ -739,7 +1097,7 @@ fn handle_tcp_connection_inner(
     let phext = match validate_tenant_path(&phext_name, &resolved_data_dir) {
         Some(path) => path,
         None => {
-            send_response(stream, 403, "Forbidden: invalid phext path");
+            send_response(stream, 403, "Forbidden: invalid phext path", keep_alive);
             return;
         }
     };
@@ -759,6 +1117,15 @@ fn handle_tcp_connection_inner(
         reload_needed = true;
     } else if request.starts_with("GET /api/v2/select") {
         command = "select".to_string();
+    } else if request.starts_with("GET /api/v2/range") {
+        command = "select-range".to_string();
+        scroll = selection_query(&parsed, "from");
+    } else if request.starts_with("GET /api/v2/prefix") {
+        command = "select-prefix".to_string();
+        scroll = selection_query(&parsed, "prefix");
+    } else if request.starts_with("GET /api/v2/search") {
+        command = "search".to_string();
+        scroll = selection_query(&parsed, "prefix");
     } else if request.starts_with("GET /api/v2/insert") {
         command = "insert".to_string();
     } else if request.starts_with("POST /api/v2/insert") {
@@ -782,46 +1149,84 @@ fn handle_tcp_connection_inner(
         command = "toc".to_string();
     } else if request.starts_with("GET /api/v2/get") {
         command = "get".to_string();
-    } else if request.starts_with("GET /api/v2/delta") {
-        command = "delta".to_string();
-    } else if request.starts_with("POST /api/v2/delta") {
-        command = "delta".to_string();
+    } else if request.starts_with("GET /api/v2/delta") || request.starts_with("POST /api/v2/delta") ||
+              request.starts_with("POST /api/v2/merkle") {
+        command = if request.starts_with("POST /api/v2/merkle") { "merkle" } else { "delta" }.to_string();
+        if request.starts_with("POST ") {
+            if let Some(content) = parsed.get("content") { scroll = content.clone(); }
+        }
+        scroll = match delta_query(&parsed, scope.as_ref(), &scroll) {
+            Ok(query) => query,
+            Err(message) => {
+                send_response(stream, 400, &message, keep_alive);
+                return;
+            }
+        };
+    } else if request.starts_with("POST /api/v2/mesh/versions") {
+        command = "versions".to_string();
         if let Some(content) = parsed.get("content") { scroll = content.clone(); }
+    } else if request.starts_with("GET /api/v2/mesh/conflicts") {
+        send_response(stream, 200, &conflict::report(&phext, limit), keep_alive);
+        return;
     } else if request.starts_with("GET /api/v2/version") {
         command = "version".to_string();
+    } else if request.starts_with("POST /api/v2/json-import") {
+        command = "json-import".to_string();
+        let mode = parsed.get("mode").map(|m| m.as_str()).unwrap_or("merge");
+        scroll = format!("mode={}\n{}", mode, parsed.get("content").unwrap_or(&nothing));
     } else if request.starts_with("GET /api/v2/json-export") {
         command = "json-export".to_string();
         reload_needed = true;
     } else {
-        send_response(stream, 404, "Not Found");
+        send_response(stream, 404, "Not Found", keep_alive);
+        return;
+    }
+
+    if scope.is_some() && (!is_read_only(&command) || reload_needed) {
+        send_response(stream, 403, "Forbidden: mesh peers can only read", keep_alive);
         return;
     }
 
-    // Phase 3: Acquire lock, process, optionally write to disk
-    let output = {
-        let mut state = state.lock().unwrap_or_else(|poisoned| {
-            eprintln!("[#{}] recovering from poisoned mutex", connection_id);
+    // Phase 3: Reads share the phext's read lock; mutations and (re)loads take its write lock and may write to disk
+    let state = residents.get(&phext);
+    let coordinate = phext::to_coordinate(coord.as_str());
+    let query = sq::Request {
+        connection_id, source: phext.clone(), command: command.clone(), coordinate,
+        update: scroll, filename: phext.clone(), algorithm, limit,
+    };
+    let shared = if is_read_only(&command) && !reload_needed {
+        shared_query(&state, &query, scope.as_ref())
+    } else {
+        None
+    };
+    let output = if let Some(output) = shared { output } else {
+        let mut state = state.write().unwrap_or_else(|poisoned| {
+            eprintln!("[#{}] recovering from poisoned lock", connection_id);
             poisoned.into_inner()
         });
 
         // Check if we need to reload (phext changed or explicit load)
         if reload_needed || state.loaded_phext != phext {
-            state.loaded_map = fetch_source(phext.clone());
-            state.loaded_phext = phext.clone();
+            state.load(&phext, durability);
         }
 
+        let before = if is_bulk_mutation(&command) { Some(state.checksums()) } else { None };
         let mut output = String::new();
-        let _ = sq::process(
-            connection_id, phext.clone(), &mut output, command.clone(),
-            &mut state.loaded_map, phext::to_coordinate(coord.as_str()),
-            scroll.clone(), phext.clone(), algorithm, limit,
-        );
-
-        // Only flush to disk when the command actually changed something
-        // Uses implode_ref: borrows the map instead of cloning it
+        if is_read_only(&command) && !reload_needed {
+            output = read_query(&state, &query, scope.as_ref());
+        } else {
+            let _ = sq::process(&query, &mut output, &mut state.loaded_map);
+        }
+
+        // Only touch the disk when the command actually changed something
+        // Mutations are appended to the write-ahead log; the compactor rewrites the phext later
         if is_mutation(&command) {
-            let phext_buffer = sq::implode_ref(&state.loaded_map);
-            if let Err(e) = std::fs::write(&phext, &phext_buffer) {
+            if let Err(e) = state.persist(coordinate) {
+                eprintln!("[#{}] disk write failed for {}: {}", connection_id, phext, e);
+            }
+        }
+        if let Some(before) = before {
+            if let Err(e) = state.persist_changes(before) {
                 eprintln!("[#{}] disk write failed for {}: {}", connection_id, phext, e);
             }
         }
@@ -831,11 +1236,7 @@ fn handle_tcp_connection_inner(
     };
 
     // Phase 4: Send response (no lock needed)
-    let length = output.len();
-    let response = format!(
-        "HTTP/1.1 200 OK\r\nAccess-Control-Allow-Origin: *\r\nContent-Length: {length}\r\n\r\n{output}"
-    );
-    let _ = stream.write_all(response.as_bytes());
+    send_response(stream, 200, &output, keep_alive);
 }
 
 // -----------------------------------------------------------------------------------------------------------
@@ -892,7 +1293,11 @@ fn server(shmem: Shmem, wkmem: Shmem) -> Result<(), Box<dyn std::error::Error>>
         let update = phext::fetch(parts.as_str(), ps3);
 
         let mut scroll = String::new();
-        let done = sq::process(connection_id, filename.clone(), &mut scroll, command, &mut phext_buffer, coordinate, update, argtemp.clone(), HashAlgorithm::Xor, 100);
+        let request = sq::Request {
+            connection_id, source: filename.clone(), command, coordinate,
+            update, filename: argtemp.clone(), algorithm: HashAlgorithm::Xor, limit: 100,
+        };
+        let done = sq::process(&request, &mut scroll, &mut phext_buffer);
         let scroll_length = scroll.len();
 
         send_message(shmem.as_ptr(), length_offset, scroll);
@@ -954,8 +1359,33 @@ fn client(shmem: Shmem, wkmem: Shmem) -> Result<(), Box<dyn std::error::Error>>
 
     let mut coordinate = args.get(2).unwrap_or(&nothing).to_string();
     let mut message: String = args.get(3).unwrap_or(&nothing).to_string();
+    if command == "select-range" {
+        // sq select-range <from> <to> [options]
+        message = format!("from={}&to={}&{}", coordinate, message, args.get(4).unwrap_or(&nothing));
+    }
+    if command == "select-prefix" {
+        // sq select-prefix <pattern> [options]
+        message = format!("prefix={}&{}", coordinate, message);
+    }
+    if command == "search" {
+        // sq search "<query>" [options] - the query isn't a coordinate, so keep it out of the response prefix
+        let query = percent_encoding::utf8_percent_encode(&coordinate, percent_encoding::NON_ALPHANUMERIC).to_string();
+        message = format!("q={}&{}", query, message);
+        coordinate = String::new();
+    }
     if command == "push" {
-        message = phext::implode(fetch_source(message));
+        message = fetch_source(message).implode();
+    }
+    if command == "json-import" {
+        // sq json-import <file> [merge|replace]
+        let mode = if message.is_empty() { "merge".to_string() } else { message.clone() };
+        match std::fs::read_to_string(&coordinate) {
+            Ok(document) => message = format!("mode={}\n{}", mode, document),
+            Err(e) => {
+                println!("Unable to read {}: {}", coordinate, e);
+                return Ok(());
+            }
+        }
     }
     if command == "slurp" {
         let mut summary = String::new();
@@ -963,38 +1393,36 @@ fn client(shmem: Shmem, wkmem: Shmem) -> Result<(), Box<dyn std::error::Error>>
         println!("Slurping {message}...");
         let mut coord = phext::to_coordinate(coordinate.as_str());
         let toc = coord;
-        for entry in std::fs::read_dir(dir).ok().into_iter().flat_map(|e| e) {
-            if let Ok(entry) = entry {
-                coord.scroll_break();
-                if coord.x.scroll == (phext::COORDINATE_MAXIMUM - 1) {
-                    coord.section_break();
-                }
-                if coord.x.section == (phext::COORDINATE_MAXIMUM - 1) {
-                    coord.chapter_break();
-                }
-                if coord.x.chapter == (phext::COORDINATE_MAXIMUM - 1) {
-                    coord.book_break();
-                    println!("Warning: Slurp exceeded 900M scrolls.");
-                }
-                let path = entry.path();
-                let mut filename = String::new();
-                if let Some(parsed_filename) = path.file_name() {
-                    filename = parsed_filename.to_string_lossy().to_string();
-                }
-                let checker = filename.to_lowercase();
-                if is_media_resource(checker.as_str()) {
-                    summary.push_str(&format!("{coord} {filename} (Resource)\n").as_str());
-                    continue;
-                }
-                if path.is_file() {
-                    if let Ok(content) = fs::read_to_string(&path) {
-                        summary.push_str(&format!("{coord} {filename}\n").as_str());
-                        client_submit(command, coordinate.as_str(), content.as_str(), shmem.as_ptr(), length_offset);
-                        coordinate = coord.to_string();
-                        evt.set(EventState::Signaled)?;
-                        work.wait(Timeout::Infinite)?;
-                        client_response(shmem.as_ptr(), length_offset, command, message.as_str(), coordinate.as_str());
-                    }
+        for entry in std::fs::read_dir(dir).ok().into_iter().flatten().flatten() {
+            coord.scroll_break();
+            if coord.x.scroll == (phext::COORDINATE_MAXIMUM - 1) {
+                coord.section_break();
+            }
+            if coord.x.section == (phext::COORDINATE_MAXIMUM - 1) {
+                coord.chapter_break();
+            }
+            if coord.x.chapter == (phext::COORDINATE_MAXIMUM - 1) {
+                coord.book_break();
+                println!("Warning: Slurp exceeded 900M scrolls.");
+            }
+            let path = entry.path();
+            let mut filename = String::new();
+            if let Some(parsed_filename) = path.file_name() {
+                filename = parsed_filename.to_string_lossy().to_string();
+            }
+            let checker = filename.to_lowercase();
+            if is_media_resource(checker.as_str()) {
+                summary.push_str(format!("{coord} {filename} (Resource)\n").as_str());
+                continue;
+            }
+            if path.is_file() {
+                if let Ok(content) = fs::read_to_string(&path) {
+                    summary.push_str(format!("{coord} {filename}\n").as_str());
+                    client_submit(command, coordinate.as_str(), content.as_str(), shmem.as_ptr(), length_offset);
+                    coordinate = coord.to_string();
+                    evt.set(EventState::Signaled)?;
+                    work.wait(Timeout::Infinite)?;
+                    client_response(shmem.as_ptr(), length_offset, command, message.as_str(), coordinate.as_str());
                 }
             }
         }
@@ -1034,10 +1462,12 @@ fn client_response(shmem: *mut u8, length_offset: usize, command: &str, message:
     let mut response = fetch_message(shmem, length_offset);
     if command == "pull" {
         let filename = message;
-        let _ = std::fs::write(filename, response.clone());
+        if let Err(e) = persist::write_atomic(filename, &response) {
+            eprintln!("Failed to write {}: {}", filename, e);
+        }
         response = format!("Exported scroll at {coordinate} to {filename}.").to_string();
     }
-    if coordinate.len() > 0 {
+    if !coordinate.is_empty() {
         println!("{coordinate}: {response}");
     } else {
         println!("{response}");
@@ -1048,23 +1478,30 @@ fn client_response(shmem: *mut u8, length_offset: usize, command: &str, message:
 // Multi-tenant REST API server (SQ v0.5.5)
 // Loads tenant config and serves requests from single process
 // -----------------------------------------------------------------------------------------------------------
-fn run_multi_tenant_server(port: &str, config_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+fn run_multi_tenant_server(listeners: Vec<socket::Listener>, config_path: &str, durability: wal::Durability) -> Result<(), Box<dyn std::error::Error>> {
     // Load initial tenant configuration
     let tenant_config = config::load_config(config_path)?;
     println!("SQ v{} - Multi-tenant mode (on-demand reload)", env!("CARGO_PKG_VERSION"));
     println!("Loaded {} tenants from {}", tenant_config.tenants.len(), config_path);
     println!("Config file: {}", config_path);
-    println!("Reload: POST http://localhost:{}/api/v2/reload", port);
+    println!("Reload: POST /api/v2/reload from localhost (or over the unix socket)");
     
-    let listener = TcpListener::bind(format!("0.0.0.0:{}", port))?;
-    println!("Listening on port {}...", port);
+    let pool = pool::start(WORKER_THREADS, WORKER_QUEUE_DEPTH);
+    let listening = listeners.iter().map(|l| l.to_string()).collect::<Vec<_>>().join(", ");
+    println!("Listening on {} ({} workers, queue depth {})...", listening, pool.workers(), pool.queue_depth());
     
-    let active_connections = Arc::new(AtomicUsize::new(0));
     let tenant_config = Arc::new(RwLock::new(tenant_config));
     
     // Per-tenant in-memory state: phext_path → ServerState
-    let tenant_states: Arc<Mutex<HashMap<String, Arc<Mutex<ServerState>>>>> =
-        Arc::new(Mutex::new(HashMap::new()));
+    let tenant_states: Arc<TenantStates> = Arc::new(Mutex::new(HashMap::new()));
+    println!("Write-ahead log durability: {}", durability.name());
+    {
+        let tenant_states = Arc::clone(&tenant_states);
+        spawn_wal_compactor(move || {
+            let states = tenant_states.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            states.values().cloned().collect()
+        });
+    }
     
     // Create reload channel
     let (reload_tx, reload_rx) = mpsc::channel();
@@ -1096,77 +1533,95 @@ fn run_multi_tenant_server(port: &str, config_path: &str) -> Result<(), Box<dyn
         });
     }
     
-    for stream in listener.incoming() {
-        let stream = match stream {
-            Ok(s) => s,
-            Err(e) => {
-                eprintln!("Failed to accept connection: {}", e);
-                continue;
-            }
-        };
-        
-        let count = active_connections.fetch_add(1, Ordering::SeqCst) + 1;
-        if count > MAX_CONCURRENT_CONNECTIONS {
-            active_connections.fetch_sub(1, Ordering::SeqCst);
-            eprintln!("Rejecting connection (at capacity: {} concurrent)", MAX_CONCURRENT_CONNECTIONS);
-            let _ = stream.shutdown(std::net::Shutdown::Both);
-            continue;
-        }
-        
+    socket::serve(listeners, move |stream| {
         let tenant_config_clone = Arc::clone(&tenant_config);
-        let active_connections = Arc::clone(&active_connections);
         let reload_tx_clone = reload_tx.clone();
         let tenant_states_clone = Arc::clone(&tenant_states);
         
-        std::thread::spawn(move || {
-            let config = tenant_config_clone.read().unwrap();
-            handle_multi_tenant_connection_with_reload(stream, &config, Some(reload_tx_clone), &tenant_states_clone);
-            active_connections.fetch_sub(1, Ordering::SeqCst);
+        let queued = pool.try_execute(stream, move |stream| {
+            handle_multi_tenant_connection_with_reload(stream, &tenant_config_clone, Some(reload_tx_clone), &tenant_states_clone, durability);
         });
-    }
+        if let Err(mut stream) = queued {
+            eprintln!("Rejecting connection (worker queue full: {} waiting)", pool.queue_length());
+            let _ = stream.set_write_timeout(Some(Duration::from_secs(WRITE_TIMEOUT_SECS)));
+            send_response(&mut stream, 503, "Service Unavailable: server busy", false);
+        }
+    });
     
     Ok(())
 }
 
 // -----------------------------------------------------------------------------------------------------------
-// Multi-tenant connection handler
+// Multi-tenant connection handler — serves requests until the client closes, idles out, or hits the cap
+//   the tenant config is read per request, so a reload applies to persistent connections immediately
 // -----------------------------------------------------------------------------------------------------------
 fn handle_multi_tenant_connection_with_reload(
-    mut stream: TcpStream, 
-    config: &config::ServerConfig,
+    mut stream: socket::Stream, 
+    config: &RwLock<config::ServerConfig>,
     reload_trigger: Option<mpsc::Sender<()>>,
-    tenant_states: &Arc<Mutex<HashMap<String, Arc<Mutex<ServerState>>>>>,
+    tenant_states: &TenantStates,
+    durability: wal::Durability,
 ) {
-    // Set timeouts
-    let timeout = Duration::from_secs(30);
-    let _ = stream.set_read_timeout(Some(timeout));
-    let _ = stream.set_write_timeout(Some(timeout));
+    // Set timeouts (the read timeout doubles as the keep-alive idle timeout)
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(READ_TIMEOUT_SECS)));
+    let _ = stream.set_write_timeout(Some(Duration::from_secs(WRITE_TIMEOUT_SECS)));
     
-    // Get peer address for localhost-only endpoints
-    let peer_addr = stream.peer_addr().ok();
-    let is_localhost = peer_addr.map(|addr| addr.ip().is_loopback()).unwrap_or(false);
-    
-    let http_request = match read_http_request(&mut stream) {
-        Ok(req) => req,
-        Err(e) => {
-            eprintln!("Failed to read request: {}", e);
-            send_response(&mut stream, 400, "Bad Request");
+    let mut reader = http::MessageReader::new(ABSURD_HEADER_SIZE, MAX_BODY_SIZE);
+    for served in 1..=http::MAX_REQUESTS_PER_CONNECTION {
+        if served > 1 && !reader.has_buffered() &&
+            !http::wait_for_request(&stream, Duration::from_secs(READ_TIMEOUT_SECS), pool::has_backlog) {
+            return; // idle, or the worker is needed by a queued connection
+        }
+        let http_request = match reader.next_message(&mut stream) {
+            Ok(Some(req)) => req,
+            Ok(None) => return,
+            Err(e) if reader.idle_timeout(&e) => return,
+            Err(e) => {
+                eprintln!("Failed to read request: {}", e);
+                let status = if e.kind() == std::io::ErrorKind::InvalidData { 413 } else { 400 };
+                send_response(&mut stream, status, "Bad Request", false);
+                return;
+            }
+        };
+        let keep_alive = http::keep_alive(&http_request.header) && served < http::MAX_REQUESTS_PER_CONNECTION
+            && !pool::has_backlog();
+        {
+            let config = config.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+            handle_multi_tenant_request(&mut stream, &http_request, keep_alive, &config, reload_trigger.as_ref(), tenant_states, durability);
+        }
+        if !keep_alive {
             return;
         }
-    };
-    
+    }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// Multi-tenant request handler
+// -----------------------------------------------------------------------------------------------------------
+fn handle_multi_tenant_request(
+    stream: &mut socket::Stream,
+    http_request: &http::HttpRequest,
+    keep_alive: bool,
+    config: &config::ServerConfig,
+    reload_trigger: Option<&mpsc::Sender<()>>,
+    tenant_states: &TenantStates,
+    durability: wal::Durability,
+) {
     let request = &http_request.header;
     
+    // Localhost-only endpoints also accept unix socket clients (the socket's permissions already vetted them)
+    let is_localhost = stream.is_local();
+    
     // Handle OPTIONS (CORS preflight) without auth
     if request.starts_with("OPTIONS ") {
-        send_response(&mut stream, 204, "");
+        let _ = stream.write_all(http::preflight_response(keep_alive, READ_TIMEOUT_SECS).as_bytes());
         return;
     }
     
     // Handle /api/v2/reload (localhost only, no auth required)
     if request.starts_with("POST /api/v2/reload") {
         if !is_localhost {
-            send_response(&mut stream, 403, "Forbidden: Reload endpoint only accessible from localhost");
+            send_response(stream, 403, "Forbidden: Reload endpoint only accessible from localhost", keep_alive);
             return;
         }
         
@@ -1174,21 +1629,21 @@ fn handle_multi_tenant_connection_with_reload(
         if let Some(trigger) = reload_trigger {
             match trigger.send(()) {
                 Ok(_) => {
-                    send_response(&mut stream, 200, "Config reload triggered");
+                    send_response(stream, 200, "Config reload triggered", keep_alive);
                 }
                 Err(_) => {
-                    send_response(&mut stream, 500, "Failed to trigger reload");
+                    send_response(stream, 500, "Failed to trigger reload", keep_alive);
                 }
             }
         } else {
-            send_response(&mut stream, 503, "Reload not available in this mode");
+            send_response(stream, 503, "Reload not available in this mode", keep_alive);
         }
         return;
     }
     
     // Validate HTTP method
-    if !request.starts_with("GET ") && !request.starts_with("POST") {
-        send_response(&mut stream, 400, "Bad Request");
+    if http::request_line(request).is_none() || (!request.starts_with("GET ") && !request.starts_with("POST ")) {
+        send_response(stream, 400, "Bad Request", keep_alive);
         return;
     }
     
@@ -1196,22 +1651,22 @@ fn handle_multi_tenant_connection_with_reload(
     let tenant = match extract_auth_token_multi(request, config) {
         Some(t) => t,
         None => {
-            send_response(&mut stream, 401, "Unauthorized");
+            send_response(stream, 401, "Unauthorized", keep_alive);
             return;
         }
     };
     
     // Parse request
-    let parsed = match request_parse(&http_request) {
+    let parsed = match request_parse(http_request) {
         Some(p) => p,
         None => {
-            send_response(&mut stream, 400, "Bad Request");
+            send_response(stream, 400, "Bad Request", keep_alive);
             return;
         }
     };
     
     let nothing = String::new();
-    let mut scroll = parsed.get("s").unwrap_or(&nothing);
+    let mut scroll = parsed.get("s").unwrap_or(&nothing).clone();
     let coord = parsed.get("c").unwrap_or(&nothing);
     let phext_name = parsed.get("p").unwrap_or(&nothing);
     
@@ -1219,7 +1674,7 @@ fn handle_multi_tenant_connection_with_reload(
     let phext_path = match validate_tenant_path_multi(phext_name, &tenant.data_dir) {
         Some(path) => path,
         None => {
-            send_response(&mut stream, 403, "Forbidden: Invalid phext path");
+            send_response(stream, 403, "Forbidden: Invalid phext path", keep_alive);
             return;
         }
     };
@@ -1231,30 +1686,39 @@ fn handle_multi_tenant_connection_with_reload(
     let limit: usize = limit_str.parse().unwrap_or(100);
     
     // Determine command and reload flag
-    let mut command = String::new();
+    let command: String;
     
     if request.starts_with("GET /api/v2/load") {
         command = "load".to_string();
     } else if request.starts_with("GET /api/v2/select") {
         command = "select".to_string();
+    } else if request.starts_with("GET /api/v2/range") {
+        command = "select-range".to_string();
+        scroll = selection_query(&parsed, "from");
+    } else if request.starts_with("GET /api/v2/prefix") {
+        command = "select-prefix".to_string();
+        scroll = selection_query(&parsed, "prefix");
+    } else if request.starts_with("GET /api/v2/search") {
+        command = "search".to_string();
+        scroll = selection_query(&parsed, "prefix");
     } else if request.starts_with("GET /api/v2/insert") {
         command = "insert".to_string();
     } else if request.starts_with("POST /api/v2/insert") {
         command = "insert".to_string();
-        if parsed.contains_key("content") {
-            scroll = &parsed["content"];
+        if let Some(content) = parsed.get("content") {
+            scroll = content.clone();
         }
     } else if request.starts_with("GET /api/v2/update") {
         command = "update".to_string();
     } else if request.starts_with("POST /api/v2/update") {
         command = "update".to_string();
-        if parsed.contains_key("content") {
-            scroll = &parsed["content"];
+        if let Some(content) = parsed.get("content") {
+            scroll = content.clone();
         }
     } else if request.starts_with("POST /api/v2/where") {
         command = "where".to_string();
-        if parsed.contains_key("content") {
-            scroll = &parsed["content"];
+        if let Some(content) = parsed.get("content") {
+            scroll = content.clone();
         }
     } else if request.starts_with("GET /api/v2/delete") {
         command = "delete".to_string();
@@ -1266,19 +1730,31 @@ fn handle_multi_tenant_connection_with_reload(
         command = "toc".to_string();
     } else if request.starts_with("GET /api/v2/get") {
         command = "get".to_string();
-    } else if request.starts_with("GET /api/v2/delta") {
-        command = "delta".to_string();
-    } else if request.starts_with("POST /api/v2/delta") {
-        command = "delta".to_string();
-        if parsed.contains_key("content") {
-            scroll = &parsed["content"];
+    } else if request.starts_with("GET /api/v2/delta") || request.starts_with("POST /api/v2/delta") ||
+              request.starts_with("POST /api/v2/merkle") {
+        command = if request.starts_with("POST /api/v2/merkle") { "merkle" } else { "delta" }.to_string();
+        if request.starts_with("POST ") {
+            if let Some(content) = parsed.get("content") {
+                scroll = content.clone();
+            }
         }
+        scroll = match delta_query(&parsed, None, &scroll) {
+            Ok(query) => query,
+            Err(message) => {
+                send_response(stream, 400, &message, keep_alive);
+                return;
+            }
+        };
     } else if request.starts_with("GET /api/v2/version") {
         command = "version".to_string();
+    } else if request.starts_with("POST /api/v2/json-import") {
+        command = "json-import".to_string();
+        let mode = parsed.get("mode").map(|m| m.as_str()).unwrap_or("merge");
+        scroll = format!("mode={}\n{}", mode, parsed.get("content").unwrap_or(&nothing));
     } else if request.starts_with("GET /api/v2/json-export") {
         command = "json-export".to_string();
     } else {
-        send_response(&mut stream, 404, "Not Found");
+        send_response(stream, 404, "Not Found", keep_alive);
         return;
     }
     
@@ -1291,42 +1767,42 @@ fn handle_multi_tenant_connection_with_reload(
     let state = {
         let mut states = tenant_states.lock().unwrap();
         states.entry(phext_path.clone()).or_insert_with(|| {
-            Arc::new(Mutex::new(ServerState {
-                loaded_phext: String::new(),
-                loaded_map: Default::default(),
-            }))
+            Arc::new(RwLock::new(ServerState::new()))
         }).clone()
     };
     
-    // Process under per-tenant lock (serializes access to this phext)
-    let output = {
-        let mut state = state.lock().unwrap();
+    // Reads share the per-tenant read lock; mutations and reloads serialize on the write lock
+    let reload_needed = command == "load" || command == "json-export";
+    let coordinate = phext::to_coordinate(coord.as_str());
+    let query = sq::Request {
+        connection_id: 0, source: phext_path.clone(), command: command.clone(), coordinate,
+        update: scroll, filename: phext_path.clone(), algorithm, limit,
+    };
+    let shared = if is_read_only(&command) && !reload_needed {
+        shared_query(&state, &query, None)
+    } else {
+        None
+    };
+    let output = if let Some(output) = shared { output } else {
+        let mut state = state.write().unwrap_or_else(|poisoned| poisoned.into_inner());
         
         // Reload from disk if phext changed or first access
-        let reload_needed = command == "load" || command == "json-export";
         if reload_needed || state.loaded_phext != phext_path {
-            state.loaded_map = fetch_source(phext_path.clone());
-            state.loaded_phext = phext_path.clone();
+            state.load(&phext_path, durability);
         }
         
+        let before = if is_bulk_mutation(&command) { Some(state.checksums()) } else { None };
         let mut output = String::new();
-        let _ = sq::process(
-            0,
-            phext_path.clone(),
-            &mut output,
-            command.clone(),
-            &mut state.loaded_map,
-            phext::to_coordinate(coord.as_str()),
-            scroll.clone(),
-            phext_path.clone(),
-            algorithm,
-            limit
-        );
+        let _ = sq::process(&query, &mut output, &mut state.loaded_map);
         
-        // Flush to disk on mutation
+        // Log mutations to the write-ahead log
         if is_mutation(&command) {
-            let phext_buffer = sq::implode_ref(&state.loaded_map);
-            if let Err(e) = std::fs::write(&phext_path, &phext_buffer) {
+            if let Err(e) = state.persist(coordinate) {
+                eprintln!("Failed to write {}: {}", phext_path, e);
+            }
+        }
+        if let Some(before) = before {
+            if let Err(e) = state.persist_changes(before) {
                 eprintln!("Failed to write {}: {}", phext_path, e);
             }
         }
@@ -1335,7 +1811,7 @@ fn handle_multi_tenant_connection_with_reload(
     };
     
     // Send response
-    send_response(&mut stream, 200, &output);
+    send_response(stream, 200, &output, keep_alive);
 }
 
 // -----------------------------------------------------------------------------------------------------------
@@ -1352,7 +1828,7 @@ fn extract_auth_token_multi<'a>(header: &str, config: &'a config::ServerConfig)
                 let token = value.trim();
                 // Strip "Bearer " prefix if present
                 let token = if token.to_lowercase().starts_with("bearer ") {
-                    &token[7..].trim()
+                    token[7..].trim()
                 } else {
                     token
                 };
@@ -1421,7 +1897,7 @@ fn xor_phoken_hash(text: &str, limit: usize) -> phext::Coordinate
       }
    }
 
-   return composite;
+   composite
 }
 
 // -----------------------------------------------------------------------------------------------------------
@@ -0,0 +1,226 @@
+//------------------------------------------------------------------------------------------------------------
+// file: merkle.rs
+// purpose: hierarchical checksum tree over a phext's nine coordinate levels, for cheap mesh deltas
+//
+// The root covers the whole phext, its children are libraries, theirs shelves, and so on down to scrolls;
+// a node at depth d is identified by the first d components of its sort key (its prefix). A scroll's hash is
+// its own checksum; every other node's hash is the wrapping sum of one term per child, the xxh3-128 checksum
+// (phext::checksum) of the child's "<component>:<hash>:<count>" line. Two phexts agree on a subtree exactly
+// when its hashes match, and changing a scroll only updates the nine nodes above it: each parent swaps the
+// changed child's old term for its new one without rehashing the siblings.
+//
+// Protocol (POST /api/v2/merkle, see sq.rs): the caller sends prefixes as coordinate patterns, one per line
+// (e.g. "5.1.*/*.*.*/*.*.*"; "*/*/*" is the root), and gets back each one's children:
+//   <child pattern>: <hash> <scroll count>
+// A request without prefixes gets the root's own line ("*.*.*/*.*.*/*.*.*: <hash> <count>") instead.
+// Starting at the root, the caller descends only into children whose hashes differ from its own, and
+// fetches a subtree through the regular delta once it is small (replication.rs).
+//------------------------------------------------------------------------------------------------------------
+
+use crate::phext;
+use crate::sq::CoordinatePattern;
+use crate::store::SortKey;
+use std::collections::BTreeMap;
+
+pub const DEPTH: usize = 9;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Summary {
+    pub hash: u128,
+    pub count: usize,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Node {
+    summary: Summary,
+    children: BTreeMap<usize, Node>, // empty for scrolls
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MerkleTree {
+    root: Node,
+}
+
+fn parse_hash(hex: &str) -> u128 {
+    u128::from_str_radix(hex, 16).unwrap_or(0)
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// term: a child's share of its parent's hash (nothing for a child that holds no scrolls)
+// -----------------------------------------------------------------------------------------------------------
+fn term(component: usize, summary: &Summary) -> u128 {
+    if summary.count == 0 {
+        return 0;
+    }
+    parse_hash(&phext::checksum(&format!("{}:{:032x}:{}", component, summary.hash, summary.count)))
+}
+
+impl Node {
+    fn build(leaves: &[(SortKey, u128)], depth: usize) -> Node {
+        if depth == DEPTH {
+            return Node { summary: Summary { hash: leaves[0].1, count: 1 }, children: BTreeMap::new() };
+        }
+        let mut node = Node::default();
+        let mut start = 0;
+        while start < leaves.len() {
+            let component = leaves[start].0[depth];
+            let end = start + leaves[start..].iter().take_while(|(key, _)| key[depth] == component).count();
+            let child = Node::build(&leaves[start..end], depth + 1);
+            node.summary.hash = node.summary.hash.wrapping_add(term(component, &child.summary));
+            node.summary.count += child.summary.count;
+            node.children.insert(component, child);
+            start = end;
+        }
+        node
+    }
+
+    // sets (Some) or removes (None) the scroll at `key` below this node, fixing up the hashes on the way back
+    fn update(&mut self, key: &SortKey, depth: usize, hash: Option<u128>) {
+        if depth == DEPTH {
+            self.summary = hash.map(|hash| Summary { hash, count: 1 }).unwrap_or_default();
+            return;
+        }
+        let component = key[depth];
+        if hash.is_none() && !self.children.contains_key(&component) {
+            return;
+        }
+        let child = self.children.entry(component).or_default();
+        let before = child.summary;
+        child.update(key, depth + 1, hash);
+        let after = child.summary;
+        if after.count == 0 {
+            self.children.remove(&component);
+        }
+        self.summary.hash = self.summary.hash.wrapping_sub(term(component, &before)).wrapping_add(term(component, &after));
+        self.summary.count = self.summary.count + after.count - before.count;
+    }
+}
+
+impl MerkleTree {
+    // -------------------------------------------------------------------------------------------------------
+    // build: the tree over (sort key, scroll checksum) pairs, in any order
+    // -------------------------------------------------------------------------------------------------------
+    pub fn build<'a, I: IntoIterator<Item = (SortKey, &'a str)>>(leaves: I) -> MerkleTree {
+        let mut leaves: Vec<(SortKey, u128)> = leaves.into_iter().map(|(key, sum)| (key, parse_hash(sum))).collect();
+        leaves.sort_by_key(|(key, _)| *key);
+        leaves.dedup_by_key(|(key, _)| *key);
+        MerkleTree { root: Node::build(&leaves, 0) }
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // update: sets the checksum of the scroll at `key`, or removes it (None)
+    // -------------------------------------------------------------------------------------------------------
+    pub fn update(&mut self, key: &SortKey, checksum: Option<&str>) {
+        self.root.update(key, 0, checksum.map(parse_hash));
+    }
+
+    pub fn root(&self) -> Option<Summary> {
+        Some(self.root.summary).filter(|s| s.count > 0)
+    }
+
+    fn node(&self, prefix: &[usize]) -> Option<&Node> {
+        prefix.iter().try_fold(&self.root, |node, component| node.children.get(component))
+    }
+
+    pub fn child(&self, prefix: &[usize], component: usize) -> Option<Summary> {
+        self.node(prefix).and_then(|node| node.children.get(&component)).map(|child| child.summary)
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // answer: the response to one round of the protocol - the children of every requested prefix
+    // -------------------------------------------------------------------------------------------------------
+    pub fn answer<'a, I: IntoIterator<Item = &'a str>>(&self, requests: I) -> String {
+        let mut result = String::new();
+        for request in requests {
+            let prefix = match CoordinatePattern::parse(request).and_then(|p| p.as_prefix()) {
+                Some(prefix) if prefix.len() < DEPTH => prefix,
+                _ => continue,
+            };
+            let children = self.node(&prefix).map(|node| node.children.iter()).into_iter().flatten();
+            for (component, child) in children {
+                let mut pattern = prefix.clone();
+                pattern.push(*component);
+                result.push_str(&format!("{}: {:032x} {}\n", CoordinatePattern::prefix(&pattern), child.summary.hash, child.summary.count));
+            }
+        }
+        result
+    }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// parse_answer: reads a round's response back into (child prefix, summary) pairs
+// -----------------------------------------------------------------------------------------------------------
+pub fn parse_answer(text: &str) -> Vec<(Vec<usize>, Summary)> {
+    text.lines().filter_map(|line| {
+        let (pattern, rest) = line.split_once(": ")?;
+        let prefix = CoordinatePattern::parse(pattern)?.as_prefix()?;
+        let (hash, count) = rest.split_once(' ')?;
+        if prefix.is_empty() {
+            return None;
+        }
+        Some((prefix, Summary { hash: parse_hash(hash), count: count.trim().parse().ok()? }))
+    }).collect()
+}
+
+#[cfg(test)]
+mod merkle_tests {
+    use super::*;
+    use crate::store::ScrollStore;
+
+    fn store(scrolls: &[(&str, &str)]) -> ScrollStore {
+        let mut store = ScrollStore::new();
+        for (coordinate, scroll) in scrolls {
+            store.insert(phext::to_coordinate(coordinate), scroll.to_string());
+        }
+        store
+    }
+
+    #[test]
+    fn test_tree_localizes_changes() {
+        let ours = store(&[("1.1.1/1.1.1/1.1.1", "a"), ("5.1.1/1.1.1/1.1.1", "b"), ("5.2.1/1.1.1/1.1.3", "c")]);
+        let theirs = store(&[("1.1.1/1.1.1/1.1.1", "a"), ("5.1.1/1.1.1/1.1.1", "b"), ("5.2.1/1.1.1/1.1.3", "changed")]);
+        let (ours, theirs) = (ours.merkle(), theirs.merkle());
+        assert_eq!(ours.root().unwrap().count, 3);
+        assert_ne!(ours.root(), theirs.root());
+        assert_eq!(ours.child(&[], 1), theirs.child(&[], 1));
+        assert_ne!(ours.child(&[], 5), theirs.child(&[], 5));
+        assert_eq!(ours.child(&[5], 1), theirs.child(&[5], 1));
+        assert_ne!(ours.child(&[5], 2), theirs.child(&[5], 2));
+        assert_eq!(ours.child(&[5], 2).unwrap().count, 1);
+    }
+
+    #[test]
+    fn test_answer_round_trip() {
+        let tree = store(&[("1.1.1/1.1.1/1.1.1", "a"), ("5.1.1/1.1.1/1.1.1", "b"), ("5.1.1/1.1.1/1.1.2", "c")]).merkle();
+        let answer = parse_answer(&tree.answer(["*/*/*", "5.1.1/1.1.1/1.1.*", "garbage"]));
+        let prefixes: Vec<&Vec<usize>> = answer.iter().map(|(p, _)| p).collect();
+        assert_eq!(prefixes, vec![&vec![1], &vec![5], &vec![5, 1, 1, 1, 1, 1, 1, 1, 1], &vec![5, 1, 1, 1, 1, 1, 1, 1, 2]]);
+        assert_eq!(answer[1].1, tree.child(&[], 5).unwrap());
+        assert_eq!(answer[1].1.count, 2);
+        assert!(MerkleTree::build(Vec::new()).root().is_none());
+    }
+
+    // writes one scroll to both the store and a tree kept current by hand, which must match a fresh build
+    fn apply(scrolls: &mut ScrollStore, tree: &mut MerkleTree, coordinate: &str, scroll: &str) {
+        let c = phext::to_coordinate(coordinate);
+        scrolls.insert(c, scroll.to_string());
+        tree.update(&crate::sq::coord_sort_key(&c), scrolls.scroll_checksum(&c));
+        let rebuilt = MerkleTree::build(scrolls.iter().map(|(c, _)| (crate::sq::coord_sort_key(c), scrolls.scroll_checksum(c).unwrap())));
+        assert_eq!(tree.root(), rebuilt.root());
+        assert_eq!(tree.answer(["*/*/*", "5.*.*/*.*.*/*.*.*"]), rebuilt.answer(["*/*/*", "5.*.*/*.*.*/*.*.*"]));
+    }
+
+    #[test]
+    fn test_updates_match_a_rebuild() {
+        let mut scrolls = store(&[("1.1.1/1.1.1/1.1.1", "a"), ("5.1.1/1.1.1/1.1.1", "b"), ("5.1.1/1.1.1/1.1.2", "c")]);
+        let mut tree = MerkleTree::build(scrolls.iter().map(|(c, _)| (crate::sq::coord_sort_key(c), scrolls.scroll_checksum(c).unwrap())));
+        apply(&mut scrolls, &mut tree, "5.1.1/1.1.1/1.1.2", "changed");
+        apply(&mut scrolls, &mut tree, "5.2.1/1.1.1/1.1.1", "new shelf");
+        apply(&mut scrolls, &mut tree, "1.1.1/1.1.1/1.1.1", "");
+        assert!(tree.child(&[], 1).is_none());
+        apply(&mut scrolls, &mut tree, "5.1.1/1.1.1/1.1.1", "");
+        apply(&mut scrolls, &mut tree, "5.1.1/1.1.1/1.1.2", "");
+        apply(&mut scrolls, &mut tree, "5.2.1/1.1.1/1.1.1", "");
+        assert!(tree.root().is_none());
+    }
+}
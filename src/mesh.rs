@@ -4,6 +4,8 @@
 // author: Cyon 🪶 (R17)
 //------------------------------------------------------------------------------------------------------------
 
+use crate::conflict::ConflictStrategy;
+use crate::sq::CoordinateFilter;
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::Path;
@@ -15,6 +17,13 @@ pub struct NodeConfig {
     pub name: String,
     pub emoji: String,
     pub coordinate: String,
+    /// Rank of this node's own edits under the priority-wins conflict strategy (lower wins)
+    #[serde(default = "lowest_priority")]
+    pub priority: u8,
+}
+
+fn lowest_priority() -> u8 {
+    u8::MAX
 }
 
 /// Inbound server configuration
@@ -34,8 +43,25 @@ pub struct PeerConfig {
     pub host: String,
     pub port: u16,
     pub auth_key: String,
+    /// Key this peer presents when it pulls from us; it identifies the peer and limits it to `include`/`exclude`
+    #[serde(default)]
+    pub inbound_key: String,
     pub coordinate: String,
     pub priority: u8,
+    /// Coordinate patterns replicated with this peer (e.g. "5.x.x/*/*"); empty means everything
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Coordinate patterns never replicated with this peer, even inside `include`
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+impl PeerConfig {
+    /// The coordinate subtrees replicated with this peer
+    pub fn scope(&self) -> Result<CoordinateFilter, String> {
+        CoordinateFilter::parse(&self.include, &self.exclude)
+            .map_err(|e| format!("Peer {} has an {}", self.id, e))
+    }
 }
 
 /// Outbound connections configuration
@@ -52,6 +78,12 @@ pub struct MeshSettings {
     pub health_check_interval_seconds: u64,
     pub retry_backoff_seconds: u64,
     pub max_retries: u32,
+    /// Phext names to replicate; empty means every phext in the local data directory
+    #[serde(default)]
+    pub phexts: Vec<String>,
+    /// How concurrent edits of the same scroll are resolved (see conflict.rs)
+    #[serde(default)]
+    pub conflict_strategy: ConflictStrategy,
 }
 
 /// Complete mesh configuration
@@ -73,6 +105,7 @@ impl Default for MeshConfig {
                 name: "Unknown Node".to_string(),
                 emoji: "❓".to_string(),
                 coordinate: "1.1.1/1.1.1/1.1.1".to_string(),
+                priority: lowest_priority(),
             },
             inbound: InboundConfig {
                 enabled: false,
@@ -89,6 +122,8 @@ impl Default for MeshConfig {
                 health_check_interval_seconds: 60,
                 retry_backoff_seconds: 30,
                 max_retries: 3,
+                phexts: Vec::new(),
+                conflict_strategy: ConflictStrategy::default(),
             },
         }
     }
@@ -115,7 +150,16 @@ pub fn load_mesh_config<P: AsRef<Path>>(path: P) -> Result<MeshConfig, String> {
     let config: MeshConfig = serde_json::from_str(&content)
         .map_err(|e| format!("Failed to parse mesh config: {}", e))?;
     
-    // Validate config
+    validate_mesh_config(&config)?;
+    Ok(config)
+}
+
+/// Check a mesh configuration against the rules every loaded mesh.json must satisfy
+///
+/// # Returns
+/// * `Ok(())` if the config is usable
+/// * `Err(String)` describing the first problem found
+pub fn validate_mesh_config(config: &MeshConfig) -> Result<(), String> {
     if config.version != "1.0" {
         return Err(format!("Unsupported mesh config version: {}", config.version));
     }
@@ -134,8 +178,22 @@ pub fn load_mesh_config<P: AsRef<Path>>(path: P) -> Result<MeshConfig, String> {
             }
         }
     }
+
+    let mut inbound_keys = std::collections::HashSet::new();
+    for peer in &config.outbound.peers {
+        peer.scope()?;
+        if peer.inbound_key.is_empty() {
+            continue;
+        }
+        if peer.inbound_key == config.inbound.auth_key {
+            return Err(format!("Peer {} has our own inbound auth_key as its inbound_key", peer.id));
+        }
+        if !inbound_keys.insert(peer.inbound_key.as_str()) {
+            return Err(format!("Peer {} shares its inbound_key with another peer", peer.id));
+        }
+    }
     
-    Ok(config)
+    Ok(())
 }
 
 /// Save mesh configuration to file
@@ -159,12 +217,23 @@ pub fn save_mesh_config<P: AsRef<Path>>(config: &MeshConfig, path: P) -> Result<
     let json = serde_json::to_string_pretty(config)
         .map_err(|e| format!("Failed to serialize config: {}", e))?;
     
-    fs::write(path, json)
+    crate::persist::write_atomic(path, json)
         .map_err(|e| format!("Failed to write mesh config: {}", e))?;
     
     Ok(())
 }
 
+/// Generate a new auth key: 32 bytes from the OS random number generator, hex-encoded
+///
+/// # Returns
+/// * `Ok(String)` like `pmb-v1-<64 hex digits>`
+/// * `Err(String)` if the OS has no randomness to give
+pub fn generate_key() -> Result<String, String> {
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes).map_err(|e| format!("Failed to generate a key: {}", e))?;
+    Ok(format!("pmb-v1-{}", bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>()))
+}
+
 /// Generate default mesh config for a node
 ///
 /// # Arguments
@@ -174,20 +243,22 @@ pub fn save_mesh_config<P: AsRef<Path>>(config: &MeshConfig, path: P) -> Result<
 /// * `coordinate` - Phext coordinate (e.g., "2.7.1/8.2.8/3.1.4")
 ///
 /// # Returns
-/// * `MeshConfig` with sensible defaults for the node
-pub fn generate_default_config(id: &str, name: &str, emoji: &str, coordinate: &str) -> MeshConfig {
-    MeshConfig {
+/// * `Ok(MeshConfig)` with sensible defaults for the node and a freshly generated inbound auth key
+/// * `Err(String)` if no key could be generated
+pub fn generate_default_config(id: &str, name: &str, emoji: &str, coordinate: &str) -> Result<MeshConfig, String> {
+    Ok(MeshConfig {
         version: "1.0".to_string(),
         node: NodeConfig {
             id: id.to_string(),
             name: name.to_string(),
             emoji: emoji.to_string(),
             coordinate: coordinate.to_string(),
+            priority: lowest_priority(),
         },
         inbound: InboundConfig {
             enabled: true,
             port: 2086,
-            auth_key: format!("pmb-v1-{}-2026", id),
+            auth_key: generate_key()?,
             data_dir: "/var/sq/data".to_string(),
         },
         outbound: OutboundConfig {
@@ -199,8 +270,10 @@ pub fn generate_default_config(id: &str, name: &str, emoji: &str, coordinate: &s
             health_check_interval_seconds: 60,
             retry_backoff_seconds: 30,
             max_retries: 3,
+            phexts: Vec::new(),
+            conflict_strategy: ConflictStrategy::default(),
         },
-    }
+    })
 }
 
 /// Print mesh configuration summary to stdout
@@ -229,7 +302,11 @@ pub fn print_config_summary(config: &MeshConfig) {
         println!("  Peers:      {} configured", config.outbound.peers.len());
         for peer in &config.outbound.peers {
             let status = if peer.auth_key.is_empty() { "❌" } else { "✅" };
-            println!("    {} {} ({}) @ {}:{}", status, peer.name, peer.id, peer.host, peer.port);
+            let pulls = if peer.inbound_key.is_empty() { "" } else { ", pulls from us" };
+            println!("    {} {} ({}) @ {}:{}{}", status, peer.name, peer.id, peer.host, peer.port, pulls);
+            if !peer.include.is_empty() || !peer.exclude.is_empty() {
+                println!("       include [{}] exclude [{}]", peer.include.join(", "), peer.exclude.join(", "));
+            }
         }
     } else {
         println!("Outbound:     ⏸️  Disabled");
@@ -241,9 +318,206 @@ pub fn print_config_summary(config: &MeshConfig) {
     println!("  Health Check:     {} seconds", config.mesh.health_check_interval_seconds);
     println!("  Retry Backoff:    {} seconds", config.mesh.retry_backoff_seconds);
     println!("  Max Retries:      {}", config.mesh.max_retries);
+    println!("  Conflicts:        {}", config.mesh.conflict_strategy.name());
     println!();
 }
 
+/// Format a unix timestamp relative to `now` (e.g. "12s ago")
+fn ago(timestamp: Option<u64>, now: u64) -> String {
+    match timestamp {
+        Some(t) => format!("{}s ago", now.saturating_sub(t)),
+        None => "never".to_string(),
+    }
+}
+
+/// Render a mesh status report for the terminal
+pub fn format_status(status: &crate::health::MeshStatus, now: u64) -> String {
+    if !status.enabled {
+        return "Mesh mode is not enabled on this node (start it with sq host <port> --mesh-config <path>)\n".to_string();
+    }
+    let mut out = format!("Mesh status for {} ({}) - health checks every {} seconds\n\n",
+        status.node_name, status.node_id, status.health_check_interval_seconds);
+    if status.peers.is_empty() {
+        out.push_str("  No outbound peers configured\n");
+    }
+    for peer in &status.peers {
+        let mark = if peer.last_check.is_none() { "⏳" } else if peer.healthy { "✅" } else { "❌" };
+        out.push_str(&format!("  {} {} ({}) @ {}:{}  priority {}\n", mark, peer.name, peer.id, peer.host, peer.port, peer.priority));
+        let latency = peer.latency_ms.map(|ms| format!("{} ms", ms)).unwrap_or_else(|| "-".to_string());
+        out.push_str(&format!("       latency {}, last success {}, {} consecutive failures\n",
+            latency, ago(peer.last_success, now), peer.consecutive_failures));
+        if let Some(ref error) = peer.last_error {
+            out.push_str(&format!("       last error: {}\n", error));
+        }
+        match peer.last_sync_error {
+            Some(ref error) => out.push_str(&format!("       last sync {}: failed ({})\n", ago(peer.last_sync, now), error)),
+            None => out.push_str(&format!("       last sync {}: {} scrolls pulled\n", ago(peer.last_sync, now), peer.last_sync_scrolls)),
+        }
+        if peer.conflicts > 0 {
+            out.push_str(&format!("       {} conflicting edits resolved (see /api/v2/mesh/conflicts)\n", peer.conflicts));
+        }
+    }
+    out
+}
+
+/// Fetch and print the live status of a running mesh node
+///
+/// # Arguments
+/// * `config_path` - the node's mesh.json (supplies the inbound port and auth key)
+/// * `port` - overrides the inbound port (when the node was started as `sq host <port>`)
+fn print_live_status(config_path: &str, port: Option<u16>) -> Result<(), Box<dyn std::error::Error>> {
+    let config = load_mesh_config(config_path)?;
+    let port = port.unwrap_or(config.inbound.port);
+    let authorization = format!("Bearer {}", config.inbound.auth_key);
+    let (code, body) = crate::http::request("127.0.0.1", port, "GET", "/api/v2/mesh/status",
+        &[("Authorization", authorization.as_str())], b"", std::time::Duration::from_secs(5))
+        .map_err(|e| format!("Unable to reach sq on port {}: {}", port, e))?;
+    if code != 200 {
+        return Err(format!("sq on port {} answered HTTP {}", port, code).into());
+    }
+    let status: crate::health::MeshStatus = serde_json::from_slice(&body)?;
+    print!("{}", format_status(&status, crate::health::unix_now()));
+    Ok(())
+}
+
+/// Validate a mesh configuration and write it to `path`
+fn write_mesh_config(config: &MeshConfig, path: &str) -> Result<(), String> {
+    validate_mesh_config(config)?;
+    save_mesh_config(config, path)
+}
+
+/// Add `peer` to the outbound peers (enabling outbound sync), replacing any peer with the same id
+///
+/// # Returns
+/// * `true` if an existing peer was replaced
+fn add_peer(config: &mut MeshConfig, peer: PeerConfig) -> bool {
+    config.outbound.enabled = true;
+    match config.outbound.peers.iter_mut().find(|p| p.id == peer.id) {
+        Some(existing) => {
+            *existing = peer;
+            true
+        }
+        None => {
+            config.outbound.peers.push(peer);
+            false
+        }
+    }
+}
+
+/// Remove the outbound peer `id`
+fn remove_peer(config: &mut MeshConfig, id: &str) -> Result<PeerConfig, String> {
+    match config.outbound.peers.iter().position(|p| p.id == id) {
+        Some(index) => Ok(config.outbound.peers.remove(index)),
+        None => Err(format!("No peer {} in the mesh config", id)),
+    }
+}
+
+/// Split `sq mesh` arguments into positionals and `--flag value` pairs
+fn split_args(args: &[String]) -> (Vec<String>, std::collections::HashMap<String, String>) {
+    let mut positional = Vec::new();
+    let mut flags = std::collections::HashMap::new();
+    let mut i = 0;
+    while i < args.len() {
+        if let Some(flag) = args[i].strip_prefix("--") {
+            flags.insert(flag.to_string(), args.get(i + 1).cloned().unwrap_or_default());
+            i += 2;
+        } else {
+            positional.push(args[i].clone());
+            i += 1;
+        }
+    }
+    (positional, flags)
+}
+
+/// Comma-separated coordinate patterns from a flag (e.g. --include 5.x.x/*/*,6.x.x/*/*)
+fn pattern_list(value: Option<&String>) -> Vec<String> {
+    value.map(|v| v.split(',').map(|p| p.trim().to_string()).filter(|p| !p.is_empty()).collect())
+        .unwrap_or_default()
+}
+
+const MESH_USAGE: &str = "Usage:
+  sq mesh init <id> <name> <emoji> <coordinate> [--config mesh.json]
+  sq mesh add-peer <id> <host> <port> <auth_key> [--name <name>] [--priority <n>] [--coordinate <coord>]
+                   [--include <patterns>] [--exclude <patterns>] [--inbound-key <key>] [--config mesh.json]
+  sq mesh remove-peer <id> [--config mesh.json]
+  sq mesh show [--config mesh.json]
+  sq mesh status [--config mesh.json] [--port <port>]";
+
+/// Entry point for `sq mesh <subcommand>`
+///
+/// # Arguments
+/// * `args` - everything after `sq mesh`
+pub fn run_mesh_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let subcommand = args.first().map(|s| s.as_str()).unwrap_or("");
+    let (positional, flags) = split_args(args.get(1..).unwrap_or_default());
+    let config_path = flags.get("config").cloned().unwrap_or_else(|| "mesh.json".to_string());
+    match (subcommand, positional.as_slice()) {
+        ("init", [id, name, emoji, coordinate]) => {
+            if Path::new(&config_path).exists() {
+                return Err(format!("{} already exists - edit it with add-peer/remove-peer", config_path).into());
+            }
+            let config = generate_default_config(id, name, emoji, coordinate)?;
+            write_mesh_config(&config, &config_path)?;
+            println!("Created {}", config_path);
+            print_config_summary(&config);
+        }
+        ("add-peer", [id, host, port, auth_key]) => {
+            let mut config = load_mesh_config(&config_path)?;
+            let port: u16 = port.parse().map_err(|_| format!("Invalid port: {}", port))?;
+            let priority: u8 = match flags.get("priority") {
+                Some(p) => p.parse().map_err(|_| format!("Invalid priority: {}", p))?,
+                None => 1,
+            };
+            let generated = !flags.contains_key("inbound-key");
+            let inbound_key = match flags.get("inbound-key") {
+                Some(key) => key.clone(),
+                None => generate_key()?,
+            };
+            let peer = PeerConfig {
+                id: id.clone(),
+                name: flags.get("name").cloned().unwrap_or_else(|| id.clone()),
+                host: host.clone(),
+                port,
+                auth_key: auth_key.clone(),
+                inbound_key: inbound_key.clone(),
+                coordinate: flags.get("coordinate").cloned().unwrap_or_else(|| "1.1.1/1.1.1/1.1.1".to_string()),
+                priority,
+                include: pattern_list(flags.get("include")),
+                exclude: pattern_list(flags.get("exclude")),
+            };
+            let replaced = add_peer(&mut config, peer);
+            write_mesh_config(&config, &config_path)?;
+            println!("{} peer {} in {}", if replaced { "Updated" } else { "Added" }, id, config_path);
+            if generated {
+                println!("{} pulls from us with the key {}", id, inbound_key);
+            }
+        }
+        ("remove-peer", [id]) => {
+            let mut config = load_mesh_config(&config_path)?;
+            remove_peer(&mut config, id)?;
+            write_mesh_config(&config, &config_path)?;
+            println!("Removed peer {} from {}", id, config_path);
+        }
+        ("show", []) => {
+            print_config_summary(&load_mesh_config(&config_path)?);
+        }
+        ("status", legacy) if legacy.len() <= 2 => {
+            // the older positional form, `status [mesh.json] [port]`, still works
+            let config_path = flags.get("config").or(legacy.first()).cloned().unwrap_or(config_path);
+            let port: Option<u16> = match flags.get("port").or(legacy.get(1)) {
+                Some(p) => Some(p.parse().map_err(|_| format!("Invalid port: {}", p))?),
+                None => None,
+            };
+            print_live_status(&config_path, port)?;
+        }
+        _ => {
+            eprintln!("{}", MESH_USAGE);
+            std::process::exit(1);
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -256,7 +530,7 @@ mod tests {
             "Cyon",
             "🪶",
             "2.7.1/8.2.8/3.1.4"
-        );
+        ).unwrap();
         
         assert_eq!(config.node.id, "halycon-vector");
         assert_eq!(config.node.name, "Cyon");
@@ -265,6 +539,10 @@ mod tests {
         assert!(config.inbound.enabled);
         assert_eq!(config.inbound.port, 2086);
         assert!(config.inbound.auth_key.starts_with("pmb-v1-"));
+        assert_eq!(config.inbound.auth_key.len(), "pmb-v1-".len() + 64);
+        assert!(!config.inbound.auth_key.contains("halycon-vector"));
+        let again = generate_default_config("halycon-vector", "Cyon", "🪶", "2.7.1/8.2.8/3.1.4").unwrap();
+        assert_ne!(config.inbound.auth_key, again.inbound.auth_key);
     }
     
     #[test]
@@ -274,7 +552,7 @@ mod tests {
             "Test",
             "🧪",
             "1.1.1/1.1.1/1.1.1"
-        );
+        ).unwrap();
         
         let temp_path = "/tmp/sq-mesh-test.json";
         
@@ -293,6 +571,93 @@ mod tests {
         let _ = fs::remove_file(temp_path);
     }
     
+    #[test]
+    fn test_add_and_remove_peer() {
+        let mut config = generate_default_config("test-node", "Test", "🧪", "1.1.1/1.1.1/1.1.1").unwrap();
+        config.outbound.enabled = false;
+        let (positional, flags) = split_args(&["work".to_string(), "--include".to_string(), "5.x.x/*/*, 6.x.x/*/*".to_string()]);
+        assert_eq!(positional, vec!["work"]);
+        let peer = PeerConfig {
+            id: "work".to_string(),
+            name: "Work".to_string(),
+            host: "10.0.0.5".to_string(),
+            port: 2086,
+            auth_key: "pmb-v1-work".to_string(),
+            inbound_key: String::new(),
+            coordinate: "5.1.1/1.1.1/1.1.1".to_string(),
+            priority: 2,
+            include: pattern_list(flags.get("include")),
+            exclude: Vec::new(),
+        };
+        assert_eq!(peer.include, vec!["5.x.x/*/*", "6.x.x/*/*"]);
+
+        assert!(!add_peer(&mut config, peer.clone()));
+        assert!(config.outbound.enabled);
+        assert!(add_peer(&mut config, PeerConfig { port: 2087, ..peer.clone() }));
+        assert_eq!(config.outbound.peers.len(), 1);
+        assert_eq!(config.outbound.peers[0].port, 2087);
+
+        let temp_path = "/tmp/sq-mesh-cli-test.json";
+        let mut invalid = config.clone();
+        invalid.outbound.peers[0].include = vec!["nope".to_string()];
+        assert!(write_mesh_config(&invalid, temp_path).is_err());
+        assert!(!Path::new(temp_path).exists());
+
+        // inbound keys identify peers, so they can't be shared or reuse our own key
+        let mut shared = config.clone();
+        shared.outbound.peers[0].inbound_key = shared.inbound.auth_key.clone();
+        assert!(validate_mesh_config(&shared).unwrap_err().contains("inbound_key"));
+        shared.outbound.peers[0].inbound_key = "pmb-v1-pull".to_string();
+        let twin = PeerConfig { id: "home".to_string(), ..shared.outbound.peers[0].clone() };
+        add_peer(&mut shared, twin);
+        assert!(validate_mesh_config(&shared).unwrap_err().contains("shares its inbound_key"));
+        write_mesh_config(&config, temp_path).unwrap();
+        assert_eq!(load_mesh_config(temp_path).unwrap().outbound.peers[0].include.len(), 2);
+        let _ = fs::remove_file(temp_path);
+
+        assert_eq!(remove_peer(&mut config, "work").unwrap().id, "work");
+        assert!(remove_peer(&mut config, "work").is_err());
+    }
+
+    #[test]
+    fn test_mesh_commands() {
+        let dir = crate::tests::TempDir::new("mesh-commands");
+        let path = dir.path("mesh.json");
+        let run = |args: &[&str]| {
+            let mut args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+            args.extend(["--config".to_string(), path.clone()]);
+            run_mesh_command(&args).map_err(|e| e.to_string())
+        };
+
+        run(&["init", "home", "Home", "🏠", "2.1.1/1.1.1/1.1.1"]).unwrap();
+        let created = load_mesh_config(&path).unwrap();
+        assert_eq!(created.node.id, "home");
+        assert!(created.outbound.peers.is_empty());
+
+        // init never overwrites an existing config
+        assert!(run(&["init", "other", "Other", "🧪", "1.1.1/1.1.1/1.1.1"]).unwrap_err().contains("already exists"));
+        assert_eq!(load_mesh_config(&path).unwrap().inbound.auth_key, created.inbound.auth_key);
+
+        run(&["add-peer", "work", "10.0.0.5", "2086", "pmb-v1-work", "--priority", "2", "--include", "5.x.x/*/*"]).unwrap();
+        let peer = load_mesh_config(&path).unwrap().outbound.peers[0].clone();
+        assert_eq!((peer.id.as_str(), peer.host.as_str(), peer.port, peer.priority), ("work", "10.0.0.5", 2086, 2));
+        assert_eq!(peer.include, vec!["5.x.x/*/*"]);
+        assert!(peer.inbound_key.starts_with("pmb-v1-") && peer.inbound_key != created.inbound.auth_key);
+        assert!(run(&["add-peer", "bad", "10.0.0.6", "port", "pmb-v1-bad"]).unwrap_err().contains("Invalid port"));
+        assert!(run(&["add-peer", "bad", "10.0.0.6", "2086", "pmb-v1-bad", "--include", "nope"]).is_err());
+        assert_eq!(load_mesh_config(&path).unwrap().outbound.peers.len(), 1);
+
+        assert!(run(&["remove-peer", "nobody"]).unwrap_err().contains("No peer nobody"));
+        run(&["remove-peer", "work"]).unwrap();
+        assert!(load_mesh_config(&path).unwrap().outbound.peers.is_empty());
+
+        // status takes the same --config flag, and --port overrides the configured port
+        assert!(run(&["status", "--port", "1"]).unwrap_err().contains("on port 1"));
+        assert!(run(&["status", "--port", "nope"]).unwrap_err().contains("Invalid port: nope"));
+        let positional = ["status".to_string(), path.clone(), "1".to_string()];
+        assert!(run_mesh_command(&positional).unwrap_err().to_string().contains("on port 1"));
+    }
+
     #[test]
     fn test_load_missing_config() {
         let result = load_mesh_config("/tmp/nonexistent-mesh-config.json");
@@ -300,6 +665,35 @@ mod tests {
         assert!(result.unwrap_err().contains("not found"));
     }
     
+    #[test]
+    fn test_format_status() {
+        let status = crate::health::MeshStatus {
+            enabled: true,
+            node_id: "halycon-vector".to_string(),
+            node_name: "Cyon".to_string(),
+            health_check_interval_seconds: 60,
+            peers: vec![crate::health::PeerHealth {
+                id: "peer-1".to_string(),
+                name: "Peer".to_string(),
+                host: "10.0.0.2".to_string(),
+                port: 2086,
+                priority: 1,
+                last_check: Some(990),
+                consecutive_failures: 3,
+                last_error: Some("Connection refused".to_string()),
+                conflicts: 2,
+                ..Default::default()
+            }],
+        };
+        let text = format_status(&status, 1000);
+        assert!(text.contains("Cyon (halycon-vector)"));
+        assert!(text.contains("❌ Peer (peer-1) @ 10.0.0.2:2086"));
+        assert!(text.contains("last success never, 3 consecutive failures"));
+        assert!(text.contains("last error: Connection refused"));
+        assert!(text.contains("2 conflicting edits resolved"));
+        assert!(format_status(&Default::default(), 0).contains("not enabled"));
+    }
+    
     #[test]
     fn test_validation_missing_auth_key() {
         let mut config = generate_default_config(
@@ -307,7 +701,7 @@ mod tests {
             "Test",
             "🧪",
             "1.1.1/1.1.1/1.1.1"
-        );
+        ).unwrap();
         
         config.inbound.auth_key = String::new();
         
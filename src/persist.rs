@@ -0,0 +1,149 @@
+//------------------------------------------------------------------------------------------------------------
+// file: persist.rs
+// purpose: crash-safe file replacement for every path that writes a phext (or an export of one) to disk
+//
+// write_atomic never truncates the destination in place:
+//   1. write the new contents to a sibling temp file and fsync it
+//   2. optionally hard-link the current generation to <file>.bak
+//   3. rename the temp file over the destination
+//   4. fsync the directory so the rename itself survives a crash
+// A reader (or a crash) observes either the old file or the new one, never a partial write.
+//------------------------------------------------------------------------------------------------------------
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+static KEEP_BACKUP: AtomicBool = AtomicBool::new(false);
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// -----------------------------------------------------------------------------------------------------------
+// Enables/disables keeping <file>.bak with the previous generation (sq ... --backup)
+// -----------------------------------------------------------------------------------------------------------
+pub fn set_keep_backup(enabled: bool) {
+    KEEP_BACKUP.store(enabled, Ordering::Relaxed);
+}
+
+pub fn keep_backup() -> bool {
+    KEEP_BACKUP.load(Ordering::Relaxed)
+}
+
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".bak");
+    PathBuf::from(name)
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+fn temp_path(path: &Path) -> std::io::Result<PathBuf> {
+    let name = path.file_name().ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("not a file path: {}", path.display()))
+    })?;
+    let unique = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let temp_name = format!(".{}.tmp-{}-{}", name.to_string_lossy(), std::process::id(), unique);
+    Ok(parent_dir(path).join(temp_name))
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// Flushes directory metadata (the rename) to disk; directories can't be opened for sync on Windows
+// -----------------------------------------------------------------------------------------------------------
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    fs::File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// Preserves the previous generation as <file>.bak without ever leaving `path` missing
+// -----------------------------------------------------------------------------------------------------------
+fn preserve_backup(path: &Path) -> std::io::Result<()> {
+    let backup = backup_path(path);
+    let staged = temp_path(&backup)?;
+    if fs::hard_link(path, &staged).is_err() {
+        fs::copy(path, &staged)?;
+    }
+    if let Err(e) = fs::rename(&staged, &backup) {
+        let _ = fs::remove_file(&staged);
+        return Err(e);
+    }
+    Ok(())
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// write_atomic: replaces `path` with `contents` via temp file + fsync + rename + directory fsync
+// -----------------------------------------------------------------------------------------------------------
+pub fn write_atomic<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> std::io::Result<()> {
+    let path = path.as_ref();
+    let temp = temp_path(path)?;
+
+    let staged = (|| -> std::io::Result<()> {
+        let mut file = OpenOptions::new().write(true).create_new(true).open(&temp)?;
+        file.write_all(contents.as_ref())?;
+        if let Ok(existing) = fs::metadata(path) {
+            file.set_permissions(existing.permissions())?;
+        }
+        file.sync_all()
+    })();
+    if let Err(e) = staged {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+
+    if keep_backup() && path.exists() {
+        if let Err(e) = preserve_backup(path) {
+            eprintln!("Warning: Failed to keep backup of {}: {}", path.display(), e);
+        }
+    }
+
+    if let Err(e) = fs::rename(&temp, path) {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+    sync_dir(parent_dir(path))
+}
+
+#[cfg(test)]
+mod persist_tests {
+    use super::*;
+    use crate::tests::TempDir;
+
+    #[test]
+    fn test_write_atomic_replaces_contents() {
+        let dir = TempDir::new("persist-replace");
+        let path = PathBuf::from(dir.path("world.phext"));
+        write_atomic(&path, "first").unwrap();
+        write_atomic(&path, "second").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+
+        // only the destination remains - no stray temp files
+        let entries: Vec<_> = fs::read_dir(parent_dir(&path)).unwrap().flatten().collect();
+        assert_eq!(entries.len(), 1);
+    }
+
+    #[test]
+    fn test_backup_path() {
+        assert_eq!(backup_path(Path::new("/tmp/a.phext")), PathBuf::from("/tmp/a.phext.bak"));
+    }
+
+    #[test]
+    fn test_preserve_backup_keeps_previous_generation() {
+        let dir = TempDir::new("persist-backup");
+        let path = PathBuf::from(dir.path("world.phext"));
+        write_atomic(&path, "generation 1").unwrap();
+        preserve_backup(&path).unwrap();
+        write_atomic(&path, "generation 2").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "generation 2");
+        assert_eq!(fs::read_to_string(backup_path(&path)).unwrap(), "generation 1");
+    }
+}
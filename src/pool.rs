@@ -0,0 +1,196 @@
+//------------------------------------------------------------------------------------------------------------
+// file: pool.rs
+// purpose: bounded worker pool shared by every listener (sq host, sq host --config, sq route)
+//
+// Accepted connections are queued for a fixed set of worker threads. When the queue is full the listener
+// answers 503 itself instead of spawning more threads, so memory stays bounded by
+// workers x stack size + queue depth x socket, no matter how many clients show up.
+//
+// Persistent (keep-alive) connections hold a worker between requests. To keep idle clients from starving
+// queued ones, connection loops check has_backlog(): while anything is waiting, they finish the current
+// response with Connection: close and stop waiting for the next request.
+//------------------------------------------------------------------------------------------------------------
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, OnceLock};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+static GLOBAL: OnceLock<WorkerPool> = OnceLock::new();
+
+pub struct WorkerPool {
+    sender: Mutex<mpsc::Sender<Job>>,
+    workers: usize,
+    queue_depth: usize,
+    queued: Arc<AtomicUsize>,
+    busy: Arc<AtomicUsize>,
+}
+
+impl WorkerPool {
+    pub fn new(workers: usize, queue_depth: usize) -> WorkerPool {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let queued = Arc::new(AtomicUsize::new(0));
+        let busy = Arc::new(AtomicUsize::new(0));
+
+        for id in 0..workers {
+            let receiver = Arc::clone(&receiver);
+            let queued = Arc::clone(&queued);
+            let busy = Arc::clone(&busy);
+            let spawned = std::thread::Builder::new().name(format!("sq-worker-{}", id)).spawn(move || {
+                loop {
+                    let job = {
+                        let receiver = receiver.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                        match receiver.recv() {
+                            Ok(job) => job,
+                            Err(_) => return, // pool dropped
+                        }
+                    };
+                    queued.fetch_sub(1, Ordering::SeqCst);
+                    busy.fetch_add(1, Ordering::SeqCst);
+                    // a panicking job must not take the worker down with it
+                    if std::panic::catch_unwind(std::panic::AssertUnwindSafe(job)).is_err() {
+                        eprintln!("[!] worker {} recovered from a panicking job", id);
+                    }
+                    busy.fetch_sub(1, Ordering::SeqCst);
+                }
+            });
+            if let Err(e) = spawned {
+                eprintln!("[!] Failed to spawn worker {}: {}", id, e);
+            }
+        }
+
+        WorkerPool {
+            sender: Mutex::new(sender),
+            workers,
+            queue_depth,
+            queued,
+            busy,
+        }
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // try_execute: queues job(item), or hands `item` back (e.g. the socket, to answer 503) when the queue is
+    // already at its depth limit
+    // -------------------------------------------------------------------------------------------------------
+    pub fn try_execute<T, F>(&self, item: T, job: F) -> Result<(), T>
+    where
+        T: Send + 'static,
+        F: FnOnce(T) + Send + 'static,
+    {
+        let mut current = self.queued.load(Ordering::SeqCst);
+        loop {
+            if current >= self.queue_depth {
+                return Err(item);
+            }
+            match self.queued.compare_exchange(current, current + 1, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => break,
+                Err(actual) => current = actual,
+            }
+        }
+        let sender = self.sender.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if sender.send(Box::new(move || job(item))).is_err() {
+            // every worker is gone; nothing will ever run this
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    pub fn queue_length(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+
+    pub fn busy(&self) -> usize {
+        self.busy.load(Ordering::SeqCst)
+    }
+
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    pub fn queue_depth(&self) -> usize {
+        self.queue_depth
+    }
+
+    pub fn has_backlog(&self) -> bool {
+        self.queue_length() > 0
+    }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// start: creates the process-wide pool (later calls return the existing one)
+// -----------------------------------------------------------------------------------------------------------
+pub fn start(workers: usize, queue_depth: usize) -> &'static WorkerPool {
+    GLOBAL.get_or_init(|| WorkerPool::new(workers, queue_depth))
+}
+
+pub fn global() -> Option<&'static WorkerPool> {
+    GLOBAL.get()
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// has_backlog: true when connections are waiting for a worker (false outside the network modes)
+// -----------------------------------------------------------------------------------------------------------
+pub fn has_backlog() -> bool {
+    global().map(|pool| pool.has_backlog()).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod pool_tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    #[test]
+    fn test_jobs_run_on_workers() {
+        let pool = WorkerPool::new(2, 8);
+        let (tx, rx) = channel();
+        for i in 0..4 {
+            let tx = tx.clone();
+            assert!(pool.try_execute(i, move |i| { tx.send(i).unwrap(); }).is_ok());
+        }
+        let mut results: Vec<i32> = (0..4).map(|_| rx.recv_timeout(Duration::from_secs(5)).unwrap()).collect();
+        results.sort();
+        assert_eq!(results, vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_back_pressure_when_queue_is_full() {
+        let pool = WorkerPool::new(1, 2);
+        let (release_tx, release_rx) = channel::<()>();
+        let (started_tx, started_rx) = channel::<()>();
+        assert!(pool.try_execute((), move |_| {
+            started_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+        }).is_ok());
+        started_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(pool.busy(), 1);
+
+        // the only worker is blocked: two jobs fit in the queue, the third is refused
+        assert!(pool.try_execute(1, |_| {}).is_ok());
+        assert!(pool.try_execute(2, |_| {}).is_ok());
+        assert_eq!(pool.queue_length(), 2);
+        assert!(pool.has_backlog());
+        assert_eq!(pool.try_execute(3, |_| {}), Err(3));
+
+        release_tx.send(()).unwrap();
+        for _ in 0..100 {
+            if pool.queue_length() == 0 && pool.busy() == 0 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(pool.queue_length(), 0);
+        assert_eq!(pool.busy(), 0);
+    }
+
+    #[test]
+    fn test_panicking_job_keeps_worker() {
+        let pool = WorkerPool::new(1, 4);
+        let _ = pool.try_execute((), |_| panic!("boom"));
+        let (tx, rx) = channel();
+        let _ = pool.try_execute(7, move |n| { tx.send(n).unwrap(); });
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), 7);
+    }
+}
@@ -0,0 +1,557 @@
+//------------------------------------------------------------------------------------------------------------
+// file: replication.rs
+// purpose: mesh sync engine - pulls changed scrolls from every outbound peer into the local phexts
+//
+// Each round, for every replicated phext, we first walk the peer's hierarchical checksum tree
+// (/api/v2/merkle, see merkle.rs) from the root down to the small subtrees that differ from ours. Then we
+// POST our checksums for just those subtrees to the peer's delta endpoint (/api/v2/delta) and get back a
+// phext holding the scrolls that differ, and ask for their versions (/api/v2/mesh/versions). Peers without
+// the merkle endpoint get every checksum, as before. Scrolls we don't have, or only have older versions of, are applied locally and
+// logged to the write-ahead log like any other mutation; scrolls both sides edited are settled by the
+// configured conflict strategy (conflict.rs). Scrolls the peer doesn't have are left alone; sync only ever
+// pulls.
+//
+// Scheduling:
+//   - every peer is synced once per sync_interval_seconds
+//   - a failed round is retried after retry_backoff_seconds, doubling on each consecutive failure; after
+//     max_retries retries the peer waits for its next regular interval
+//   - peers due at the same time are synced in priority order (1 first), and within one round a scroll
+//     pulled from a higher-priority peer is not overwritten by a lower-priority one
+// Every round's outcome is recorded in the mesh status (health.rs).
+//
+// A peer's include/exclude patterns limit which scrolls are exchanged with it: only those are offered in the
+// delta request, the peer is asked to answer for just those subtrees, and anything else it sends is ignored.
+// The same patterns bound what the peer can read from us: it authenticates with its own inbound_key, and
+// every read made with that key only sees the peer's subtrees (see caller_scope). Requests also carry our
+// node id (X-SQ-Node); a caller naming itself a mesh node without a peer key is shown nothing.
+//------------------------------------------------------------------------------------------------------------
+
+use crate::conflict::{self, ConflictRecord, ConflictStrategy, Resolution};
+use crate::health;
+use crate::http;
+use crate::merkle::{self, MerkleTree};
+use crate::mesh::{MeshConfig, PeerConfig};
+use crate::phext;
+use crate::resident::ResidentSet;
+use crate::sq::{CoordinateFilter, CoordinatePattern};
+use crate::versions::{self, ScrollVersion};
+use crate::wal;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+const TICK_MS: u64 = 1000;
+const PEER_TIMEOUT_SECS: u64 = 30;
+const MERKLE_BATCH: usize = 64; // differing subtrees this small are fetched whole instead of descended into
+
+// inbound_key → the subtrees that peer may read
+static PEER_SCOPES: OnceLock<HashMap<String, CoordinateFilter>> = OnceLock::new();
+
+struct PeerSchedule {
+    peer: PeerConfig,
+    next_due: Instant,
+    failures: u32,
+}
+
+// phext path → coordinates already pulled from a higher-priority peer this round
+type Claimed = HashMap<String, HashSet<phext::Coordinate>>;
+
+// -----------------------------------------------------------------------------------------------------------
+// phext_path: where the host keeps a phext (mirrors validate_tenant_path for trusted names)
+// -----------------------------------------------------------------------------------------------------------
+fn phext_path(name: &str, data_dir: &Option<String>) -> String {
+    match data_dir {
+        Some(dir) => format!("{}/{}.phext", dir, name),
+        None => format!("{}.phext", name),
+    }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// replicated_phexts: the configured list, or every phext in the data directory
+// -----------------------------------------------------------------------------------------------------------
+fn replicated_phexts(config: &MeshConfig, data_dir: &Option<String>) -> Vec<String> {
+    if !config.mesh.phexts.is_empty() {
+        return config.mesh.phexts.clone();
+    }
+    let dir = data_dir.clone().unwrap_or_else(|| ".".to_string());
+    let mut names: Vec<String> = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries.filter_map(|e| e.ok())
+            .filter_map(|e| e.file_name().to_str().and_then(|n| n.strip_suffix(".phext")).map(|n| n.to_string()))
+            .collect(),
+        Err(e) => {
+            eprintln!("Warning: Failed to list phexts in {}: {}", dir, e);
+            Vec::new()
+        }
+    };
+    names.sort();
+    names
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// delta_request: the body for /api/v2/delta - one "coord: checksum" line per local scroll
+// -----------------------------------------------------------------------------------------------------------
+fn delta_request(checksums: &HashMap<phext::Coordinate, String>) -> String {
+    let mut lines: Vec<String> = checksums.iter().map(|(c, sum)| format!("{}: {}", c, sum)).collect();
+    lines.sort();
+    lines.join("\n")
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// peer_path: a peer endpoint (delta or merkle) for one phext, limited to the subtrees replicated with `peer`
+// -----------------------------------------------------------------------------------------------------------
+fn peer_path(endpoint: &str, name: &str, peer: &PeerConfig) -> String {
+    let encode = |v: &str| percent_encoding::utf8_percent_encode(v, percent_encoding::NON_ALPHANUMERIC).to_string();
+    let mut path = format!("/api/v2/{}?p={}", endpoint, encode(name));
+    if !peer.include.is_empty() {
+        path.push_str(&format!("&include={}", encode(&peer.include.join(","))));
+    }
+    if !peer.exclude.is_empty() {
+        path.push_str(&format!("&exclude={}", encode(&peer.exclude.join(","))));
+    }
+    path
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// merkle_diff: walks the peer's checksum tree (merkle.rs) down to the subtrees that differ from `local`
+//   returns the differing subtrees of at most MERKLE_BATCH scrolls (or single scrolls), or None when the
+//   peer predates the merkle endpoint
+// -----------------------------------------------------------------------------------------------------------
+fn merkle_diff(config: &MeshConfig, peer: &PeerConfig, name: &str, local: &MerkleTree) -> Result<Option<Vec<Vec<usize>>>, String> {
+    let authorization = format!("Bearer {}", peer.auth_key);
+    let mut frontier: Vec<Vec<usize>> = vec![Vec::new()];
+    let mut diverged = Vec::new();
+    while !frontier.is_empty() {
+        let request: Vec<String> = frontier.iter().map(|p| CoordinatePattern::prefix(p).to_string()).collect();
+        let (status, body) = http::request(
+            &peer.host, peer.port, "POST", &peer_path("merkle", name, peer),
+            &[("Authorization", authorization.as_str()), ("X-SQ-Node", config.node.id.as_str())],
+            request.join("\n").as_bytes(),
+            Duration::from_secs(PEER_TIMEOUT_SECS),
+        ).map_err(|e| e.to_string())?;
+        match status {
+            200 => {}
+            404 => return Ok(None),
+            _ => return Err(format!("merkle for {} returned HTTP {}", name, status)),
+        }
+
+        let mut next = Vec::new();
+        for (prefix, remote) in merkle::parse_answer(&String::from_utf8_lossy(&body)) {
+            let (parent, component) = prefix.split_at(prefix.len() - 1);
+            if local.child(parent, component[0]).map(|s| s.hash) == Some(remote.hash) {
+                continue;
+            }
+            if remote.count <= MERKLE_BATCH || prefix.len() == merkle::DEPTH {
+                diverged.push(prefix);
+            } else {
+                next.push(prefix);
+            }
+        }
+        frontier = next;
+    }
+    Ok(Some(diverged))
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// peer_scopes: inbound_key → scope for every peer allowed to pull from us (peers without a valid scope get none)
+// -----------------------------------------------------------------------------------------------------------
+fn peer_scopes(config: &MeshConfig) -> HashMap<String, CoordinateFilter> {
+    config.outbound.peers.iter()
+        .filter(|peer| !peer.inbound_key.is_empty())
+        .map(|peer| (peer.inbound_key.clone(), peer.scope().unwrap_or_else(|_| CoordinateFilter::nothing())))
+        .collect()
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// caller_scope: what an authenticated request may read
+//   None for the host's own key (everything); a peer's inbound_key yields that peer's subtrees, so our own
+//   config decides what we share with it; a caller claiming to be a mesh node (X-SQ-Node) without one of
+//   those keys is an unknown peer and gets nothing
+// -----------------------------------------------------------------------------------------------------------
+pub fn caller_scope(token: Option<&str>, node: Option<&str>) -> Option<CoordinateFilter> {
+    let scopes = PEER_SCOPES.get();
+    match token.and_then(|token| scopes.and_then(|scopes| scopes.get(token))) {
+        Some(scope) => Some(scope.clone()),
+        None if node.is_some() => Some(CoordinateFilter::nothing()),
+        None => None,
+    }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// is_peer_key: true if `token` is the inbound_key of one of our peers
+// -----------------------------------------------------------------------------------------------------------
+pub fn is_peer_key(token: &str) -> bool {
+    PEER_SCOPES.get().map(|scopes| scopes.contains_key(token)).unwrap_or(false)
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// fetch_versions: the peer's versions of `coordinates` (all unversioned when the peer predates versioning)
+// -----------------------------------------------------------------------------------------------------------
+fn fetch_versions(
+    config: &MeshConfig,
+    peer: &PeerConfig,
+    name: &str,
+    coordinates: &[phext::Coordinate],
+) -> Result<HashMap<phext::Coordinate, ScrollVersion>, String> {
+    let query = percent_encoding::utf8_percent_encode(name, percent_encoding::NON_ALPHANUMERIC).to_string();
+    let authorization = format!("Bearer {}", peer.auth_key);
+    let body: Vec<String> = coordinates.iter().map(|c| c.to_string()).collect();
+    let (status, body) = http::request(
+        &peer.host, peer.port, "POST", &format!("/api/v2/mesh/versions?p={}", query),
+        &[("Authorization", authorization.as_str()), ("X-SQ-Node", config.node.id.as_str())],
+        body.join("\n").as_bytes(),
+        Duration::from_secs(PEER_TIMEOUT_SECS),
+    ).map_err(|e| e.to_string())?;
+    match status {
+        200 => versions::parse_report(&body).map_err(|e| format!("bad version report for {}: {}", name, e)),
+        404 => Ok(HashMap::new()),
+        _ => Err(format!("versions for {} returned HTTP {}", name, status)),
+    }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// pull_phext: fetches and applies one phext's delta from `peer`, returning the number of scrolls changed
+//   scrolls we also edited are settled by conflict::resolve; conflicts are logged next to the phext
+// -----------------------------------------------------------------------------------------------------------
+fn pull_phext(
+    config: &MeshConfig,
+    peer: &PeerConfig,
+    name: &str,
+    path: &str,
+    residents: &ResidentSet,
+    durability: wal::Durability,
+    claimed: &mut Claimed,
+) -> Result<usize, String> {
+    let state = residents.get(path);
+    let scope = peer.scope()?;
+    let local_tree = {
+        let mut state = state.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if state.loaded_phext != path {
+            state.load(path, durability);
+        }
+        state.loaded_map.merkle_scoped(&scope)
+    };
+
+    // only the differing subtrees go into the delta request (everything when the peer can't tell us which)
+    let diverged = match merkle_diff(config, peer, name, &local_tree)? {
+        Some(diverged) if diverged.is_empty() => return Ok(0),
+        Some(diverged) => {
+            let patterns: Vec<String> = diverged.iter().map(|p| CoordinatePattern::prefix(p).to_string()).collect();
+            CoordinateFilter::parse(&patterns, &[])?
+        }
+        None => CoordinateFilter::default(),
+    };
+    let request = {
+        let mut state = state.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if state.loaded_phext != path {
+            state.load(path, durability);
+        }
+        let store = &state.loaded_map;
+        let checksums: HashMap<phext::Coordinate, String> = diverged.restrict(&scope).scan(store)
+            .map(|(c, _)| (*c, store.scroll_checksum(c).unwrap_or_default().to_string()))
+            .collect();
+        format!("{}{}", diverged.to_lines(), delta_request(&checksums))
+    };
+
+    let authorization = format!("Bearer {}", peer.auth_key);
+    let (status, body) = http::request(
+        &peer.host, peer.port, "POST", &peer_path("delta", name, peer),
+        &[("Authorization", authorization.as_str()), ("X-SQ-Node", config.node.id.as_str())],
+        request.as_bytes(),
+        Duration::from_secs(PEER_TIMEOUT_SECS),
+    ).map_err(|e| e.to_string())?;
+    if status != 200 {
+        return Err(format!("delta for {} returned HTTP {}", name, status));
+    }
+
+    let offered = phext::explode(&String::from_utf8_lossy(&body));
+    let claimed = claimed.entry(path.to_string()).or_default();
+    // an empty delta explodes to a single empty scroll; the peer never sends empty ones otherwise
+    let mut coordinates: Vec<phext::Coordinate> = offered.iter()
+        .filter(|(_, scroll)| !scroll.is_empty() && scroll.as_str() != crate::sq::MISSING_SCROLL)
+        .map(|(c, _)| *c)
+        .filter(|c| scope.matches(c))
+        .filter(|c| !claimed.contains(c))
+        .collect();
+    if coordinates.is_empty() {
+        return Ok(0);
+    }
+    coordinates.sort_by_key(crate::sq::coord_sort_key);
+    let remote_versions = fetch_versions(config, peer, name, &coordinates)?;
+    // claimed only once we can apply them, so a failed fetch leaves them to the next peer this round
+    claimed.extend(coordinates.iter().copied());
+
+    let (changed, conflicts) = {
+        let mut state = state.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if state.loaded_phext != path {
+            state.load(path, durability);
+        }
+        apply_pulled(&mut state, config, &peer.id, &offered, &coordinates, &remote_versions, claimed)?
+    };
+
+    conflict::record(path, &conflicts);
+    health::record_conflicts(&peer.id, conflicts.len());
+    Ok(changed)
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// apply_pulled: applies the `coordinates` of a peer's delta to the loaded phext, returning the number of
+//   scrolls changed and the conflicts found; `claimed` gains the siblings keep-both writes
+// -----------------------------------------------------------------------------------------------------------
+fn apply_pulled(
+    state: &mut crate::ServerState,
+    config: &MeshConfig,
+    peer_id: &str,
+    offered: &HashMap<phext::Coordinate, String>,
+    coordinates: &[phext::Coordinate],
+    remote_versions: &HashMap<phext::Coordinate, ScrollVersion>,
+    claimed: &mut HashSet<phext::Coordinate>,
+) -> Result<(usize, Vec<ConflictRecord>), String> {
+    let path = state.loaded_phext.clone();
+    let strategy = config.mesh.conflict_strategy;
+    let mut changed = 0;
+    let mut conflicts = Vec::new();
+    for coordinate in coordinates {
+        let coordinate = *coordinate;
+        let scroll = &offered[&coordinate];
+        let remote = remote_versions.get(&coordinate).cloned().unwrap_or_default();
+        let ours = match state.loaded_map.get(&coordinate) {
+            Some(ours) if ours == scroll => continue,
+            Some(ours) => ours.clone(),
+            // a scroll we deleted is settled against its tombstone like any other edit; one we never had is taken
+            None if deleted(state, &coordinate) => String::new(),
+            None => {
+                state.loaded_map.insert(coordinate, scroll.clone());
+                state.persist_pulled(coordinate, remote).map_err(|e| format!("failed to log {} in {}: {}", coordinate, path, e))?;
+                changed += 1;
+                continue;
+            }
+        };
+        let local = state.versions.as_ref().map(|v| v.get(&coordinate)).unwrap_or_default();
+
+        let remote_wins = match conflict::resolve(&local, &remote, strategy, config) {
+            Resolution::TakeRemote => true,
+            Resolution::KeepLocal => false,
+            Resolution::Conflict { remote_wins } => {
+                let lost = if remote_wins { ours.clone() } else { scroll.clone() };
+                let mut record = ConflictRecord {
+                    time: health::unix_now(),
+                    coordinate: coordinate.to_string(),
+                    peer: peer_id.to_string(),
+                    strategy,
+                    winner: if remote_wins { "remote" } else { "local" }.to_string(),
+                    local: local.clone(),
+                    remote: remote.clone(),
+                    sibling: None,
+                    lost: None,
+                };
+                if strategy == ConflictStrategy::KeepBoth && !lost.is_empty() {
+                    let sibling = conflict::sibling(coordinate, &state.loaded_map, |c| offered.contains_key(c) || claimed.contains(c));
+                    claimed.insert(sibling);
+                    state.loaded_map.insert(sibling, lost);
+                    state.persist(sibling).map_err(|e| format!("failed to log {} in {}: {}", sibling, path, e))?;
+                    changed += 1;
+                    record.sibling = Some(sibling.to_string());
+                } else {
+                    record.lost = Some(lost);
+                }
+                println!("[mesh] conflict at {} in {} with {}: kept the {} version ({})",
+                    coordinate, path, peer_id, record.winner, strategy.name());
+                conflicts.push(record);
+                remote_wins
+            }
+        };
+        if remote_wins {
+            state.loaded_map.insert(coordinate, scroll.clone());
+            state.persist_pulled(coordinate, remote).map_err(|e| format!("failed to log {} in {}: {}", coordinate, path, e))?;
+            changed += 1;
+        }
+    }
+
+    Ok((changed, conflicts))
+}
+
+fn deleted(state: &crate::ServerState, coordinate: &phext::Coordinate) -> bool {
+    state.versions.as_ref().map(|v| v.get(coordinate).deleted).unwrap_or(false)
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// sync_peer: pulls every replicated phext from one peer
+// -----------------------------------------------------------------------------------------------------------
+fn sync_peer(
+    config: &MeshConfig,
+    peer: &PeerConfig,
+    phexts: &[String],
+    data_dir: &Option<String>,
+    residents: &ResidentSet,
+    durability: wal::Durability,
+    claimed: &mut Claimed,
+) -> Result<usize, String> {
+    let mut changed = 0;
+    for name in phexts {
+        let path = phext_path(name, data_dir);
+        let pulled = pull_phext(config, peer, name, &path, residents, durability, claimed)?;
+        if pulled > 0 {
+            println!("[mesh] pulled {} scrolls into {} from {}", pulled, path, peer.id);
+        }
+        changed += pulled;
+    }
+    Ok(changed)
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// retry_delay: backoff before retry number `failures` (1-based), doubling each time
+// -----------------------------------------------------------------------------------------------------------
+fn retry_delay(backoff_seconds: u64, failures: u32) -> Duration {
+    let factor = 1u64 << (failures.saturating_sub(1)).min(16);
+    Duration::from_secs(backoff_seconds.saturating_mul(factor))
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// spawn: starts the background sync thread (no-op when outbound sync is disabled or has no peers)
+// -----------------------------------------------------------------------------------------------------------
+pub fn spawn(config: MeshConfig, data_dir: Option<String>, residents: Arc<ResidentSet>, durability: wal::Durability) {
+    let _ = PEER_SCOPES.set(peer_scopes(&config));
+    if !config.outbound.enabled || config.outbound.peers.is_empty() {
+        return;
+    }
+    let mut schedule: Vec<PeerSchedule> = config.outbound.peers.iter()
+        .map(|peer| PeerSchedule { peer: peer.clone(), next_due: Instant::now(), failures: 0 })
+        .collect();
+    schedule.sort_by(|a, b| (a.peer.priority, &a.peer.id).cmp(&(b.peer.priority, &b.peer.id)));
+    let interval = Duration::from_secs(config.mesh.sync_interval_seconds.max(1));
+    println!("Mesh sync: {} peers every {} seconds", schedule.len(), interval.as_secs());
+
+    std::thread::spawn(move || {
+        loop {
+            let now = Instant::now();
+            if schedule.iter().any(|s| s.next_due <= now) {
+                let phexts = replicated_phexts(&config, &data_dir);
+                let mut claimed = Claimed::new();
+                for entry in schedule.iter_mut().filter(|s| s.next_due <= now) {
+                    let result = sync_peer(&config, &entry.peer, &phexts, &data_dir, &residents, durability, &mut claimed);
+                    health::record_sync(&entry.peer.id, &result);
+                    match result {
+                        Ok(_) => {
+                            entry.failures = 0;
+                            entry.next_due = Instant::now() + interval;
+                        }
+                        Err(e) if entry.failures < config.mesh.max_retries => {
+                            entry.failures += 1;
+                            let delay = retry_delay(config.mesh.retry_backoff_seconds, entry.failures);
+                            eprintln!("Warning: mesh sync with {} failed (retry {}/{} in {}s): {}",
+                                entry.peer.id, entry.failures, config.mesh.max_retries, delay.as_secs(), e);
+                            entry.next_due = Instant::now() + delay;
+                        }
+                        Err(e) => {
+                            eprintln!("Warning: mesh sync with {} failed, giving up until the next interval: {}", entry.peer.id, e);
+                            entry.failures = 0;
+                            entry.next_due = Instant::now() + interval;
+                        }
+                    }
+                }
+            }
+            std::thread::sleep(Duration::from_millis(TICK_MS));
+        }
+    });
+}
+
+#[cfg(test)]
+mod replication_tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay_doubles() {
+        assert_eq!(retry_delay(30, 1), Duration::from_secs(30));
+        assert_eq!(retry_delay(30, 2), Duration::from_secs(60));
+        assert_eq!(retry_delay(30, 3), Duration::from_secs(120));
+        assert_eq!(retry_delay(0, 5), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn test_delta_request_format() {
+        let mut checksums = HashMap::new();
+        checksums.insert(phext::to_coordinate("1.1.1/1.1.1/1.1.2"), "bb".to_string());
+        checksums.insert(phext::to_coordinate("1.1.1/1.1.1/1.1.1"), "aa".to_string());
+        assert_eq!(delta_request(&checksums), "1.1.1/1.1.1/1.1.1: aa\n1.1.1/1.1.1/1.1.2: bb");
+    }
+
+    #[test]
+    fn test_caller_scope_follows_peer_keys() {
+        let mut config = MeshConfig::default();
+        config.inbound.auth_key = "pmb-v1-host".to_string();
+        config.outbound.peers.push(PeerConfig {
+            id: "work".to_string(),
+            name: "Work".to_string(),
+            host: "10.0.0.5".to_string(),
+            port: 2086,
+            auth_key: "pmb-v1-theirs".to_string(),
+            inbound_key: "pmb-v1-work".to_string(),
+            coordinate: "5.1.1/1.1.1/1.1.1".to_string(),
+            priority: 1,
+            include: vec!["5.x.x/*/*".to_string()],
+            exclude: Vec::new(),
+        });
+        let _ = PEER_SCOPES.set(peer_scopes(&config));
+        let work = phext::to_coordinate("5.1.1/1.1.1/1.1.1");
+        let home = phext::to_coordinate("1.1.1/1.1.1/1.1.1");
+
+        // the key decides the scope, whatever node id the caller claims
+        assert!(is_peer_key("pmb-v1-work"));
+        let scope = caller_scope(Some("pmb-v1-work"), Some("home")).unwrap();
+        assert!(scope.matches(&work) && !scope.matches(&home));
+        assert_eq!(caller_scope(Some("pmb-v1-work"), None), Some(scope));
+
+        // an unknown mesh node sees nothing; the host's own clients see everything
+        assert!(!is_peer_key("pmb-v1-host"));
+        let unknown = caller_scope(Some("pmb-v1-host"), Some("work")).unwrap();
+        assert!(!unknown.matches(&work) && !unknown.matches(&home));
+        assert_eq!(caller_scope(Some("pmb-v1-host"), None), None);
+        assert_eq!(caller_scope(None, None), None);
+    }
+
+    #[test]
+    fn test_local_delete_survives_a_pull() {
+        let dir = crate::tests::TempDir::new("replication-delete");
+        let mut state = crate::ServerState::new();
+        state.load(&dir.path("world.phext"), wal::Durability::None);
+        state.versions = Some(versions::VersionTable::open(&state.loaded_phext));
+        let config = MeshConfig::default();
+        let (kept, edited) = (phext::to_coordinate("1.1.1/1.1.1/1.1.1"), phext::to_coordinate("1.1.1/1.1.1/1.1.2"));
+
+        // both scrolls came from the peer, then we deleted them
+        let pulled: HashMap<phext::Coordinate, String> = [(kept, "old".to_string()), (edited, "old".to_string())].into();
+        let peer_versions: HashMap<phext::Coordinate, ScrollVersion> = [kept, edited].iter()
+            .map(|c| (*c, ScrollVersion { version: versions::Version { stamp: 1, node: "peer".to_string() }, ..Default::default() }))
+            .collect();
+        apply_pulled(&mut state, &config, "peer", &pulled, &[kept, edited], &peer_versions, &mut HashSet::new()).unwrap();
+        for coordinate in [kept, edited] {
+            state.loaded_map.remove(&coordinate);
+            state.persist(coordinate).unwrap();
+        }
+
+        // the peer offers them again: the untouched one stays deleted, the one it edited since comes back
+        let mut offered = pulled.clone();
+        offered.insert(edited, "new".to_string());
+        let mut remote = peer_versions.clone();
+        remote.insert(edited, peer_versions[&edited].successor(versions::tick() + 1_000_000, "peer"));
+        let (changed, conflicts) = apply_pulled(&mut state, &config, "peer", &offered, &[kept, edited], &remote, &mut HashSet::new()).unwrap();
+        assert_eq!(state.loaded_map.get(&kept), None);
+        assert_eq!(state.loaded_map.get(&edited).map(|s| s.as_str()), Some("new"));
+        assert_eq!((changed, conflicts.len()), (1, 1));
+        assert!(!state.versions.as_ref().unwrap().get(&edited).deleted);
+    }
+
+    #[test]
+    fn test_replicated_phexts_lists_data_dir() {
+        let dir = crate::tests::TempDir::new("replication-phexts");
+        dir.write("b.phext", "");
+        dir.write("a.phext", "");
+        dir.write("a.phext.wal", "");
+        let data_dir = Some(dir.path(""));
+
+        let mut config = MeshConfig::default();
+        assert_eq!(replicated_phexts(&config, &data_dir), vec!["a", "b"]);
+        config.mesh.phexts = vec!["world".to_string()];
+        assert_eq!(replicated_phexts(&config, &data_dir), vec!["world"]);
+    }
+}
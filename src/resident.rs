@@ -0,0 +1,199 @@
+//------------------------------------------------------------------------------------------------------------
+// file: resident.rs
+// purpose: the set of phexts kept in memory by sq host, evicted least-recently-used under a byte budget
+//
+// Each phext gets its own ServerState (and lock), so requests for different phexts never reload each other.
+// The budget counts serialized phext bytes (ScrollStore::byte_size). The phext that was just requested is
+// never evicted, even when it alone is over budget. Neither is a phext some request is still using.
+// Evicted phexts have their write-ahead log compacted first, so nothing is left only in memory. A victim stays
+// in the set while it is compacted, so a request that arrives meanwhile reuses it instead of reloading a
+// half-compacted phext from disk. If the background compactor is already compacting it, eviction waits for it.
+//------------------------------------------------------------------------------------------------------------
+
+use crate::ServerState;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+
+struct Resident {
+    state: Arc<RwLock<ServerState>>,
+    last_used: u64,
+    bytes: usize, // as of the last time the state could be inspected without waiting
+}
+
+#[derive(Default)]
+struct Residents {
+    phexts: HashMap<String, Resident>,
+    clock: u64,
+}
+
+pub struct ResidentSet {
+    inner: Mutex<Residents>,
+    budget: usize,
+}
+
+impl ResidentSet {
+    pub fn new(budget: usize) -> ResidentSet {
+        ResidentSet { inner: Mutex::new(Residents::default()), budget }
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // get: the state for `phext` (created unloaded on first use), evicting cold phexts if over budget
+    // -------------------------------------------------------------------------------------------------------
+    pub fn get(&self, phext: &str) -> Arc<RwLock<ServerState>> {
+        let (state, evicted) = {
+            let mut residents = self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            residents.clock += 1;
+            let clock = residents.clock;
+            let resident = residents.phexts.entry(phext.to_string()).or_insert_with(|| Resident {
+                state: Arc::new(RwLock::new(ServerState::new())),
+                last_used: 0,
+                bytes: 0,
+            });
+            resident.last_used = clock;
+            let state = Arc::clone(&resident.state);
+            (state, residents.victims(phext, self.budget))
+        };
+
+        // compaction writes the whole phext - done after the set's lock is released
+        for (name, victim) in evicted {
+            crate::compact_state(&victim, true);
+            let mut residents = self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            // only the set and `victim` may still hold it; otherwise a request picked it up meanwhile
+            let unused = residents.phexts.get(&name)
+                .map(|r| Arc::ptr_eq(&r.state, &victim) && Arc::strong_count(&victim) == 2)
+                .unwrap_or(false);
+            if unused {
+                residents.phexts.remove(&name);
+                println!("Evicted {} from memory", name);
+            }
+        }
+        state
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // states: every resident phext (for the write-ahead log compactor)
+    // -------------------------------------------------------------------------------------------------------
+    pub fn states(&self) -> Vec<Arc<RwLock<ServerState>>> {
+        let residents = self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        residents.phexts.values().map(|r| Arc::clone(&r.state)).collect()
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // report: the /api/v2/loaded listing, most recently used first
+    //   `data_dir` limits the listing to one tenant's phexts
+    // -------------------------------------------------------------------------------------------------------
+    pub fn report(&self, data_dir: &Option<String>) -> String {
+        let mut residents = self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        residents.refresh_sizes();
+        let mut listed: Vec<(&String, &Resident)> = residents.phexts.iter()
+            .filter(|(name, _)| data_dir.as_ref().map(|dir| name.starts_with(&format!("{}/", dir))).unwrap_or(true))
+            .collect();
+        listed.sort_by_key(|(_, r)| std::cmp::Reverse(r.last_used));
+
+        let total: usize = listed.iter().map(|(_, r)| r.bytes).sum();
+        let mut result = format!("Resident: {} phexts, {} bytes (budget {} bytes)\n", listed.len(), total, self.budget);
+        for (name, resident) in listed {
+            result.push_str(&format!("* {}: {} bytes\n", name, resident.bytes));
+        }
+        result
+    }
+}
+
+impl Residents {
+    fn refresh_sizes(&mut self) {
+        for resident in self.phexts.values_mut() {
+            if let Ok(state) = resident.state.try_read() {
+                resident.bytes = state.loaded_map.byte_size();
+            }
+        }
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // victims: least-recently-used phexts to evict so the rest fit in `budget`
+    //   `keep` (the phext being requested) and phexts still referenced by a request are skipped
+    // -------------------------------------------------------------------------------------------------------
+    fn victims(&mut self, keep: &str, budget: usize) -> Vec<(String, Arc<RwLock<ServerState>>)> {
+        self.refresh_sizes();
+        let mut total: usize = self.phexts.values().map(|r| r.bytes).sum();
+        let mut evicted = Vec::new();
+        if total <= budget {
+            return evicted;
+        }
+
+        let mut candidates: Vec<(u64, String)> = self.phexts.iter()
+            .filter(|(name, r)| name.as_str() != keep && Arc::strong_count(&r.state) == 1)
+            .map(|(name, r)| (r.last_used, name.clone()))
+            .collect();
+        candidates.sort();
+        for (_, name) in candidates {
+            if total <= budget {
+                break;
+            }
+            if let Some(resident) = self.phexts.get(&name) {
+                total -= resident.bytes;
+                evicted.push((name, Arc::clone(&resident.state)));
+            }
+        }
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod resident_tests {
+    use super::*;
+    use crate::phext;
+
+    fn fill(set: &ResidentSet, name: &str, bytes: usize) {
+        let state = set.get(name);
+        let mut state = state.write().unwrap();
+        state.loaded_phext = name.to_string();
+        state.loaded_map.insert(phext::to_coordinate("1.1.1/1.1.1/1.1.1"), "x".repeat(bytes));
+    }
+
+    fn names(set: &ResidentSet) -> Vec<String> {
+        set.report(&None).lines().skip(1)
+            .map(|line| line.trim_start_matches("* ").split(':').next().unwrap_or_default().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let set = ResidentSet::new(100);
+        fill(&set, "a.phext", 40);
+        fill(&set, "b.phext", 40);
+        assert_eq!(names(&set), vec!["b.phext", "a.phext"]);
+
+        // touching a makes b the coldest; c pushes the total over budget
+        let _ = set.get("a.phext");
+        fill(&set, "c.phext", 40);
+        let _ = set.get("c.phext");
+        assert_eq!(names(&set), vec!["c.phext", "a.phext"]);
+    }
+
+    #[test]
+    fn test_requested_and_busy_phexts_stay() {
+        let set = ResidentSet::new(10);
+        fill(&set, "big.phext", 50);
+        let _ = set.get("big.phext");
+        assert_eq!(names(&set), vec!["big.phext"]);
+
+        let held = set.get("big.phext");
+        fill(&set, "other.phext", 50);
+        let _ = set.get("other.phext");
+        assert_eq!(names(&set).len(), 2);
+        drop(held);
+        let _ = set.get("other.phext");
+        assert_eq!(names(&set), vec!["other.phext"]);
+    }
+
+    #[test]
+    fn test_report_filters_by_data_dir() {
+        let set = ResidentSet::new(1000);
+        fill(&set, "tenant1/a.phext", 5);
+        fill(&set, "tenant2/b.phext", 5);
+        let report = set.report(&Some("tenant1".to_string()));
+        assert!(report.starts_with("Resident: 1 phexts, 5 bytes"));
+        assert!(report.contains("tenant1/a.phext"));
+        assert!(!report.contains("tenant2"));
+    }
+}
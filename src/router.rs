@@ -4,22 +4,63 @@
 // 
 // Architecture:
 // - Single router process listens on public port (e.g., 443 or 1337)
-// - Each tenant gets dedicated SQ instance on private port with --key and --data-dir
+// - Each tenant gets dedicated SQ instance on private port with --key and --data-dir. Backends needn't be local:
+//   a tenant's backend (and each replica) is a host and port, or a unix socket path, with the tenant's own
+//   connect/read/write timeouts
 // - Router reads Authorization header, looks up tenant config, proxies to backend
+// - Routing rules (RouterConfig.routes) can pick the tenant by Host header and/or path prefix instead; a public
+//   rule lets token-less clients use the read-only endpoints in PUBLIC_READS. Rules are tried in order, and
+//   requests no rule matches are routed by token (Authorization, then X-SQ-API-Key) as before
+// - Backends always receive `Authorization: Bearer <tenant token>`, with the matched path prefix stripped
+// - Client connections are persistent (keep-alive, pipelining); each one reuses its backend connections
+// - A tenant may list read replicas next to its backend (e.g. mesh followers of it). Mutations - the commands
+//   the host itself treats as mutations (is_mutation / is_bulk_mutation) - always go to the tenant's primary
+//   backend; reads are spread over the primary and its replicas, round-robin or to the backend with the fewest
+//   requests in flight (balance). Backends that are down are left out of the rotation
+// - Connections are served by the shared bounded worker pool (pool.rs); a full queue is answered with 503
+// - Fairness: open client connections are capped router-wide (max_connections, 503 beyond it), and each tenant
+//   may only have so many requests in flight to its backend (max_in_flight, 429 beyond it), so one tenant with
+//   a slow backend or a flood of requests ties up at most its share of the workers
+// - Every backend (primary and replicas) is probed with GET /api/v2/version (using the tenant's token) at startup
+//   and every health_check_interval_seconds. While a backend is down its requests go to the other healthy
+//   backends, or get a fast 503 instead of a connect attempt when there are none; a refused connection while proxying marks it down too, and the next good probe brings it back.
+//   GET /health (no auth) reports every backend by tenant name and address - never by token
+// - With "supervise": true the router starts each tenant's backend itself and keeps it running (supervisor.rs).
+//   SIGTERM/SIGINT stop the children before the router exits. Replicas are never supervised
+// - The tenant table is reloaded on SIGHUP, on POST /api/v2/reload from localhost, and (with --watch) when the
+//   config file changes. A config that fails to load or validate is logged and the current table is kept
 //
-// Usage: sq route <config.json> <port>
+// Usage: sq route <config.json> [port] [--bind <addr>]... [--socket <path>] [--watch <seconds>]
 //------------------------------------------------------------------------------------------------------------
 
+use crate::http;
+use crate::pool;
+use crate::socket::{Listener, Stream};
+use crate::supervisor::{BackendSpec, Supervisor};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt;
 use std::fs;
-use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
-use std::sync::{Arc, RwLock};
-use std::time::Duration;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicU64, AtomicUsize};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
 
 const MAX_HEADER_SIZE: usize = 16_384; // 16 KB header limit
-const ROUTER_TIMEOUT_MS: u64 = 30_000; // 30 second timeout
+const CLIENT_TIMEOUT_MS: u64 = 30_000; // writes to clients
+const DEFAULT_CONNECT_TIMEOUT_MS: u64 = 5_000; // backend timeouts, unless the tenant sets its own
+const DEFAULT_BACKEND_TIMEOUT_MS: u64 = 30_000;
+const DEFAULT_MAX_IN_FLIGHT: usize = crate::WORKER_THREADS / 4 - 1; // per tenant: any four tenants still leave workers free
+const DEFAULT_HEALTH_CHECK_SECS: u64 = 10;
+const PROBE_TIMEOUT_SECS: u64 = 5;
+const PROBE_CONCURRENCY: usize = 32; // backends probed at once, so a round of dead backends takes seconds, not minutes
+const BACKEND_STARTUP_MS: u64 = 3_000;
+const MAX_IDLE_BACKENDS: usize = 4; // backend connections one client connection keeps open for reuse
+
+// GET /api/v2/<endpoint> requests a public route serves without a token
+//   (not json-export: it rewrites the phext on disk)
+const PUBLIC_READS: &[&str] = &["select", "range", "prefix", "search", "toc", "checksum", "get", "delta", "version"];
 
 // -----------------------------------------------------------------------------------------------------------
 // Configuration structures
@@ -27,14 +68,102 @@ const ROUTER_TIMEOUT_MS: u64 = 30_000; // 30 second timeout
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TenantConfig {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,       // shown by /health and in logs (defaults to "port <port>")
     pub token: String,      // pmb-v1-xxx auth token
-    pub port: u16,          // backend SQ instance port
-    pub data_dir: String,   // tenant data directory
+    #[serde(flatten)]
+    pub backend: BackendAddress, // backend SQ instance: "host" (default 127.0.0.1) and "port", or "socket"
+    #[serde(default)]
+    pub data_dir: String,   // tenant data directory (used when supervising)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_in_flight: Option<usize>, // overrides RouterConfig.max_in_flight_per_tenant
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub replicas: Vec<BackendAddress>, // read-only copies of the tenant; mutations still go to `backend`
+    #[serde(default)]
+    pub balance: Balance,   // how reads are spread over the backend and its replicas
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_timeout_ms: Option<u64>, // for the backend and its replicas (default: 5000)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_timeout_ms: Option<u64>,    // waiting for a response (default: 30000)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub write_timeout_ms: Option<u64>,   // sending a request (default: 30000)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct BackendAddress {
+    #[serde(default = "default_backend_host")]
+    pub host: String,       // defaults to 127.0.0.1
+    #[serde(default)]
+    pub port: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub socket: Option<String>, // unix socket path; replaces host and port
+}
+
+fn default_backend_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+impl BackendAddress {
+    // a backend on this machine (what supervise can run): a unix socket, or a loopback host
+    pub fn is_local(&self) -> bool {
+        self.socket.is_some() || self.host == "localhost" ||
+            self.host.parse::<std::net::IpAddr>().map(|ip| ip.is_loopback()).unwrap_or(false)
+    }
+}
+
+impl fmt::Display for BackendAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref socket) = self.socket {
+            write!(f, "unix:{}", socket)
+        } else if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Balance {
+    #[default]
+    RoundRobin,       // each read goes to the next healthy backend in turn
+    LeastConnections, // each read goes to the healthy backend with the fewest requests in flight
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RouterConfig {
     pub tenants: Vec<TenantConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_connections: Option<usize>,          // open client connections (default: workers + queue depth)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_in_flight_per_tenant: Option<usize>, // concurrent proxied requests per tenant (default: workers / 4 - 1)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_check_interval_seconds: Option<u64>, // backend probes (default: 10)
+    #[serde(default)]
+    pub supervise: bool,                         // start and restart every tenant's `sq host` backend
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub routes: Vec<RouteRule>,                  // host/path rules, tried in order before token routing
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RouteRule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,        // Host header to match (case-insensitive, port ignored)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_prefix: Option<String>, // e.g. "/t/alice" - stripped before the request is forwarded
+    pub tenant: String,              // the `name` of the tenant serving matched requests
+    #[serde(default)]
+    pub public: bool,                // read-only requests need no token
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub phexts: Vec<String>,         // public rules: the phexts readable without a token (empty = all)
+}
+
+fn valid_backend(backend: &BackendAddress) -> bool {
+    match backend.socket {
+        Some(ref socket) => !socket.is_empty(),
+        None => !backend.host.is_empty() && backend.port != 0,
+    }
 }
 
 // -----------------------------------------------------------------------------------------------------------
@@ -46,17 +175,733 @@ pub fn load_router_config(path: &str) -> Result<RouterConfig, Box<dyn std::error
     
     // Validate no duplicate tokens
     let mut seen_tokens = std::collections::HashSet::new();
+    let mut seen_backends = std::collections::HashSet::new();
     for tenant in &config.tenants {
         if !seen_tokens.insert(&tenant.token) {
             return Err(format!("Duplicate token in config: {}", tenant.token).into());
         }
+        if config.supervise && !tenant.backend.is_local() {
+            return Err(format!("Tenant {}: supervise only runs local backends, not {}", tenant_name(tenant), tenant.backend).into());
+        }
+        if config.supervise && !seen_backends.insert(tenant.backend.socket.clone().ok_or(tenant.backend.port)) {
+            return Err(format!("Duplicate backend in config: {} (each supervised backend needs its own)", tenant.backend).into());
+        }
+        if tenant.max_in_flight == Some(0) {
+            return Err(format!("max_in_flight must be at least 1 (tenant {})", tenant_name(tenant)).into());
+        }
+        if [tenant.connect_timeout_ms, tenant.read_timeout_ms, tenant.write_timeout_ms].contains(&Some(0)) {
+            return Err(format!("Timeouts must be at least 1 ms (tenant {})", tenant_name(tenant)).into());
+        }
+        if !valid_backend(&tenant.backend) {
+            return Err(format!("Tenant {}: backend {} needs a host and a port, or a socket", tenant_name(tenant), tenant.backend).into());
+        }
+        let mut backends = vec![tenant.backend.clone()];
+        for replica in &tenant.replicas {
+            if !valid_backend(replica) {
+                return Err(format!("Replica {} of tenant {}: needs a host and a port, or a socket", replica, tenant_name(tenant)).into());
+            }
+            if backends.contains(replica) {
+                return Err(format!("Replica {} of tenant {} is listed twice (or is its primary)", replica, tenant_name(tenant)).into());
+            }
+            backends.push(replica.clone());
+        }
+    }
+    if config.max_connections == Some(0) || config.max_in_flight_per_tenant == Some(0) || config.health_check_interval_seconds == Some(0) {
+        return Err("max_connections, max_in_flight_per_tenant and health_check_interval_seconds must be at least 1".into());
+    }
+    for (index, rule) in config.routes.iter().enumerate() {
+        let named = config.tenants.iter().filter(|t| t.name == rule.tenant).count();
+        if rule.tenant.is_empty() || named != 1 {
+            return Err(format!("Route {}: tenant '{}' must name exactly one tenant", index + 1, rule.tenant).into());
+        }
+        if rule.host.is_none() && rule.path_prefix.is_none() {
+            return Err(format!("Route {}: needs a host, a path_prefix, or both", index + 1).into());
+        }
+        if rule.path_prefix.as_ref().map(|p| !p.starts_with('/')).unwrap_or(false) {
+            return Err(format!("Route {}: path_prefix must start with '/'", index + 1).into());
+        }
+        if !rule.public && !rule.phexts.is_empty() {
+            return Err(format!("Route {}: phexts only applies to public routes", index + 1).into());
+        }
     }
     
     Ok(config)
 }
 
 // -----------------------------------------------------------------------------------------------------------
-// Extracts Authorization header from HTTP request
+// Router: the live routing table, swapped in whole by each successful reload, and the fairness counters
+//   (which outlive reloads, so a reload never resets a tenant's in-flight count)
+// -----------------------------------------------------------------------------------------------------------
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    pub name: String,
+    pub primary: BackendAddress,
+    pub replicas: Vec<BackendAddress>,
+    pub balance: Balance,
+    pub max_in_flight: usize,
+    pub timeouts: BackendTimeouts,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BackendTimeouts {
+    pub connect: Duration,
+    pub read: Duration,
+    pub write: Duration,
+}
+
+impl Route {
+    // the primary first, then the replicas in config order
+    pub fn backends(&self) -> impl Iterator<Item = &BackendAddress> {
+        std::iter::once(&self.primary).chain(self.replicas.iter())
+    }
+
+    fn role(&self, backend: &BackendAddress) -> &'static str {
+        if *backend == self.primary { "primary" } else { "replica" }
+    }
+}
+
+// a RouteRule with its tenant resolved to a token
+struct Rule {
+    host: Option<String>,
+    path_prefix: Option<String>,
+    token: String,
+    public: bool,
+    phexts: Vec<String>,
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// Resolved: where a request goes, and the header to forward it with
+// -----------------------------------------------------------------------------------------------------------
+#[derive(Debug)]
+pub struct Resolved {
+    pub token: String,
+    pub route: Route,
+    pub header: String,
+    pub write: bool, // must go to the primary
+}
+
+struct RouteTable {
+    routes: HashMap<String, Route>,
+    rules: Vec<Rule>,
+    max_connections: usize,
+    health_check_interval: Duration,
+    supervised: Vec<BackendSpec>, // empty unless the config sets supervise
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// BackendHealth: the latest probe results for one tenant backend (primary or replica), as listed by GET /health
+// -----------------------------------------------------------------------------------------------------------
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BackendHealth {
+    pub name: String,
+    pub role: String,
+    pub host: String,
+    pub port: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub socket: Option<String>,
+    pub healthy: bool,
+    pub latency_ms: Option<u64>,
+    pub last_check: Option<u64>,
+    pub last_success: Option<u64>,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+}
+
+impl BackendHealth {
+    fn record(&mut self, result: Result<Duration, String>, now: u64) {
+        self.last_check = Some(now);
+        match result {
+            Ok(latency) => {
+                self.healthy = true;
+                self.latency_ms = Some(latency.as_millis() as u64);
+                self.last_success = Some(now);
+                self.consecutive_failures = 0;
+                self.last_error = None;
+            }
+            Err(e) => {
+                self.healthy = false;
+                self.latency_ms = None;
+                self.consecutive_failures += 1;
+                self.last_error = Some(e);
+            }
+        }
+    }
+}
+
+pub struct Router {
+    config_path: String,
+    table: RwLock<RouteTable>,
+    loaded_mtime: Mutex<Option<SystemTime>>, // held for the whole reload, so reloads never interleave
+    connections: AtomicUsize,
+    in_flight: Mutex<InFlight>,
+    rotation: Mutex<HashMap<String, usize>>, // token → where its next read starts looking
+    health: Mutex<HashMap<(String, BackendAddress), BackendHealth>>, // (token, backend) → probe results
+}
+
+#[derive(Default)]
+struct InFlight {
+    tenants: HashMap<String, usize>,          // token → requests being proxied
+    backends: HashMap<BackendAddress, usize>, // backend → requests being proxied to it
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// ConnectionSlot / RequestSlot: a client connection or proxied request counted against its limit until dropped
+// -----------------------------------------------------------------------------------------------------------
+pub struct ConnectionSlot {
+    router: Arc<Router>,
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.router.connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub struct RequestSlot<'a> {
+    router: &'a Router,
+    token: String,
+    backend: BackendAddress,
+}
+
+fn release<K: std::hash::Hash + Eq>(counts: &mut HashMap<K, usize>, key: &K) {
+    if let Some(count) = counts.get_mut(key) {
+        *count -= 1;
+        if *count == 0 {
+            counts.remove(key);
+        }
+    }
+}
+
+impl Drop for RequestSlot<'_> {
+    fn drop(&mut self) {
+        let mut in_flight = self.router.in_flight.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        release(&mut in_flight.tenants, &self.token);
+        release(&mut in_flight.backends, &self.backend);
+    }
+}
+
+fn config_mtime(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn tenant_name(tenant: &TenantConfig) -> String {
+    match (tenant.name.is_empty(), &tenant.backend.socket) {
+        (false, _) => tenant.name.clone(),
+        (true, Some(socket)) => format!("socket {}", socket),
+        (true, None) => format!("port {}", tenant.backend.port),
+    }
+}
+
+fn route_table(config: &RouterConfig) -> RouteTable {
+    let default_limit = config.max_in_flight_per_tenant.unwrap_or(DEFAULT_MAX_IN_FLIGHT);
+    RouteTable {
+        routes: config.tenants.iter().map(|tenant| {
+            let max_in_flight = tenant.max_in_flight.unwrap_or(default_limit);
+            (tenant.token.clone(), Route {
+                name: tenant_name(tenant),
+                primary: tenant.backend.clone(),
+                replicas: tenant.replicas.clone(),
+                balance: tenant.balance,
+                max_in_flight,
+                timeouts: BackendTimeouts {
+                    connect: Duration::from_millis(tenant.connect_timeout_ms.unwrap_or(DEFAULT_CONNECT_TIMEOUT_MS)),
+                    read: Duration::from_millis(tenant.read_timeout_ms.unwrap_or(DEFAULT_BACKEND_TIMEOUT_MS)),
+                    write: Duration::from_millis(tenant.write_timeout_ms.unwrap_or(DEFAULT_BACKEND_TIMEOUT_MS)),
+                },
+            })
+        }).collect(),
+        rules: config.routes.iter().filter_map(|rule| {
+            let tenant = config.tenants.iter().find(|t| t.name == rule.tenant)?;
+            Some(Rule {
+                host: rule.host.as_ref().map(|h| h.to_ascii_lowercase()),
+                path_prefix: rule.path_prefix.as_ref().map(|p| p.trim_end_matches('/').to_string()),
+                token: tenant.token.clone(),
+                public: rule.public,
+                phexts: rule.phexts.clone(),
+            })
+        }).collect(),
+        supervised: if !config.supervise { Vec::new() } else {
+            config.tenants.iter().map(|tenant| BackendSpec {
+                name: tenant_name(tenant),
+                port: tenant.backend.port,
+                bind: match tenant.backend.host.parse::<std::net::IpAddr>() {
+                    Ok(ip) => ip.to_string(),
+                    Err(_) => "127.0.0.1".to_string(), // localhost
+                },
+                socket: tenant.backend.socket.clone(),
+                token: tenant.token.clone(),
+                data_dir: tenant.data_dir.clone(),
+            }).collect()
+        },
+        max_connections: config.max_connections.unwrap_or(crate::WORKER_THREADS + crate::WORKER_QUEUE_DEPTH),
+        health_check_interval: Duration::from_secs(config.health_check_interval_seconds.unwrap_or(DEFAULT_HEALTH_CHECK_SECS)),
+    }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// Host header without its port, lowercased ("[::1]:1337" → "[::1]")
+// -----------------------------------------------------------------------------------------------------------
+fn request_host(header: &str) -> Option<String> {
+    let host = http::header_value(header, "host")?;
+    let host = match host.strip_prefix('[') {
+        Some(rest) => format!("[{}]", rest.split(']').next().unwrap_or("")),
+        None => host.split(':').next().unwrap_or("").to_string(),
+    };
+    Some(host.to_ascii_lowercase())
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// The request target with `prefix` removed, or None when the target isn't under it ("/t/a" covers "/t/a",
+// "/t/a/..." and "/t/a?...", but not "/t/ab")
+// -----------------------------------------------------------------------------------------------------------
+fn strip_path_prefix(target: &str, prefix: &str) -> Option<String> {
+    let rest = target.strip_prefix(prefix)?;
+    if rest.is_empty() || rest.starts_with('?') {
+        Some(format!("/{}", rest))
+    } else if rest.starts_with('/') {
+        Some(rest.to_string())
+    } else {
+        None
+    }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// True when a token-less request may use a public route: a GET of a PUBLIC_READS endpoint on a listed phext
+// -----------------------------------------------------------------------------------------------------------
+fn public_read(method: &str, target: &str, phexts: &[String]) -> bool {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let endpoint = match path.strip_prefix("/api/v2/") {
+        Some(endpoint) => endpoint,
+        None => return false,
+    };
+    if method != "GET" || !PUBLIC_READS.contains(&endpoint) {
+        return false;
+    }
+    phexts.is_empty() || crate::parse_query_string(query).get("p").map(|p| phexts.contains(p)).unwrap_or(false)
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// True when a request has to go to the tenant's primary: anything outside /api/v2/, and every endpoint the host
+// runs as a mutation. The host matches endpoints by prefix ("/api/v2/insertx" inserts), so every prefix counts
+// -----------------------------------------------------------------------------------------------------------
+fn is_write(target: &str) -> bool {
+    let path = target.split('?').next().unwrap_or("");
+    match path.strip_prefix("/api/v2/") {
+        Some(endpoint) => endpoint.char_indices()
+            .map(|(i, c)| &endpoint[..i + c.len_utf8()])
+            .any(|command| crate::is_mutation(command) || crate::is_bulk_mutation(command)),
+        None => true,
+    }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// Rewrites a request header for the backend: a request line rebuilt from the validated method, target and
+// version, and the tenant's token as the only credential (backends check Authorization only)
+// -----------------------------------------------------------------------------------------------------------
+fn forward_header(header: &str, method: &str, target: &str, version: &str, token: &str) -> String {
+    let mut result = format!("{} {} {}\r\n", method, target, version);
+    for line in header.trim_end_matches("\r\n").split("\r\n").skip(1) {
+        let lower = line.to_ascii_lowercase();
+        if !lower.starts_with("authorization:") && !lower.starts_with("x-sq-api-key:") {
+            result.push_str(line);
+            result.push_str("\r\n");
+        }
+    }
+    result.push_str(&format!("Authorization: Bearer {}\r\n\r\n", token));
+    result
+}
+
+impl RouteTable {
+    // -------------------------------------------------------------------------------------------------------
+    // resolve: picks the tenant for a request (first matching rule, else its token), or the status and
+    // message to reject it with
+    // -------------------------------------------------------------------------------------------------------
+    fn resolve(&self, header: &str) -> Result<Resolved, (u16, &'static str)> {
+        let presented = extract_auth_token(header);
+        let (method, target, version) = http::request_line(header).ok_or((400, "Bad Request - malformed request line"))?;
+        let host = request_host(header);
+
+        let matched = self.rules.iter().find_map(|rule| {
+            if rule.host.is_some() && rule.host != host {
+                return None;
+            }
+            match rule.path_prefix {
+                Some(ref prefix) => strip_path_prefix(target, prefix).map(|target| (rule, target)),
+                None => Some((rule, target.to_string())),
+            }
+        });
+
+        let (token, target) = match matched {
+            Some((rule, target)) => {
+                if presented.as_deref() != Some(rule.token.as_str()) && !(rule.public && public_read(method, &target, &rule.phexts)) {
+                    return Err(match presented {
+                        None if rule.public => (401, "Unauthorized - Token required for this request"),
+                        None => (401, "Unauthorized - No token provided"),
+                        Some(_) => (401, "Unauthorized - Invalid token"),
+                    });
+                }
+                (rule.token.clone(), target)
+            }
+            None => match presented {
+                None => return Err((401, "Unauthorized - No token provided")),
+                Some(token) => (token, target.to_string()),
+            },
+        };
+        let route = self.routes.get(&token).cloned().ok_or((401, "Unauthorized - Invalid token"))?;
+        let header = forward_header(header, method, &target, version, &token);
+        Ok(Resolved { token, route, header, write: is_write(&target) })
+    }
+}
+
+fn probe(backend: &BackendAddress, token: &str) -> Result<Duration, String> {
+    let started = Instant::now();
+    let timeout = Duration::from_secs(PROBE_TIMEOUT_SECS);
+    let mut connection = BackendConnection::open(backend, &BackendTimeouts { connect: timeout, read: timeout, write: timeout })
+        .map_err(|e| e.to_string())?;
+    let request = format!("GET /api/v2/version HTTP/1.1\r\nHost: {}\r\nAuthorization: Bearer {}\r\nConnection: close\r\n\r\n",
+        backend.host, token);
+    let response = connection.exchange(request.as_bytes()).map_err(|e| e.to_string())?
+        .ok_or("Backend closed the connection")?;
+    let code: u16 = response.header.split_whitespace().nth(1).and_then(|code| code.parse().ok()).unwrap_or(0);
+    if code != 200 {
+        return Err(format!("HTTP {}", code));
+    }
+    Ok(started.elapsed())
+}
+
+impl Router {
+    pub fn load(config_path: &str) -> Result<Router, Box<dyn std::error::Error>> {
+        let loaded_mtime = config_mtime(config_path);
+        let config = load_router_config(config_path)?;
+        Ok(Router {
+            config_path: config_path.to_string(),
+            table: RwLock::new(route_table(&config)),
+            loaded_mtime: Mutex::new(loaded_mtime),
+            connections: AtomicUsize::new(0),
+            in_flight: Mutex::new(InFlight::default()),
+            rotation: Mutex::new(HashMap::new()),
+            health: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn resolve(&self, header: &str) -> Result<Resolved, (u16, &'static str)> {
+        self.table.read().unwrap_or_else(|poisoned| poisoned.into_inner()).resolve(header)
+    }
+
+    pub fn tenant_count(&self) -> usize {
+        self.table.read().unwrap_or_else(|poisoned| poisoned.into_inner()).routes.len()
+    }
+
+    pub fn max_connections(&self) -> usize {
+        self.table.read().unwrap_or_else(|poisoned| poisoned.into_inner()).max_connections
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // admit_connection: counts a newly accepted client connection, or None when max_connections are open
+    // -------------------------------------------------------------------------------------------------------
+    pub fn admit_connection(self: &Arc<Self>) -> Option<ConnectionSlot> {
+        let limit = self.max_connections();
+        let mut current = self.connections.load(Ordering::SeqCst);
+        loop {
+            if current >= limit {
+                return None;
+            }
+            match self.connections.compare_exchange(current, current + 1, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => return Some(ConnectionSlot { router: Arc::clone(self) }),
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // pick_backend: the backend for a request - the primary for writes, otherwise the next healthy backend
+    // by route.balance - or None when every candidate is down
+    // -------------------------------------------------------------------------------------------------------
+    pub fn pick_backend(&self, token: &str, route: &Route, write: bool) -> Option<BackendAddress> {
+        let candidates: Vec<&BackendAddress> = if write {
+            vec![&route.primary]
+        } else {
+            route.backends().collect()
+        };
+        let candidates: Vec<&BackendAddress> = candidates.into_iter().filter(|b| !self.backend_down(token, b)).collect();
+        if candidates.len() <= 1 {
+            return candidates.first().map(|b| (*b).clone());
+        }
+
+        // both policies start looking where the last read left off, so ties take turns
+        let start = {
+            let mut rotation = self.rotation.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let next = rotation.entry(token.to_string()).or_insert(0);
+            let start = *next % candidates.len();
+            *next = start + 1;
+            start
+        };
+        let order = (0..candidates.len()).map(|i| candidates[(start + i) % candidates.len()]);
+        let chosen = match route.balance {
+            Balance::RoundRobin => candidates[start],
+            Balance::LeastConnections => {
+                let in_flight = self.in_flight.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                order.min_by_key(|b| in_flight.backends.get(*b).copied().unwrap_or(0)).unwrap_or(candidates[start])
+            }
+        };
+        Some(chosen.clone())
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // begin_request: counts a request about to be proxied to `backend` for `token`, or None when the tenant
+    // already has route.max_in_flight requests at its backends
+    // -------------------------------------------------------------------------------------------------------
+    pub fn begin_request(&self, token: &str, route: &Route, backend: &BackendAddress) -> Option<RequestSlot<'_>> {
+        let mut in_flight = self.in_flight.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let count = in_flight.tenants.entry(token.to_string()).or_insert(0);
+        if *count >= route.max_in_flight {
+            return None;
+        }
+        *count += 1;
+        *in_flight.backends.entry(backend.clone()).or_insert(0) += 1;
+        Some(RequestSlot { router: self, token: token.to_string(), backend: backend.clone() })
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // backend_down: true when the last probe of one of `token`'s backends failed (unprobed ones count as up)
+    // -------------------------------------------------------------------------------------------------------
+    pub fn backend_down(&self, token: &str, backend: &BackendAddress) -> bool {
+        let health = self.health.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        health.get(&(token.to_string(), backend.clone())).map(|h| !h.healthy).unwrap_or(false)
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // record_health: folds a probe (or a refused proxy connection) into a backend's health
+    //   logs only transitions, so a backend that stays down doesn't flood the log
+    // -------------------------------------------------------------------------------------------------------
+    pub fn record_health(&self, token: &str, route: &Route, backend: &BackendAddress, result: Result<Duration, String>) {
+        let mut health = self.health.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let entry = health.entry((token.to_string(), backend.clone())).or_default();
+        let was_healthy = entry.healthy || entry.last_check.is_none();
+        entry.name = route.name.clone();
+        entry.role = route.role(backend).to_string();
+        entry.host = backend.host.clone();
+        entry.port = backend.port;
+        entry.socket = backend.socket.clone();
+        entry.record(result, crate::health::unix_now());
+        if was_healthy && !entry.healthy {
+            eprintln!("Warning: {} {} of {} is down: {}", entry.role, backend, entry.name, entry.last_error.as_deref().unwrap_or(""));
+        } else if !was_healthy && entry.healthy {
+            println!("The {} {} of {} is back up", entry.role, backend, entry.name);
+        }
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // check_backends: probes every backend once (PROBE_CONCURRENCY at a time) and forgets backends that were
+    // removed
+    // -------------------------------------------------------------------------------------------------------
+    pub fn check_backends(&self) {
+        let routes: Vec<(String, Route)> = {
+            let table = self.table.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+            table.routes.iter().map(|(token, route)| (token.clone(), route.clone())).collect()
+        };
+        let backends: Vec<(&String, &Route, &BackendAddress)> = routes.iter()
+            .flat_map(|(token, route)| route.backends().map(move |backend| (token, route, backend)))
+            .collect();
+        for batch in backends.chunks(PROBE_CONCURRENCY) {
+            std::thread::scope(|scope| {
+                for &(token, route, backend) in batch {
+                    scope.spawn(move || self.record_health(token, route, backend, probe(backend, token)));
+                }
+            });
+        }
+        let mut health = self.health.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        health.retain(|(token, backend), _| routes.iter().any(|(t, route)| t == token && route.backends().any(|b| b == backend)));
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // health_report: the JSON body of GET /health
+    // -------------------------------------------------------------------------------------------------------
+    pub fn health_report(&self) -> String {
+        let (tenants, mut backends) = {
+            let table = self.table.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let health = self.health.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let backends: Vec<BackendHealth> = table.routes.iter().flat_map(|(token, route)| {
+                let health = &health;
+                route.backends().map(move |backend| match health.get(&(token.clone(), backend.clone())) {
+                    Some(h) => h.clone(),
+                    None => BackendHealth {
+                        name: route.name.clone(),
+                        role: route.role(backend).to_string(),
+                        host: backend.host.clone(),
+                        port: backend.port,
+                        socket: backend.socket.clone(),
+                        healthy: true,
+                        ..Default::default()
+                    },
+                })
+            }).collect();
+            (table.routes.len(), backends)
+        };
+        backends.sort_by(|a, b| (a.port, &a.socket, &a.host, &a.name).cmp(&(b.port, &b.socket, &b.host, &b.name)));
+        let healthy = backends.iter().filter(|b| b.healthy).count();
+        serde_json::json!({
+            "status": if healthy == backends.len() { "ok" } else { "degraded" },
+            "tenants": tenants,
+            "healthy": healthy,
+            "backends": backends,
+        }).to_string()
+    }
+
+    pub fn supervised_backends(&self) -> Vec<BackendSpec> {
+        self.table.read().unwrap_or_else(|poisoned| poisoned.into_inner()).supervised.clone()
+    }
+
+    fn health_check_interval(&self) -> Duration {
+        self.table.read().unwrap_or_else(|poisoned| poisoned.into_inner()).health_check_interval
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // reload: re-reads the config file and returns the new tenant count; on any error the table is kept
+    // -------------------------------------------------------------------------------------------------------
+    pub fn reload(&self, reason: &str) -> Result<usize, String> {
+        let mut loaded_mtime = self.loaded_mtime.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        // recorded even when the load fails, so a broken file is reported once rather than on every poll
+        *loaded_mtime = config_mtime(&self.config_path);
+        match load_router_config(&self.config_path) {
+            Ok(config) => {
+                let table = route_table(&config);
+                let count = table.routes.len();
+                let mut current = self.table.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+                let old_count = std::mem::replace(&mut *current, table).routes.len();
+                println!("Config reloaded ({}): {} tenants (was {})", reason, count, old_count);
+                Ok(count)
+            }
+            Err(e) => {
+                eprintln!("Failed to reload config ({}), keeping the current tenants: {}", reason, e);
+                Err(e.to_string())
+            }
+        }
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // changed_on_disk: true when the config file's mtime differs from the one last loaded
+    // -------------------------------------------------------------------------------------------------------
+    fn changed_on_disk(&self) -> bool {
+        let loaded_mtime = self.loaded_mtime.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        config_mtime(&self.config_path) != *loaded_mtime
+    }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// SIGHUP only sets a flag; the reload thread picks it up
+// -----------------------------------------------------------------------------------------------------------
+static HANGUP: AtomicBool = AtomicBool::new(false);
+const RELOAD_POLL_MS: u64 = 250;
+
+#[cfg(unix)]
+fn install_hangup_handler() {
+    extern "C" fn on_hangup(_: libc::c_int) {
+        HANGUP.store(true, Ordering::SeqCst);
+    }
+    let handler: extern "C" fn(libc::c_int) = on_hangup;
+    unsafe {
+        libc::signal(libc::SIGHUP, handler as libc::sighandler_t);
+    }
+}
+
+#[cfg(not(unix))]
+fn install_hangup_handler() {}
+
+// -----------------------------------------------------------------------------------------------------------
+// SIGTERM/SIGINT likewise only set a flag, so the supervisor thread can stop the backends before exiting
+// -----------------------------------------------------------------------------------------------------------
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+fn install_shutdown_handler() {
+    extern "C" fn on_shutdown(_: libc::c_int) {
+        SHUTDOWN.store(true, Ordering::SeqCst);
+    }
+    let handler: extern "C" fn(libc::c_int) = on_shutdown;
+    unsafe {
+        libc::signal(libc::SIGTERM, handler as libc::sighandler_t);
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+    }
+}
+
+#[cfg(not(unix))]
+fn install_shutdown_handler() {}
+
+// -----------------------------------------------------------------------------------------------------------
+// Supervisor thread: keeps the supervised backends in line with the (reloadable) config, and on SIGTERM or
+// SIGINT stops them and exits the router
+// -----------------------------------------------------------------------------------------------------------
+fn spawn_supervisor(router: Arc<Router>, mut supervisor: Supervisor) {
+    install_shutdown_handler();
+    std::thread::spawn(move || {
+        loop {
+            std::thread::sleep(Duration::from_millis(RELOAD_POLL_MS));
+            if SHUTDOWN.load(Ordering::SeqCst) {
+                if supervisor.running() > 0 {
+                    println!("Shutting down: stopping {} backends", supervisor.running());
+                }
+                supervisor.stop_all();
+                std::process::exit(0);
+            }
+            supervisor.tick(&router.supervised_backends());
+        }
+    });
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// Waits (up to BACKEND_STARTUP_MS) for freshly started backends to accept connections
+// -----------------------------------------------------------------------------------------------------------
+fn wait_for_backends(backends: &[BackendSpec]) {
+    let deadline = Instant::now() + Duration::from_millis(BACKEND_STARTUP_MS);
+    let mut waiting: Vec<&BackendSpec> = backends.iter().collect();
+    while !waiting.is_empty() && Instant::now() < deadline {
+        waiting.retain(|backend| match backend.socket {
+            Some(ref path) => Stream::connect_unix(path).is_err(),
+            None => Stream::connect_tcp(&backend.bind, backend.port, Duration::from_millis(BACKEND_STARTUP_MS)).is_err(),
+        });
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// Reload thread: handles SIGHUP and, when `watch` is set, polls the config file's mtime at that interval
+// -----------------------------------------------------------------------------------------------------------
+fn spawn_reloader(router: Arc<Router>, watch: Option<Duration>) {
+    install_hangup_handler();
+    std::thread::spawn(move || {
+        let mut last_check = Instant::now();
+        loop {
+            std::thread::sleep(Duration::from_millis(RELOAD_POLL_MS));
+            if HANGUP.swap(false, Ordering::SeqCst) {
+                let _ = router.reload("SIGHUP");
+            }
+            if let Some(interval) = watch {
+                if last_check.elapsed() >= interval {
+                    last_check = Instant::now();
+                    if router.changed_on_disk() {
+                        let _ = router.reload("file changed");
+                    }
+                }
+            }
+        }
+    });
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// Health check thread: probes every backend each health_check_interval_seconds (re-read after a reload)
+// -----------------------------------------------------------------------------------------------------------
+fn spawn_health_checker(router: Arc<Router>) {
+    std::thread::spawn(move || {
+        loop {
+            std::thread::sleep(router.health_check_interval());
+            router.check_backends();
+        }
+    });
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// Extracts the token from the Authorization header, falling back to X-SQ-API-Key (as the multi-tenant host does)
 // Returns the token (without "Bearer " prefix if present)
 // -----------------------------------------------------------------------------------------------------------
 fn extract_auth_token(header: &str) -> Option<String> {
@@ -74,135 +919,154 @@ fn extract_auth_token(header: &str) -> Option<String> {
             }
         }
     }
-    None
+    http::header_value(header, "x-sq-api-key").map(|token| token.to_string())
 }
 
 // -----------------------------------------------------------------------------------------------------------
-// Reads HTTP request header from stream (up to first \r\n\r\n)
-// Returns (header_string, header_end_offset, buffer, total_bytes_read)
-// The buffer may contain extra bytes beyond the header (start of body)
+// ConnectError: the backend couldn't be reached at all, so it never saw the request
 // -----------------------------------------------------------------------------------------------------------
-fn read_http_header(stream: &mut TcpStream) -> Result<(String, usize, Vec<u8>, usize), Box<dyn std::error::Error>> {
-    let mut buffer = vec![0u8; MAX_HEADER_SIZE];
-    let mut total_read = 0;
-    
-    loop {
-        let bytes_read = stream.read(&mut buffer[total_read..])?;
-        if bytes_read == 0 {
-            return Err("Connection closed before header complete".into());
-        }
-        total_read += bytes_read;
-        
-        // Check for end of header (\r\n\r\n)
-        if total_read >= 4 {
-            let check_start = if total_read > 4 { total_read - bytes_read } else { 0 };
-            for i in check_start..total_read - 3 {
-                if &buffer[i..i+4] == b"\r\n\r\n" {
-                    let header = String::from_utf8_lossy(&buffer[..i+4]).to_string();
-                    return Ok((header, i + 4, buffer, total_read));
-                }
-            }
-        }
-        
-        if total_read >= MAX_HEADER_SIZE {
-            return Err("Header too large".into());
-        }
+#[derive(Debug)]
+struct ConnectError(std::io::Error);
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cannot connect: {}", self.0)
+    }
+}
+
+impl std::error::Error for ConnectError {}
+
+// -----------------------------------------------------------------------------------------------------------
+// BackendConnection: a persistent connection to one tenant backend, reused across a client's requests
+// -----------------------------------------------------------------------------------------------------------
+struct BackendConnection {
+    address: BackendAddress,
+    stream: Stream,
+    reader: http::MessageReader,
+}
+
+impl BackendConnection {
+    fn open(address: &BackendAddress, timeouts: &BackendTimeouts) -> std::io::Result<BackendConnection> {
+        let stream = match address.socket {
+            Some(ref path) => Stream::connect_unix(path)?,
+            None => Stream::connect_tcp(&address.host, address.port, timeouts.connect)?,
+        };
+        stream.set_read_timeout(Some(timeouts.read))?;
+        stream.set_write_timeout(Some(timeouts.write))?;
+        Ok(BackendConnection { address: address.clone(), stream, reader: http::MessageReader::new(MAX_HEADER_SIZE, http::MAX_RESPONSE_SIZE) })
+    }
+
+    fn exchange(&mut self, request: &[u8]) -> std::io::Result<Option<http::HttpRequest>> {
+        self.stream.write_all(request)?;
+        self.reader.next_message(&mut self.stream)
     }
 }
 
 // -----------------------------------------------------------------------------------------------------------
-// Proxies HTTP request to backend SQ instance
-// Returns response or error
+// Rewrites a message header for the next hop: hop-by-hop connection headers are replaced with `connection`
+// -----------------------------------------------------------------------------------------------------------
+fn rewrite_connection_headers(header: &str, connection: &str) -> String {
+    let mut lines: Vec<&str> = header.trim_end_matches("\r\n").split("\r\n")
+        .filter(|line| {
+            let lower = line.to_ascii_lowercase();
+            !lower.starts_with("connection:") && !lower.starts_with("keep-alive:")
+        })
+        .collect();
+    lines.push("");
+    format!("{}{}\r\n", lines.join("\r\n"), connection)
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// True when a reused backend connection had already been closed (the backend's idle timeout)
+// -----------------------------------------------------------------------------------------------------------
+fn stale_connection(result: &std::io::Result<Option<http::HttpRequest>>) -> bool {
+    match result {
+        Ok(None) => true,
+        Err(e) => matches!(e.kind(),
+            std::io::ErrorKind::BrokenPipe | std::io::ErrorKind::ConnectionReset | std::io::ErrorKind::ConnectionAborted),
+        Ok(Some(_)) => false,
+    }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// Proxies one HTTP request to a backend SQ instance, reusing the client's connection to it when possible
+//   if a reused connection turns out to be closed by the backend (its idle timeout), the request is retried
+//   once on a fresh connection - the backend never read it, so a mutation can't be applied twice.
+//   Timeouts are never retried: a slow backend may already have acted on the request.
 // -----------------------------------------------------------------------------------------------------------
 fn proxy_request(
-    client_stream: &mut TcpStream,
-    backend_port: u16,
+    client_stream: &mut Stream,
+    idle: &mut Vec<BackendConnection>,
+    backend: &BackendAddress,
+    timeouts: &BackendTimeouts,
     header: &str,
-    header_end: usize,
-    initial_buffer: &[u8],
-    initial_bytes_read: usize,
+    request: &http::HttpRequest,
+    keep_alive: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // Connect to backend
-    let mut backend = TcpStream::connect(format!("127.0.0.1:{}", backend_port))?;
-    backend.set_read_timeout(Some(Duration::from_millis(ROUTER_TIMEOUT_MS)))?;
-    backend.set_write_timeout(Some(Duration::from_millis(ROUTER_TIMEOUT_MS)))?;
-    
-    // Forward header
-    backend.write_all(header.as_bytes())?;
-    
-    // Check if request has body (Content-Length header)
-    let content_length = extract_content_length(header);
-    
-    if content_length > 0 {
-        // First, forward any body bytes that were already read with the header
-        let already_read = initial_bytes_read - header_end;
-        if already_read > 0 {
-            let body_start = header_end;
-            let body_end = std::cmp::min(header_end + already_read, initial_bytes_read);
-            backend.write_all(&initial_buffer[body_start..body_end])?;
-        }
-        
-        // Now read and forward the rest of the body
-        let mut remaining = content_length - already_read;
-        let mut buffer = vec![0u8; 8192];
-        
-        while remaining > 0 {
-            let to_read = std::cmp::min(remaining, buffer.len());
-            let bytes_read = client_stream.read(&mut buffer[..to_read])?;
-            if bytes_read == 0 {
-                break; // Client closed connection
-            }
-            backend.write_all(&buffer[..bytes_read])?;
-            remaining -= bytes_read;
-        }
+    let mut forwarded = rewrite_connection_headers(header, "Connection: keep-alive\r\n").into_bytes();
+    forwarded.extend_from_slice(&request.content);
+
+    let reused = idle.iter().position(|c| c.address == *backend).map(|i| idle.swap_remove(i));
+    let was_reused = reused.is_some();
+    let mut connection = match reused {
+        Some(c) => c,
+        None => BackendConnection::open(backend, timeouts).map_err(ConnectError)?,
+    };
+    let mut result = connection.exchange(&forwarded);
+    if was_reused && stale_connection(&result) {
+        connection = BackendConnection::open(backend, timeouts).map_err(ConnectError)?;
+        result = connection.exchange(&forwarded);
     }
-    
-    // Read response from backend and forward to client
-    let mut response_buffer = vec![0u8; 8192];
-    loop {
-        match backend.read(&mut response_buffer) {
-            Ok(0) => break, // EOF
-            Ok(n) => {
-                client_stream.write_all(&response_buffer[..n])?;
-            }
-            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
-            Err(e) => return Err(e.into()),
+    let response = result?.ok_or("Backend closed the connection")?;
+
+    let mut reply = rewrite_connection_headers(&response.header, &http::connection_headers(keep_alive, crate::READ_TIMEOUT_SECS)).into_bytes();
+    reply.extend_from_slice(&response.content);
+    client_stream.write_all(&reply)?;
+
+    if http::keep_alive(&response.header) {
+        if idle.len() >= MAX_IDLE_BACKENDS {
+            idle.remove(0);
         }
+        idle.push(connection);
     }
-    
     Ok(())
 }
 
 // -----------------------------------------------------------------------------------------------------------
-// Extracts Content-Length header value
+// True when a proxy error means the backend couldn't be reached (refused, unreachable, connect timeout)
 // -----------------------------------------------------------------------------------------------------------
-fn extract_content_length(header: &str) -> usize {
-    for line in header.lines() {
-        let lower_line = line.to_lowercase();
-        if lower_line.starts_with("content-length:") {
-            if let Some(value) = line.split(':').nth(1) {
-                return value.trim().parse().unwrap_or(0);
-            }
-        }
-    }
-    0
+fn connect_failed(error: &(dyn std::error::Error + 'static)) -> bool {
+    error.downcast_ref::<ConnectError>().is_some()
 }
 
 // -----------------------------------------------------------------------------------------------------------
 // Sends error response to client
 // -----------------------------------------------------------------------------------------------------------
-fn send_error(stream: &mut TcpStream, code: u16, message: &str) {
-    let body = format!("{{\"error\": \"{}\"}}", message);
+fn send_error(stream: &mut Stream, code: u16, message: &str, keep_alive: bool) {
+    send_json(stream, code, &format!("{{\"error\": \"{}\"}}", message), keep_alive);
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// Sends a JSON response generated by the router itself
+// -----------------------------------------------------------------------------------------------------------
+fn send_json(stream: &mut Stream, code: u16, body: &str, keep_alive: bool) {
     let response = format!(
-        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\n{}Content-Length: {}\r\n\r\n{}",
         code,
         match code {
+            200 => "OK",
+            400 => "Bad Request",
             401 => "Unauthorized",
+            403 => "Forbidden",
             404 => "Not Found",
+            429 => "Too Many Requests",
+            413 => "Payload Too Large",
             500 => "Internal Server Error",
             502 => "Bad Gateway",
+            503 => "Service Unavailable",
             _ => "Error",
         },
+        http::connection_headers(keep_alive, crate::READ_TIMEOUT_SECS),
         body.len(),
         body
     );
@@ -212,98 +1076,425 @@ fn send_error(stream: &mut TcpStream, code: u16, message: &str) {
 // -----------------------------------------------------------------------------------------------------------
 // Main router loop - listens for connections and routes to backends
 // -----------------------------------------------------------------------------------------------------------
-pub fn run_router(config_path: &str, listen_port: u16) -> Result<(), Box<dyn std::error::Error>> {
-    // Load config
-    let config = load_router_config(config_path)?;
-    
-    // Build token→port lookup map
-    let mut token_map: HashMap<String, u16> = HashMap::new();
-    for tenant in &config.tenants {
-        token_map.insert(tenant.token.clone(), tenant.port);
-    }
-    
-    let token_map = Arc::new(RwLock::new(token_map));
+pub fn run_router(config_path: &str, listeners: Vec<Listener>, watch: Option<Duration>) -> Result<(), Box<dyn std::error::Error>> {
+    // Load config and build the token→port table
+    let router = Arc::new(Router::load(config_path)?);
     
     println!("╔══════════════════════════════════════════════════════════╗");
     println!("║             SQ Router v0.5.5 (Token-based)              ║");
     println!("╚══════════════════════════════════════════════════════════╝");
     println!();
-    println!("Listening on: 0.0.0.0:{}", listen_port);
-    println!("Tenants configured: {}", config.tenants.len());
+    println!("Listening on: {}", listeners.iter().map(|l| l.to_string()).collect::<Vec<_>>().join(", "));
+    println!("Tenants configured: {}", router.tenant_count());
+    println!("Connection limit: {}", router.max_connections());
     println!("Config file: {}", config_path);
+    match watch {
+        Some(interval) => println!("Reload: SIGHUP, POST /api/v2/reload from localhost, or a file change (checked every {}s)", interval.as_secs()),
+        None => println!("Reload: SIGHUP or POST /api/v2/reload from localhost"),
+    }
     println!();
+    spawn_reloader(Arc::clone(&router), watch);
+
+    // Start the supervised backends (if any) and give them a moment to start listening
+    let mut supervisor = Supervisor::default();
+    let supervised = router.supervised_backends();
+    if !supervised.is_empty() {
+        println!("Supervising {} backends", supervised.len());
+        supervisor.tick(&supervised);
+        wait_for_backends(&supervised);
+    }
+    spawn_supervisor(Arc::clone(&router), supervisor);
+
+    // Probe every backend before taking traffic, so a dead one is reported (and answered with 503) up front
+    router.check_backends();
+    let report: serde_json::Value = serde_json::from_str(&router.health_report()).unwrap_or_default();
+    println!("Backends healthy: {} of {}", report["healthy"], report["backends"].as_array().map(|b| b.len()).unwrap_or(0));
+    spawn_health_checker(Arc::clone(&router));
     
-    // Start listening
-    let listener = TcpListener::bind(format!("0.0.0.0:{}", listen_port))?;
+    // Start serving
+    let pool = pool::start(crate::WORKER_THREADS, crate::WORKER_QUEUE_DEPTH);
+    println!("Workers: {} (queue depth {})", pool.workers(), pool.queue_depth());
+    println!();
     
-    let mut connection_id = 0u64;
-    for stream in listener.incoming() {
-        match stream {
-            Ok(mut client_stream) => {
-                connection_id += 1;
-                let conn_id = connection_id;
-                
-                // Set timeouts
-                let _ = client_stream.set_read_timeout(Some(Duration::from_millis(ROUTER_TIMEOUT_MS)));
-                let _ = client_stream.set_write_timeout(Some(Duration::from_millis(ROUTER_TIMEOUT_MS)));
-                
-                // Read request header
-                let (header, header_end, buffer, total_bytes) = match read_http_header(&mut client_stream) {
-                    Ok(h) => h,
-                    Err(e) => {
-                        eprintln!("[{}] Failed to read header: {}", conn_id, e);
-                        send_error(&mut client_stream, 400, "Bad Request");
-                        continue;
-                    }
-                };
-                
-                // Handle CORS preflight (no auth needed)
-                if header.starts_with("OPTIONS ") {
-                    let cors = "HTTP/1.1 204 No Content\r\n\
-                        Access-Control-Allow-Origin: *\r\n\
-                        Access-Control-Allow-Methods: GET, POST, OPTIONS\r\n\
-                        Access-Control-Allow-Headers: Authorization, Content-Type\r\n\
-                        Access-Control-Max-Age: 86400\r\n\r\n";
-                    let _ = client_stream.write_all(cors.as_bytes());
-                    continue;
-                }
+    let connection_id = AtomicU64::new(0);
+    crate::socket::serve(listeners, move |mut client_stream| {
+        let conn_id = connection_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let slot = match router.admit_connection() {
+            Some(slot) => slot,
+            None => {
+                eprintln!("[{}] Connection limit reached, rejecting", conn_id);
+                let _ = client_stream.set_write_timeout(Some(Duration::from_millis(CLIENT_TIMEOUT_MS)));
+                send_error(&mut client_stream, 503, "Service Unavailable - connection limit reached", false);
+                return;
+            }
+        };
+        let queued = pool.try_execute(client_stream, move |client_stream| {
+            handle_router_connection(client_stream, conn_id, &slot.router);
+            drop(slot);
+        });
+        if let Err(mut client_stream) = queued {
+            eprintln!("[{}] Worker queue full, rejecting", conn_id);
+            let _ = client_stream.set_write_timeout(Some(Duration::from_millis(CLIENT_TIMEOUT_MS)));
+            send_error(&mut client_stream, 503, "Service Unavailable - server busy", false);
+        }
+    });
+    
+    Ok(())
+}
 
-                // Extract auth token
-                let token = match extract_auth_token(&header) {
-                    Some(t) => t,
+// -----------------------------------------------------------------------------------------------------------
+// Routes every request on one client connection until it closes, idles out, or hits the request cap
+//   tokens are looked up per request, so a reload applies to persistent connections immediately
+// -----------------------------------------------------------------------------------------------------------
+fn handle_router_connection(mut client_stream: Stream, conn_id: u64, router: &Router) {
+    // Set timeouts (the read timeout doubles as the keep-alive idle timeout)
+    let _ = client_stream.set_read_timeout(Some(Duration::from_secs(crate::READ_TIMEOUT_SECS)));
+    let _ = client_stream.set_write_timeout(Some(Duration::from_millis(CLIENT_TIMEOUT_MS)));
+    let is_localhost = client_stream.is_local(); // unix socket clients count: the socket's permissions let them in
+
+    let mut reader = http::MessageReader::new(MAX_HEADER_SIZE, crate::MAX_BODY_SIZE);
+    let mut backends: Vec<BackendConnection> = Vec::new();
+    for served in 1..=http::MAX_REQUESTS_PER_CONNECTION {
+        // Read request (an idle connection gives its worker up as soon as another connection is queued)
+        if served > 1 && !reader.has_buffered() &&
+            !http::wait_for_request(&client_stream, Duration::from_secs(crate::READ_TIMEOUT_SECS), pool::has_backlog) {
+            return;
+        }
+        let request = match reader.next_message(&mut client_stream) {
+            Ok(Some(r)) => r,
+            Ok(None) => return,
+            Err(e) if reader.idle_timeout(&e) => return,
+            Err(e) => {
+                eprintln!("[{}] Failed to read request: {}", conn_id, e);
+                let code = if e.kind() == std::io::ErrorKind::InvalidData { 413 } else { 400 };
+                send_error(&mut client_stream, code, "Bad Request", false);
+                return;
+            }
+        };
+        let keep_alive = http::keep_alive(&request.header) && served < http::MAX_REQUESTS_PER_CONNECTION
+            && !pool::has_backlog();
+        let header = &request.header;
+
+        // Handle CORS preflight (no auth needed)
+        if header.starts_with("OPTIONS ") {
+            let _ = client_stream.write_all(http::preflight_response(keep_alive, crate::READ_TIMEOUT_SECS).as_bytes());
+        } else if header.starts_with("GET /health ") || header.starts_with("GET /health?") {
+            // Router health (no auth required)
+            send_json(&mut client_stream, 200, &router.health_report(), keep_alive);
+        } else if header.starts_with("POST /api/v2/reload") {
+            // Admin endpoint (localhost only, no auth required)
+            if !is_localhost {
+                send_error(&mut client_stream, 403, "Forbidden - Reload endpoint only accessible from localhost", keep_alive);
+            } else {
+                match router.reload("admin endpoint") {
+                    Ok(count) => send_json(&mut client_stream, 200, &format!("{{\"tenants\": {}}}", count), keep_alive),
+                    Err(e) => send_json(&mut client_stream, 400, &serde_json::json!({ "error": e }).to_string(), keep_alive),
+                }
+            }
+        } else {
+            // Pick the tenant (routing rules, then token), then a healthy backend, and claim an in-flight slot
+            let routed = match router.resolve(header) {
+                Err((code, message)) => {
+                    eprintln!("[{}] {}", conn_id, message);
+                    send_error(&mut client_stream, code, message, keep_alive);
+                    None
+                }
+                Ok(resolved) => match router.pick_backend(&resolved.token, &resolved.route, resolved.write) {
                     None => {
-                        eprintln!("[{}] No Authorization header", conn_id);
-                        send_error(&mut client_stream, 401, "Unauthorized - No token provided");
-                        continue;
+                        let role = if resolved.write { "primary" } else { "every backend" };
+                        eprintln!("[{}] Tenant {}: {} is down", conn_id, resolved.route.name, role);
+                        send_error(&mut client_stream, 503, "Service Unavailable - backend down", keep_alive);
+                        None
                     }
-                };
-                
-                // Look up backend port
-                let backend_port = {
-                    let map = token_map.read().unwrap();
-                    match map.get(&token) {
-                        Some(port) => *port,
+                    Some(backend) => match router.begin_request(&resolved.token, &resolved.route, &backend) {
+                        Some(slot) => Some((resolved, backend, slot)),
                         None => {
-                            eprintln!("[{}] Invalid token: {}", conn_id, &token[..8.min(token.len())]);
-                            send_error(&mut client_stream, 401, "Unauthorized - Invalid token");
-                            continue;
+                            let route = &resolved.route;
+                            eprintln!("[{}] Tenant {} at its limit of {} requests in flight", conn_id, route.name, route.max_in_flight);
+                            send_error(&mut client_stream, 429, "Too Many Requests - tenant concurrency limit reached", keep_alive);
+                            None
                         }
-                    }
-                };
-                
-                println!("[{}] Routing to backend port {}", conn_id, backend_port);
-                
-                // Proxy request
-                if let Err(e) = proxy_request(&mut client_stream, backend_port, &header, header_end, &buffer, total_bytes) {
+                    },
+                },
+            };
+
+            if let Some((Resolved { token, route, header, write }, first, slot)) = routed {
+                let (mut backend, mut slot, mut attempts) = (first, Some(slot), 1);
+                loop {
+                    println!("[{}] Routing to {} {}", conn_id, route.role(&backend), backend);
+                    let e = match proxy_request(&mut client_stream, &mut backends, &backend, &route.timeouts, &header, &request, keep_alive) {
+                        Ok(()) => break,
+                        Err(e) => e,
+                    };
                     eprintln!("[{}] Proxy error: {}", conn_id, e);
-                    send_error(&mut client_stream, 502, "Bad Gateway");
+                    if connect_failed(e.as_ref()) {
+                        router.record_health(&token, &route, &backend, Err(e.to_string()));
+                        // the unreachable backend never saw the request, so another healthy one may take it
+                        drop(slot.take());
+                        if let Some(next) = router.pick_backend(&token, &route, write).filter(|_| attempts < route.backends().count()) {
+                            slot = router.begin_request(&token, &route, &next);
+                            if slot.is_some() {
+                                backend = next;
+                                attempts += 1;
+                                continue;
+                            }
+                        }
+                    }
+                    send_error(&mut client_stream, 502, "Bad Gateway", false);
+                    return;
                 }
             }
-            Err(e) => {
-                eprintln!("Connection error: {}", e);
-            }
+        }
+
+        if !keep_alive {
+            return;
         }
     }
-    
-    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::TempDir;
+
+    fn route(router: &Router, token: &str) -> Option<Route> {
+        router.resolve(&format!("GET /api/v2/toc?p=w HTTP/1.1\r\nAuthorization: Bearer {}\r\n\r\n", token)).ok().map(|r| r.route)
+    }
+
+    #[test]
+    fn test_bad_reload_keeps_current_tenants() {
+        let dir = TempDir::new("router");
+        let path = dir.write("router.json", r#"{"tenants": [{"token": "a", "port": 2001, "data_dir": "/tmp/a"}]}"#);
+        let router = Router::load(&path).unwrap();
+        assert_eq!(route(&router, "a").map(|r| r.primary.port), Some(2001));
+
+        fs::write(&path, r#"{"tenants": [{"token": "a", "port": 2001, "data_dir": "/tmp/a"},
+                                          {"token": "b", "port": 2002, "data_dir": "/tmp/b"}]}"#).unwrap();
+        assert_eq!(router.reload("test"), Ok(2));
+        assert_eq!(route(&router, "b").map(|r| r.primary.port), Some(2002));
+
+        // duplicate tokens and unparseable files are rejected without touching the table
+        fs::write(&path, r#"{"tenants": [{"token": "c", "port": 2003, "data_dir": "/tmp/c"},
+                                          {"token": "c", "port": 2004, "data_dir": "/tmp/c"}]}"#).unwrap();
+        assert!(router.reload("test").unwrap_err().contains("Duplicate token"));
+        fs::write(&path, "{").unwrap();
+        assert!(router.reload("test").is_err());
+        assert!(!router.changed_on_disk());
+        assert_eq!(router.tenant_count(), 2);
+        assert_eq!(route(&router, "c"), None);
+    }
+
+    #[test]
+    fn test_fairness_limits() {
+        let dir = TempDir::new("router-limits");
+        let path = dir.write("router.json", r#"{"max_connections": 2, "max_in_flight_per_tenant": 2, "tenants": [
+            {"token": "noisy", "port": 2001, "data_dir": "/tmp/a", "max_in_flight": 1},
+            {"token": "quiet", "port": 2002, "data_dir": "/tmp/b"}]}"#);
+        let router = Arc::new(Router::load(&path).unwrap());
+
+        // a tenant at its limit is refused without affecting anyone else, and gets its slot back on drop
+        let noisy = route(&router, "noisy").unwrap();
+        let quiet = route(&router, "quiet").unwrap();
+        assert_eq!(quiet.max_in_flight, 2);
+        let first = router.begin_request("noisy", &noisy, &noisy.primary);
+        assert!(first.is_some());
+        assert!(router.begin_request("noisy", &noisy, &noisy.primary).is_none());
+        let _q1 = router.begin_request("quiet", &quiet, &quiet.primary).unwrap();
+        let _q2 = router.begin_request("quiet", &quiet, &quiet.primary).unwrap();
+        assert!(router.begin_request("quiet", &quiet, &quiet.primary).is_none());
+        drop(first);
+        assert!(router.begin_request("noisy", &noisy, &noisy.primary).is_some());
+        assert_eq!(noisy.name, "port 2001");
+
+        let a = router.admit_connection();
+        let b = router.admit_connection();
+        assert!(a.is_some() && b.is_some());
+        assert!(router.admit_connection().is_none());
+        drop(a);
+        assert!(router.admit_connection().is_some());
+
+        fs::write(&path, r#"{"max_in_flight_per_tenant": 0, "tenants": []}"#).unwrap();
+        assert!(router.reload("test").is_err());
+    }
+
+    #[test]
+    fn test_backend_goes_down_and_recovers() {
+        let dir = TempDir::new("router-health");
+        let path = dir.write("router.json", r#"{"tenants": [{"name": "alice", "token": "secret-a", "port": 2001, "data_dir": "/tmp/a"},
+                                          {"token": "secret-b", "port": 2002, "data_dir": "/tmp/b"}]}"#);
+        let router = Router::load(&path).unwrap();
+        let alice = route(&router, "secret-a").unwrap();
+        assert!(!router.backend_down("secret-a", &alice.primary));
+
+        router.record_health("secret-a", &alice, &alice.primary, Err("Connection refused".to_string()));
+        assert!(router.backend_down("secret-a", &alice.primary));
+        assert_eq!(router.pick_backend("secret-a", &alice, false), None);
+        let report: serde_json::Value = serde_json::from_str(&router.health_report()).unwrap();
+        assert_eq!(report["status"], "degraded");
+        assert_eq!(report["healthy"], 1);
+        assert_eq!(report["backends"][0]["name"], "alice");
+        assert_eq!(report["backends"][0]["consecutive_failures"], 1);
+        assert_eq!(report["backends"][0]["role"], "primary");
+        assert_eq!(report["backends"][1]["name"], "port 2002");
+        assert!(!router.health_report().contains("secret"));
+
+        // a tenant moved to another port starts over
+        assert!(!router.backend_down("secret-a", &BackendAddress { port: 2003, ..alice.primary.clone() }));
+
+        router.record_health("secret-a", &alice, &alice.primary, Ok(Duration::from_millis(3)));
+        assert!(!router.backend_down("secret-a", &alice.primary));
+        let report: serde_json::Value = serde_json::from_str(&router.health_report()).unwrap();
+        assert_eq!(report["status"], "ok");
+        assert_eq!(report["backends"][0]["latency_ms"], 3);
+    }
+
+    #[test]
+    fn test_backends_are_probed_in_parallel() {
+        // listeners that never answer: every probe runs into its timeout
+        let silent: Vec<std::net::TcpListener> = (0..3).map(|_| std::net::TcpListener::bind("127.0.0.1:0").unwrap()).collect();
+        let tenants: Vec<String> = silent.iter().enumerate()
+            .map(|(i, l)| format!(r#"{{"token": "t{}", "port": {}, "data_dir": "/tmp/t{}"}}"#, i, l.local_addr().unwrap().port(), i))
+            .collect();
+        let dir = TempDir::new("router-probes");
+        let path = dir.write("router.json", &format!(r#"{{"tenants": [{}]}}"#, tenants.join(", ")));
+        let router = Router::load(&path).unwrap();
+
+        let started = Instant::now();
+        router.check_backends();
+        assert!(started.elapsed() < Duration::from_secs(2 * PROBE_TIMEOUT_SECS));
+        let report: serde_json::Value = serde_json::from_str(&router.health_report()).unwrap();
+        assert_eq!(report["healthy"], 0);
+    }
+
+    #[test]
+    fn test_host_and_path_rules() {
+        let dir = TempDir::new("router-rules");
+        let path = dir.write("router.json", r#"{"tenants": [
+            {"name": "alice", "token": "tok-a", "port": 2001, "data_dir": "/tmp/a"},
+            {"name": "bob", "token": "tok-b", "port": 2002, "data_dir": "/tmp/b"}],
+          "routes": [
+            {"host": "docs.example.com", "tenant": "bob", "public": true, "phexts": ["manual"]},
+            {"path_prefix": "/t/alice", "tenant": "alice"}]}"#);
+        let router = Router::load(&path).unwrap();
+        let resolve = |request: &str| router.resolve(&request.replace('\n', "\r\n"));
+
+        // path prefix: stripped, and the tenant's own token is still required (in either header)
+        let ok = resolve("GET /t/alice/api/v2/toc?p=w HTTP/1.1\nX-SQ-API-Key: tok-a\nHost: sq.example.com\n\n").unwrap();
+        assert_eq!(ok.route.primary.port, 2001);
+        assert!(ok.header.starts_with("GET /api/v2/toc?p=w HTTP/1.1\r\n"));
+        assert!(ok.header.ends_with("Authorization: Bearer tok-a\r\n\r\n"));
+        assert!(!ok.header.contains("X-SQ-API-Key"));
+        assert_eq!(resolve("GET /t/alice HTTP/1.1\nAuthorization: tok-a\n\n").unwrap().header.lines().next(), Some("GET / HTTP/1.1"));
+        assert_eq!(resolve("GET /t/alice/api/v2/toc HTTP/1.1\nAuthorization: tok-b\n\n").unwrap_err().0, 401);
+        assert_eq!(resolve("GET /t/alice/api/v2/toc HTTP/1.1\n\n").unwrap_err().0, 401);
+        // "/t/alicex" isn't under "/t/alice", so it falls through to token routing
+        assert_eq!(resolve("GET /t/alicex HTTP/1.1\nAuthorization: tok-b\n\n").unwrap().route.primary.port, 2002);
+
+        // public host: token-less reads of listed phexts only
+        let public = resolve("GET /api/v2/select?p=manual&c=1.1.1/1.1.1/1.1.1 HTTP/1.1\nHost: Docs.Example.com:8080\n\n").unwrap();
+        assert_eq!(public.route.primary.port, 2002);
+        assert!(public.header.contains("Authorization: Bearer tok-b"));
+        assert!(resolve("GET /api/v2/select?p=private HTTP/1.1\nHost: docs.example.com\n\n").is_err());
+        assert!(resolve("GET /api/v2/insert?p=manual&s=x HTTP/1.1\nHost: docs.example.com\n\n").is_err());
+        assert!(resolve("POST /api/v2/delta?p=manual HTTP/1.1\nHost: docs.example.com\n\n").is_err());
+        assert!(resolve("GET /api/v2/insert?p=manual&s=x HTTP/1.1\nHost: docs.example.com\nAuthorization: tok-b\n\n").is_ok());
+        // a second query smuggled after a fake version never reaches the backend
+        let smuggled = "GET /api/v2/get?p=manual HTTP/1.1&p=secret HTTP/1.1\nHost: docs.example.com\n\n";
+        assert_eq!(resolve(smuggled).unwrap_err().0, 400);
+        assert!(crate::request_parse(&http::HttpRequest { header: smuggled.replace('\n', "\r\n"), content: Vec::new() }).is_none());
+        assert_eq!(resolve("GET /api/v2/json-export?p=manual HTTP/1.1\nHost: docs.example.com\n\n").unwrap_err().0, 401);
+
+        fs::write(&path, r#"{"tenants": [{"token": "tok-a", "port": 2001, "data_dir": "/tmp/a"}],
+                             "routes": [{"path_prefix": "/t/a", "tenant": "nobody"}]}"#).unwrap();
+        assert!(router.reload("test").unwrap_err().contains("must name exactly one tenant"));
+    }
+
+    #[test]
+    fn test_replicas_share_reads_but_not_writes() {
+        let dir = TempDir::new("router-replicas");
+        let path = dir.write("router.json", r#"{"tenants": [
+            {"name": "rr", "token": "a", "port": 2001, "data_dir": "/tmp/a",
+             "replicas": [{"host": "10.0.0.2", "port": 2001}, {"port": 2011}]},
+            {"name": "lc", "token": "b", "port": 2002, "data_dir": "/tmp/b", "balance": "least-connections",
+             "replicas": [{"port": 2012}]}]}"#);
+        let router = Router::load(&path).unwrap();
+        let rr = route(&router, "a").unwrap();
+        let picks: Vec<String> = (0..4).map(|_| router.pick_backend("a", &rr, false).unwrap().to_string()).collect();
+        assert_eq!(picks, vec!["127.0.0.1:2001", "10.0.0.2:2001", "127.0.0.1:2011", "127.0.0.1:2001"]);
+        assert_eq!(router.pick_backend("a", &rr, true), Some(rr.primary.clone()));
+
+        // a replica that is down drops out of the rotation; writes wait for the primary
+        router.record_health("a", &rr, &rr.replicas[0], Err("Connection refused".to_string()));
+        assert!((0..4).all(|_| router.pick_backend("a", &rr, false) != Some(rr.replicas[0].clone())));
+        router.record_health("a", &rr, &rr.primary, Err("Connection refused".to_string()));
+        assert_eq!(router.pick_backend("a", &rr, false), Some(rr.replicas[1].clone()));
+        assert_eq!(router.pick_backend("a", &rr, true), None);
+
+        // least-connections: reads go where fewer requests are in flight
+        let lc = route(&router, "b").unwrap();
+        let _busy = router.begin_request("b", &lc, &lc.primary).unwrap();
+        assert!((0..3).all(|_| router.pick_backend("b", &lc, false) == Some(lc.replicas[0].clone())));
+
+        let report: serde_json::Value = serde_json::from_str(&router.health_report()).unwrap();
+        assert_eq!(report["tenants"], 2);
+        assert_eq!(report["backends"].as_array().unwrap().len(), 5);
+        assert_eq!(report["healthy"], 3);
+
+        // writes are whatever the host runs as a mutation, matched by prefix like the host does
+        assert!(is_write("/api/v2/insert?p=w&c=1.1.1/1.1.1/1.1.1&s=x"));
+        assert!(is_write("/api/v2/json-import?p=w"));
+        assert!(is_write("/api/v2/deletex?p=w"));
+        assert!(is_write("/"));
+        assert!(!is_write("/api/v2/select?p=insert"));
+        assert!(!is_write("/api/v2/mesh/versions"));
+
+        fs::write(&path, r#"{"tenants": [{"token": "a", "port": 2001, "data_dir": "/tmp/a", "replicas": [{"port": 2001}]}]}"#).unwrap();
+        assert!(router.reload("test").unwrap_err().contains("listed twice"));
+    }
+
+    #[test]
+    fn test_remote_and_socket_backends() {
+        let dir = TempDir::new("router-remote");
+        let path = dir.path("router.json");
+        let socket = dir.path("alice.sock");
+        fs::write(&path, format!(r#"{{"tenants": [
+            {{"name": "alice", "token": "a", "socket": "{}", "read_timeout_ms": 250}},
+            {{"name": "bob", "token": "b", "host": "db.internal", "port": 1338, "connect_timeout_ms": 1000,
+              "replicas": [{{"host": "::1", "port": 1338}}]}}]}}"#, socket)).unwrap();
+        let router = Router::load(&path).unwrap();
+        let alice = route(&router, "a").unwrap();
+        let bob = route(&router, "b").unwrap();
+        assert_eq!(alice.primary.to_string(), format!("unix:{}", socket));
+        assert_eq!(alice.timeouts.read, Duration::from_millis(250));
+        assert_eq!(alice.timeouts.connect, Duration::from_millis(DEFAULT_CONNECT_TIMEOUT_MS));
+        assert_eq!(bob.primary.to_string(), "db.internal:1338");
+        assert_eq!(bob.replicas[0].to_string(), "[::1]:1338");
+        assert_eq!(bob.timeouts.connect, Duration::from_secs(1));
+
+        // a missing socket is a connect failure; a listening one is probed like any backend
+        assert!(probe(&alice.primary, "a").is_err());
+        #[cfg(unix)]
+        {
+            let listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+            let server = std::thread::spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                let request = http::MessageReader::new(MAX_HEADER_SIZE, 0).next_message(&mut stream).unwrap().unwrap();
+                let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n0.5.6");
+                request.header
+            });
+            assert!(probe(&alice.primary, "a").is_ok());
+            assert!(server.join().unwrap().contains("Authorization: Bearer a\r\n"));
+        }
+
+        // supervise only runs local backends (a loopback port or a socket), and every backend needs somewhere to connect to
+        fs::write(&path, r#"{"supervise": true, "tenants": [{"token": "b", "host": "db.internal", "port": 1338, "data_dir": "/tmp/b"}]}"#).unwrap();
+        assert!(router.reload("test").unwrap_err().contains("supervise only runs local backends"));
+        fs::write(&path, format!(r#"{{"supervise": true, "tenants": [{{"token": "a", "socket": "{}", "data_dir": "/tmp/a"}},
+            {{"token": "b", "socket": "{}", "data_dir": "/tmp/b"}}]}}"#, socket, socket)).unwrap();
+        assert!(load_router_config(&path).unwrap_err().to_string().contains("Duplicate backend in config"));
+        fs::write(&path, r#"{"tenants": [{"token": "b", "host": "db.internal"}]}"#).unwrap();
+        assert!(router.reload("test").unwrap_err().contains("needs a host and a port, or a socket"));
+        fs::write(&path, r#"{"tenants": [{"token": "b", "port": 1338, "write_timeout_ms": 0}]}"#).unwrap();
+        assert!(router.reload("test").unwrap_err().contains("Timeouts must be at least 1 ms"));
+    }
 }
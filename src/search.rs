@@ -0,0 +1,233 @@
+//------------------------------------------------------------------------------------------------------------
+// file: search.rs
+// purpose: inverted index for full-text search over the scrolls of a loaded phext
+//
+// Terms are lowercased runs of alphanumeric characters. Each term maps to the ordered set of scrolls
+// (coord_sort_key) that contain it, so results come back in hierarchy order without sorting.
+//
+// query syntax (every clause must match):
+//   word          scrolls containing the word
+//   pre*          scrolls containing a word starting with "pre"
+//   "two words"   scrolls containing the exact phrase
+//------------------------------------------------------------------------------------------------------------
+
+use crate::store::SortKey;
+use std::collections::{BTreeMap, BTreeSet};
+
+const SNIPPET_RADIUS: usize = 40; // characters of context on each side of the first match
+
+// -----------------------------------------------------------------------------------------------------------
+// tokenize: (byte offset, lowercased term) for every word in `text`
+// -----------------------------------------------------------------------------------------------------------
+pub fn tokenize(text: &str) -> Vec<(usize, String)> {
+    let mut tokens = Vec::new();
+    let mut start: Option<usize> = None;
+    for (i, ch) in text.char_indices() {
+        if ch.is_alphanumeric() {
+            if start.is_none() {
+                start = Some(i);
+            }
+        } else if let Some(s) = start.take() {
+            tokens.push((s, text[s..i].to_lowercase()));
+        }
+    }
+    if let Some(s) = start {
+        tokens.push((s, text[s..].to_lowercase()));
+    }
+    tokens
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Clause {
+    Term(String),
+    Prefix(String),
+    Phrase(Vec<String>),
+}
+
+impl Clause {
+    fn matches_token(&self, token: &str) -> bool {
+        match self {
+            Clause::Term(t) => token == t,
+            Clause::Prefix(p) => token.starts_with(p.as_str()),
+            Clause::Phrase(words) => words.first().map(|w| w == token).unwrap_or(false),
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// parse_query: splits a query into clauses (bare words, prefix* words, and "quoted phrases")
+// -----------------------------------------------------------------------------------------------------------
+pub fn parse_query(query: &str) -> Vec<Clause> {
+    let mut clauses = Vec::new();
+    let mut rest = query.trim();
+    while !rest.is_empty() {
+        let (chunk, quoted, remainder) = if let Some(stripped) = rest.strip_prefix('"') {
+            match stripped.find('"') {
+                Some(end) => (&stripped[..end], true, &stripped[end + 1..]),
+                None => (stripped, true, ""),
+            }
+        } else {
+            match rest.find(char::is_whitespace) {
+                Some(end) => (&rest[..end], false, &rest[end..]),
+                None => (rest, false, ""),
+            }
+        };
+        rest = remainder.trim_start();
+
+        let words: Vec<String> = tokenize(chunk).into_iter().map(|(_, t)| t).collect();
+        if words.is_empty() {
+            continue;
+        }
+        if !quoted && chunk.ends_with('*') && words.len() == 1 {
+            clauses.push(Clause::Prefix(words[0].clone()));
+        } else if words.len() == 1 {
+            clauses.push(Clause::Term(words[0].clone()));
+        } else {
+            // quoted text, or a hyphenated/punctuated word that tokenizes into several terms
+            clauses.push(Clause::Phrase(words));
+        }
+    }
+    clauses
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// contains_phrase: true when `words` appear consecutively in the scroll
+// -----------------------------------------------------------------------------------------------------------
+fn contains_phrase(text: &str, words: &[String]) -> bool {
+    let tokens: Vec<String> = tokenize(text).into_iter().map(|(_, t)| t).collect();
+    tokens.windows(words.len()).any(|w| w == words)
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// snippet: up to SNIPPET_RADIUS characters either side of the first matching word, on one line
+// -----------------------------------------------------------------------------------------------------------
+pub fn snippet(text: &str, clauses: &[Clause]) -> String {
+    let position = tokenize(text).into_iter()
+        .find(|(_, token)| clauses.iter().any(|c| c.matches_token(token)))
+        .map(|(offset, _)| offset)
+        .unwrap_or(0);
+
+    let before: Vec<(usize, char)> = text[..position].char_indices().collect();
+    let start = if before.len() > SNIPPET_RADIUS { before[before.len() - SNIPPET_RADIUS].0 } else { 0 };
+    let end = text[position..].char_indices()
+        .nth(SNIPPET_RADIUS)
+        .map(|(i, _)| position + i)
+        .unwrap_or(text.len());
+
+    let mut result = String::new();
+    if start > 0 {
+        result.push_str("...");
+    }
+    result.push_str(&text[start..end].split_whitespace().collect::<Vec<&str>>().join(" "));
+    if end < text.len() {
+        result.push_str("...");
+    }
+    result
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// SearchIndex: term → scrolls containing it
+// -----------------------------------------------------------------------------------------------------------
+#[derive(Default)]
+pub struct SearchIndex {
+    terms: BTreeMap<String, BTreeSet<SortKey>>,
+}
+
+impl SearchIndex {
+    pub fn add(&mut self, key: SortKey, text: &str) {
+        for (_, term) in tokenize(text) {
+            self.terms.entry(term).or_default().insert(key);
+        }
+    }
+
+    pub fn remove(&mut self, key: SortKey, text: &str) {
+        for (_, term) in tokenize(text) {
+            if let Some(postings) = self.terms.get_mut(&term) {
+                postings.remove(&key);
+                if postings.is_empty() {
+                    self.terms.remove(&term);
+                }
+            }
+        }
+    }
+
+    fn postings(&self, clause: &Clause) -> BTreeSet<SortKey> {
+        match clause {
+            Clause::Term(term) => self.terms.get(term).cloned().unwrap_or_default(),
+            Clause::Prefix(prefix) => self.terms.range(prefix.clone()..)
+                .take_while(|(term, _)| term.starts_with(prefix.as_str()))
+                .flat_map(|(_, postings)| postings.iter().copied())
+                .collect(),
+            Clause::Phrase(words) => {
+                let mut sets = words.iter().map(|w| self.terms.get(w).cloned().unwrap_or_default());
+                let first = sets.next().unwrap_or_default();
+                sets.fold(first, |acc, set| acc.intersection(&set).copied().collect())
+            }
+        }
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // candidates: scrolls that contain every clause's terms, in hierarchy order
+    //   phrases are only narrowed to scrolls containing all their words; callers verify adjacency
+    // -------------------------------------------------------------------------------------------------------
+    pub fn candidates(&self, clauses: &[Clause]) -> BTreeSet<SortKey> {
+        let mut sets: Vec<BTreeSet<SortKey>> = clauses.iter().map(|c| self.postings(c)).collect();
+        sets.sort_by_key(|s| s.len());
+        let mut iter = sets.into_iter();
+        let first = iter.next().unwrap_or_default();
+        iter.fold(first, |acc, set| acc.intersection(&set).copied().collect())
+    }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// verify: confirms phrase clauses against the scroll text
+// -----------------------------------------------------------------------------------------------------------
+pub fn verify(text: &str, clauses: &[Clause]) -> bool {
+    clauses.iter().all(|clause| match clause {
+        Clause::Phrase(words) => contains_phrase(text, words),
+        _ => true,
+    })
+}
+
+#[cfg(test)]
+mod search_tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        let tokens: Vec<String> = tokenize("Hello, Phext-World! 42").into_iter().map(|(_, t)| t).collect();
+        assert_eq!(tokens, vec!["hello", "phext", "world", "42"]);
+    }
+
+    #[test]
+    fn test_parse_query() {
+        assert_eq!(parse_query("mind \"prefix firewalling\" exo*"), vec![
+            Clause::Term("mind".to_string()),
+            Clause::Phrase(vec!["prefix".to_string(), "firewalling".to_string()]),
+            Clause::Prefix("exo".to_string()),
+        ]);
+        assert!(parse_query("  ,, ").is_empty());
+    }
+
+    #[test]
+    fn test_index_add_remove() {
+        let mut index = SearchIndex::default();
+        index.add([1; 9], "alpha beta");
+        index.add([2; 9], "beta gamma");
+        assert_eq!(index.candidates(&parse_query("beta")).len(), 2);
+        assert_eq!(index.candidates(&parse_query("beta gamma")).len(), 1);
+        index.remove([2; 9], "beta gamma");
+        assert_eq!(index.candidates(&parse_query("beta")).len(), 1);
+        assert!(index.candidates(&parse_query("gam*")).is_empty());
+    }
+
+    #[test]
+    fn test_snippet() {
+        let text = format!("{} needle {}", "a".repeat(100), "b".repeat(100));
+        let s = snippet(&text, &parse_query("needle"));
+        assert!(s.starts_with("..."));
+        assert!(s.ends_with("..."));
+        assert!(s.contains("needle"));
+        assert_eq!(snippet("short needle", &parse_query("needle")), "short needle");
+    }
+}
@@ -0,0 +1,342 @@
+//------------------------------------------------------------------------------------------------------------
+// file: socket.rs
+// purpose: the sockets sq talks HTTP over - TCP, or unix domain sockets for clients on the same machine
+//
+// A unix socket listener (`sq host --socket <path>`, `sq route ... --socket <path>`) serves exactly the requests
+// a TCP listener does; who may connect is decided by the socket file's permissions instead of the network.
+// The file is created with SOCKET_MODE (owner and group may connect), so run the router and its backends as
+// one user or share a group - a directory only they can enter narrows it further. A socket file left behind
+// by a server that is gone is replaced; a live socket or any other kind of file at the path is an error.
+//
+// TCP listeners bind 0.0.0.0:<port> unless given `--bind <addr>` (repeatable). An IPv6 listener only takes IPv6
+// clients, so `--bind 0.0.0.0 --bind ::` listens on both families without the second bind failing.
+//------------------------------------------------------------------------------------------------------------
+
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+#[cfg(unix)]
+pub const SOCKET_MODE: u32 = 0o660;
+
+// -----------------------------------------------------------------------------------------------------------
+// Stream: one connection, either way round (a client accepted by a server, or the router's backend connection)
+// -----------------------------------------------------------------------------------------------------------
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    // -------------------------------------------------------------------------------------------------------
+    // connect_tcp: connects to the first address `host` resolves to that accepts within `timeout`
+    // -------------------------------------------------------------------------------------------------------
+    pub fn connect_tcp(host: &str, port: u16, timeout: Duration) -> std::io::Result<Stream> {
+        let mut last_error = std::io::Error::new(std::io::ErrorKind::NotFound, format!("cannot resolve {}", host));
+        for address in (host, port).to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, timeout) {
+                Ok(stream) => return Ok(Stream::Tcp(stream)),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    pub fn connect_unix(path: &str) -> std::io::Result<Stream> {
+        #[cfg(unix)]
+        return Ok(Stream::Unix(UnixStream::connect(path)?));
+        #[cfg(not(unix))]
+        return Err(unsupported(path));
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // peek: reads without consuming, as TcpStream::peek (UnixStream's is still unstable)
+    // -------------------------------------------------------------------------------------------------------
+    pub fn peek(&self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Tcp(s) => s.peek(buf),
+            #[cfg(unix)]
+            Stream::Unix(s) => {
+                use std::os::unix::io::AsRawFd;
+                let read = unsafe { libc::recv(s.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void, buf.len(), libc::MSG_PEEK) };
+                if read < 0 { Err(std::io::Error::last_os_error()) } else { Ok(read as usize) }
+            }
+        }
+    }
+
+    pub fn read_timeout(&self) -> std::io::Result<Option<Duration>> {
+        match self {
+            Stream::Tcp(s) => s.read_timeout(),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.read_timeout(),
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        match self {
+            Stream::Tcp(s) => s.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.set_read_timeout(timeout),
+        }
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        match self {
+            Stream::Tcp(s) => s.set_write_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.set_write_timeout(timeout),
+        }
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // is_local: true for a loopback TCP peer, and for any unix socket peer (the file permissions let it in)
+    // -------------------------------------------------------------------------------------------------------
+    pub fn is_local(&self) -> bool {
+        match self {
+            Stream::Tcp(s) => s.peer_addr().map(|addr| addr.ip().to_canonical().is_loopback()).unwrap_or(false),
+            #[cfg(unix)]
+            Stream::Unix(_) => true,
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Tcp(s) => s.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Tcp(s) => s.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Stream::Tcp(s) => s.flush(),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.flush(),
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// listen_address: parses a --bind value - an IP (`127.0.0.1`, `::1`, `[::1]`) listening on `port`, or `ip:port`
+// -----------------------------------------------------------------------------------------------------------
+pub fn listen_address(bind: &str, port: Option<u16>) -> Result<SocketAddr, String> {
+    if let Ok(address) = bind.parse::<SocketAddr>() {
+        return Ok(address);
+    }
+    let ip: IpAddr = bind.trim_start_matches('[').trim_end_matches(']').parse()
+        .map_err(|_| format!("--bind {}: expected an IP address or address:port", bind))?;
+    match port {
+        Some(port) => Ok(SocketAddr::new(ip, port)),
+        None if ip.is_ipv6() => Err(format!("--bind {} needs a port: [{}]:<port>", bind, ip)),
+        None => Err(format!("--bind {} needs a port: {}:<port>", bind, ip)),
+    }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// bind_v6_only: TcpListener::bind for an IPv6 address, with IPV6_V6ONLY set before binding (std can't do that)
+// -----------------------------------------------------------------------------------------------------------
+#[cfg(unix)]
+fn bind_v6_only(address: std::net::SocketAddrV6) -> std::io::Result<TcpListener> {
+    use std::os::unix::io::FromRawFd;
+    fn check(result: libc::c_int) -> std::io::Result<()> {
+        if result < 0 { Err(std::io::Error::last_os_error()) } else { Ok(()) }
+    }
+    let on: libc::c_int = 1;
+    let on_size = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    unsafe {
+        let fd = libc::socket(libc::AF_INET6, libc::SOCK_STREAM, 0);
+        check(fd)?;
+        let listener = TcpListener::from_raw_fd(fd); // closes the socket if anything below fails
+        check(libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC))?;
+        check(libc::setsockopt(fd, libc::IPPROTO_IPV6, libc::IPV6_V6ONLY, &on as *const libc::c_int as *const libc::c_void, on_size))?;
+        check(libc::setsockopt(fd, libc::SOL_SOCKET, libc::SO_REUSEADDR, &on as *const libc::c_int as *const libc::c_void, on_size))?;
+        let mut sockaddr: libc::sockaddr_in6 = std::mem::zeroed();
+        sockaddr.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+        sockaddr.sin6_port = address.port().to_be();
+        sockaddr.sin6_addr.s6_addr = address.ip().octets();
+        sockaddr.sin6_flowinfo = address.flowinfo();
+        sockaddr.sin6_scope_id = address.scope_id();
+        check(libc::bind(fd, &sockaddr as *const libc::sockaddr_in6 as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t))?;
+        check(libc::listen(fd, 128))?;
+        Ok(listener)
+    }
+}
+
+#[cfg(not(unix))]
+fn unsupported(path: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Unsupported, format!("unix sockets are not supported here ({})", path))
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// Listener: a bound TCP port or unix socket
+// -----------------------------------------------------------------------------------------------------------
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, String),
+}
+
+impl Listener {
+    pub fn bind_tcp(address: SocketAddr) -> std::io::Result<Listener> {
+        #[cfg(unix)]
+        if let SocketAddr::V6(address) = address {
+            return Ok(Listener::Tcp(bind_v6_only(address)?));
+        }
+        Ok(Listener::Tcp(TcpListener::bind(address)?))
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // bind_unix: creates the socket file at `path` with SOCKET_MODE, replacing a stale one
+    // -------------------------------------------------------------------------------------------------------
+    pub fn bind_unix(path: &str) -> std::io::Result<Listener> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+            if let Ok(metadata) = std::fs::symlink_metadata(path) {
+                let in_use = || std::io::Error::new(std::io::ErrorKind::AddrInUse, format!("{} is already being served", path));
+                if !metadata.file_type().is_socket() {
+                    return Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, format!("{} exists and is not a socket", path)));
+                }
+                if UnixStream::connect(path).is_ok() {
+                    return Err(in_use());
+                }
+                std::fs::remove_file(path)?;
+            }
+            // created owner-only so nobody can connect before the mode is set; the umask is process-wide, but
+            // listeners are bound at startup before any other thread creates files
+            let umask = unsafe { libc::umask(0o077) };
+            let bound = UnixListener::bind(path);
+            unsafe { libc::umask(umask) };
+            let listener = bound?;
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(SOCKET_MODE))?;
+            Ok(Listener::Unix(listener, path.to_string()))
+        }
+        #[cfg(not(unix))]
+        Err(unsupported(path))
+    }
+
+    pub fn accept(&self) -> std::io::Result<Stream> {
+        match self {
+            Listener::Tcp(l) => l.accept().map(|(s, _)| Stream::Tcp(s)),
+            #[cfg(unix)]
+            Listener::Unix(l, _) => l.accept().map(|(s, _)| Stream::Unix(s)),
+        }
+    }
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Listener::Tcp(l) => match l.local_addr() {
+                Ok(address) => write!(f, "{}", address),
+                Err(_) => write!(f, "tcp"),
+            },
+            #[cfg(unix)]
+            Listener::Unix(_, path) => write!(f, "unix:{}", path),
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// serve: hands every connection accepted on any of `listeners` to `on_connection`
+//   each listener but the last gets its own accept thread; the last one runs on the calling thread
+// -----------------------------------------------------------------------------------------------------------
+pub fn serve<F: Fn(Stream) + Send + Sync + 'static>(mut listeners: Vec<Listener>, on_connection: F) {
+    let on_connection = std::sync::Arc::new(on_connection);
+    let last = match listeners.pop() {
+        Some(listener) => listener,
+        None => return,
+    };
+    for listener in listeners {
+        let on_connection = std::sync::Arc::clone(&on_connection);
+        std::thread::spawn(move || accept_loop(&listener, on_connection.as_ref()));
+    }
+    accept_loop(&last, on_connection.as_ref());
+}
+
+fn accept_loop<F: Fn(Stream)>(listener: &Listener, on_connection: &F) {
+    loop {
+        match listener.accept() {
+            Ok(stream) => on_connection(stream),
+            Err(e) => eprintln!("Accept error on {}: {}", listener, e),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unix_listener_permissions_and_stale_files() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = crate::tests::TempDir::new("socket");
+        let path = dir.path("sq.sock");
+
+        let listener = Listener::bind_unix(&path).unwrap();
+        assert_eq!(listener.to_string(), format!("unix:{}", path));
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, SOCKET_MODE);
+
+        // peeking leaves the bytes for the reader
+        let mut client = Stream::connect_unix(&path).unwrap();
+        let mut accepted = listener.accept().unwrap();
+        assert!(accepted.is_local());
+        client.write_all(b"GET").unwrap();
+        let mut buf = [0u8; 3];
+        assert_eq!(accepted.peek(&mut buf[..1]).unwrap(), 1);
+        accepted.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"GET");
+
+        // a live socket isn't taken over
+        assert!(Listener::bind_unix(&path).is_err());
+
+        // once its server is gone, the file is stale and replaced
+        drop(listener);
+        assert!(Listener::bind_unix(&path).is_ok());
+
+        let file = dir.write("not-a-socket", "x");
+        assert!(Listener::bind_unix(&file).is_err());
+    }
+
+    #[test]
+    fn test_bind_addresses() {
+        assert_eq!(listen_address("127.0.0.1", Some(1338)).unwrap().to_string(), "127.0.0.1:1338");
+        assert_eq!(listen_address("::1", Some(1338)).unwrap().to_string(), "[::1]:1338");
+        assert_eq!(listen_address("[::]", Some(1338)).unwrap().to_string(), "[::]:1338");
+        assert_eq!(listen_address("[::1]:8080", Some(1338)).unwrap().to_string(), "[::1]:8080");
+        assert_eq!(listen_address("10.0.0.5:8080", None).unwrap().to_string(), "10.0.0.5:8080");
+        assert!(listen_address("::1", None).unwrap_err().contains("[::1]:<port>"));
+        assert!(listen_address("localhost", Some(1338)).is_err());
+
+        // one port on both families: the IPv6 listener leaves IPv4 to the other
+        let v4 = Listener::bind_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+        let port = match v4 { Listener::Tcp(ref l) => l.local_addr().unwrap().port(), _ => unreachable!() };
+        let v6 = match Listener::bind_tcp(SocketAddr::new("::".parse().unwrap(), port)) {
+            Ok(listener) => listener,
+            Err(_) => return, // no IPv6 here
+        };
+        assert_eq!(v6.to_string(), format!("[::]:{}", port));
+        let client = Stream::connect_tcp("::1", port, Duration::from_secs(1)).unwrap();
+        assert!(v6.accept().unwrap().is_local());
+        drop(client);
+        assert!(Listener::bind_tcp(SocketAddr::new("::".parse().unwrap(), port)).is_err());
+    }
+}
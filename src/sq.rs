@@ -5,7 +5,12 @@
 // SQ leverages libphext-rs to provide a minimal hierarchical database.
 //------------------------------------------------------------------------------------------------------------
 use crate::phext;
+use crate::store::ScrollStore;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::ops::Bound;
+
+pub const MISSING_SCROLL: &str = "---sq:Scroll-Missing---"; // delta marker: the caller has a scroll we don't
 
 pub fn args_required(command:&str) -> usize {
     if command == "shutdown" ||
@@ -16,29 +21,536 @@ pub fn args_required(command:&str) -> usize {
         return 2;
     }
 
-    return 3;
+    if command == "select-range" {
+        return 4;
+    }
+
+    3
+}
+
+const DEFAULT_PAGE_SIZE: usize = 100;
+const MAX_PAGE_SIZE: usize = 10_000;
+
+//------------------------------------------------------------------------------------------------------------
+// ScrollRecord: one entry of the json-export / json-import schema
+//   [ { "coord": "1.1.1/1.1.1/1.1.1", "scroll": "..." }, ... ]
+//------------------------------------------------------------------------------------------------------------
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ScrollRecord {
+    coord: String,
+    scroll: String,
+}
+
+//------------------------------------------------------------------------------------------------------------
+// json_export: one record per line, escaped by serde so every byte round-trips through json-import
+//------------------------------------------------------------------------------------------------------------
+fn json_export(phext_map: &ScrollStore) -> String {
+    let records: Vec<String> = phext_map.iter()
+        .map(|(c, v)| {
+            let record = ScrollRecord { coord: c.to_string(), scroll: v.clone() };
+            format!("   {}", serde_json::to_string(&record).unwrap_or_default())
+        })
+        .collect();
+    if records.is_empty() {
+        return "[\n]\n".to_string();
+    }
+    format!("[\n{}\n]\n", records.join(",\n"))
+}
+
+//------------------------------------------------------------------------------------------------------------
+// parse_coordinate: strict coordinate parsing for imported records
+//   to_coordinate is lenient about junk, so the parsed value must also print back to the same text
+//------------------------------------------------------------------------------------------------------------
+fn parse_coordinate(text: &str) -> Option<phext::Coordinate> {
+    let c = phext::to_coordinate(text);
+    if c.validate_coordinate() && c.to_string() == text.trim() {
+        return Some(c);
+    }
+    None
 }
 
 //------------------------------------------------------------------------------------------------------------
-// json_escape: simple wrapper to avoid breaking json-export
+// json_import: loads json-export output back into the store
+//
+//   update slot: an optional `mode=merge|replace` line, followed by the JSON array
+//   merge    - each valid record overwrites its scroll; invalid records are reported and skipped
+//   replace  - the store becomes exactly the imported records; any invalid record aborts the import
+//
+// Returns a JSON report: { "mode", "imported", "errors": [{ "index", "coord", "error" }...] }
 //------------------------------------------------------------------------------------------------------------
-fn json_escape(input: String) -> String {
-    let mut result = input;
-    result = result.replace('"', "\\\"");
-    result = result.replace('\n', "\\n");
-    return result;
+fn json_import(phext_map: &mut ScrollStore, update: &str) -> String {
+    let (mode, document) = match update.strip_prefix("mode=") {
+        Some(rest) => {
+            let (mode, document) = rest.split_once('\n').unwrap_or((rest, ""));
+            (mode.trim(), document)
+        }
+        None => ("merge", update),
+    };
+    if mode != "merge" && mode != "replace" {
+        return serde_json::json!({ "error": format!("Unknown import mode: {}", mode) }).to_string();
+    }
+
+    let values: Vec<serde_json::Value> = match serde_json::from_str(document) {
+        Ok(v) => v,
+        Err(e) => return serde_json::json!({ "error": format!("Expected a JSON array of {{coord, scroll}} records: {}", e) }).to_string(),
+    };
+
+    let mut records: Vec<(phext::Coordinate, String)> = Vec::new();
+    let mut errors: Vec<serde_json::Value> = Vec::new();
+    for (index, value) in values.into_iter().enumerate() {
+        let coord = value.get("coord").and_then(|c| c.as_str()).unwrap_or_default().to_string();
+        let record: ScrollRecord = match serde_json::from_value(value) {
+            Ok(r) => r,
+            Err(e) => {
+                errors.push(serde_json::json!({ "index": index, "coord": coord, "error": e.to_string() }));
+                continue;
+            }
+        };
+        match parse_coordinate(&record.coord) {
+            Some(c) => records.push((c, record.scroll)),
+            None => errors.push(serde_json::json!({ "index": index, "coord": coord, "error": "invalid coordinate" })),
+        }
+    }
+
+    let apply = mode == "merge" || errors.is_empty();
+    if apply {
+        if mode == "replace" {
+            *phext_map = ScrollStore::new();
+        }
+        for (c, scroll) in records.iter() {
+            phext_map.insert(*c, scroll.clone());
+        }
+    }
+    let report = serde_json::json!({
+        "mode": mode,
+        "imported": if apply { records.len() } else { 0 },
+        "errors": errors,
+    });
+    report.to_string()
 }
 
 //------------------------------------------------------------------------------------------------------------
 // coord_sort_key: extracts a 9-component tuple for deterministic ordering
 //   hierarchy (highest to lowest): library, shelf, series, collection, volume, book, chapter, section, scroll
 //------------------------------------------------------------------------------------------------------------
-fn coord_sort_key(c: &phext::Coordinate) -> [usize; 9] {
+pub fn coord_sort_key(c: &phext::Coordinate) -> [usize; 9] {
     [c.z.library, c.z.shelf, c.z.series,
      c.y.collection, c.y.volume, c.y.book,
      c.x.chapter, c.x.section, c.x.scroll]
 }
 
+//------------------------------------------------------------------------------------------------------------
+// CoordinatePattern: a coordinate with wildcards, used for prefix selection (mind-map prefix firewalling)
+//
+// Components are either a number or a wildcard (`*` or `x`). A `*` standing alone for a whole group covers
+// all three of its components, and missing trailing groups are wildcards:
+//   2.1.1/*/*    every scroll in series 2.1.1
+//   5.x.x/*/*    everything in library 5
+//   1.1.1/1.1.1  every scroll under book 1.1.1/1.1.1
+//------------------------------------------------------------------------------------------------------------
+#[derive(Clone, Debug, PartialEq)]
+pub struct CoordinatePattern {
+    parts: [Option<usize>; 9],
+}
+
+impl CoordinatePattern {
+    pub fn parse(pattern: &str) -> Option<CoordinatePattern> {
+        let mut parts: [Option<usize>; 9] = [None; 9];
+        let groups: Vec<&str> = pattern.trim().split('/').collect();
+        if groups.is_empty() || groups.len() > 3 {
+            return None;
+        }
+        for (g, group) in groups.iter().enumerate() {
+            if *group == "*" {
+                continue;
+            }
+            let components: Vec<&str> = group.split('.').collect();
+            if components.len() > 3 {
+                return None;
+            }
+            for (i, component) in components.iter().enumerate() {
+                if *component == "*" || *component == "x" {
+                    continue;
+                }
+                let value: usize = component.parse().ok()?;
+                if !(phext::COORDINATE_MINIMUM..=phext::COORDINATE_MAXIMUM).contains(&value) {
+                    return None;
+                }
+                parts[g * 3 + i] = Some(value);
+            }
+        }
+        Some(CoordinatePattern { parts })
+    }
+
+    pub fn matches(&self, c: &phext::Coordinate) -> bool {
+        let key = coord_sort_key(c);
+        self.parts.iter().zip(key.iter()).all(|(part, value)| part.map(|p| p == *value).unwrap_or(true))
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // bounds: the smallest and largest sort keys this pattern can match
+    // -------------------------------------------------------------------------------------------------------
+    pub fn bounds(&self) -> ([usize; 9], [usize; 9]) {
+        let mut low = [1usize; 9];
+        let mut high = [phext::COORDINATE_MAXIMUM; 9];
+        for (i, part) in self.parts.iter().enumerate() {
+            if let Some(value) = part {
+                low[i] = *value;
+                high[i] = *value;
+            }
+        }
+        (low, high)
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // prefix / as_prefix: a subtree as a pattern (leading components fixed, the rest wildcards) and back
+    // -------------------------------------------------------------------------------------------------------
+    pub fn prefix(components: &[usize]) -> CoordinatePattern {
+        let mut parts: [Option<usize>; 9] = [None; 9];
+        for (part, component) in parts.iter_mut().zip(components.iter()) {
+            *part = Some(*component);
+        }
+        CoordinatePattern { parts }
+    }
+
+    pub fn as_prefix(&self) -> Option<Vec<usize>> {
+        let components: Vec<usize> = self.parts.iter().map_while(|p| *p).collect();
+        if self.parts[components.len()..].iter().any(|p| p.is_some()) {
+            return None;
+        }
+        Some(components)
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // intersect: the pattern matching what both patterns match (None if they are disjoint)
+    // -------------------------------------------------------------------------------------------------------
+    pub fn intersect(&self, other: &CoordinatePattern) -> Option<CoordinatePattern> {
+        let mut parts = self.parts;
+        for (part, theirs) in parts.iter_mut().zip(other.parts.iter()) {
+            match (*part, *theirs) {
+                (Some(a), Some(b)) if a != b => return None,
+                (None, Some(b)) => *part = Some(b),
+                _ => {}
+            }
+        }
+        Some(CoordinatePattern { parts })
+    }
+}
+
+impl std::fmt::Display for CoordinatePattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let parts: Vec<String> = self.parts.iter().map(|p| p.map(|v| v.to_string()).unwrap_or_else(|| "*".to_string())).collect();
+        write!(f, "{}/{}/{}", parts[0..3].join("."), parts[3..6].join("."), parts[6..9].join("."))
+    }
+}
+
+//------------------------------------------------------------------------------------------------------------
+// CoordinateFilter: include/exclude coordinate patterns scoping what mesh replication exchanges
+//
+// A scroll is in scope when it matches any include (or there are none) and no exclude. In a delta request
+// the filter travels as leading lines of the body:
+//   include: 5.x.x/*/*
+//   exclude: 5.9.x/*/*
+//------------------------------------------------------------------------------------------------------------
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CoordinateFilter {
+    include: Vec<CoordinatePattern>,
+    exclude: Vec<CoordinatePattern>,
+}
+
+impl CoordinateFilter {
+    pub fn parse<S: AsRef<str>>(include: &[S], exclude: &[S]) -> Result<CoordinateFilter, String> {
+        let patterns = |list: &[S]| -> Result<Vec<CoordinatePattern>, String> {
+            list.iter()
+                .map(|p| CoordinatePattern::parse(p.as_ref()).ok_or_else(|| format!("invalid coordinate pattern: {}", p.as_ref())))
+                .collect()
+        };
+        Ok(CoordinateFilter { include: patterns(include)?, exclude: patterns(exclude)? })
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // nothing: the filter no scroll is in scope of
+    // -------------------------------------------------------------------------------------------------------
+    pub fn nothing() -> CoordinateFilter {
+        CoordinateFilter { include: Vec::new(), exclude: vec![CoordinatePattern { parts: [None; 9] }] }
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // from_lines: picks the include:/exclude: lines out of a delta body
+    // -------------------------------------------------------------------------------------------------------
+    pub fn from_lines(text: &str) -> Result<CoordinateFilter, String> {
+        let mut include = Vec::new();
+        let mut exclude = Vec::new();
+        for line in text.lines() {
+            if let Some(pattern) = line.strip_prefix("include: ") {
+                include.push(pattern);
+            } else if let Some(pattern) = line.strip_prefix("exclude: ") {
+                exclude.push(pattern);
+            }
+        }
+        CoordinateFilter::parse(&include, &exclude)
+    }
+
+    pub fn is_filter_line(line: &str) -> bool {
+        line.starts_with("include: ") || line.starts_with("exclude: ")
+    }
+
+    pub fn to_lines(&self) -> String {
+        let mut lines = String::new();
+        for pattern in &self.include {
+            lines.push_str(&format!("include: {}\n", pattern));
+        }
+        for pattern in &self.exclude {
+            lines.push_str(&format!("exclude: {}\n", pattern));
+        }
+        lines
+    }
+
+    pub fn matches(&self, c: &phext::Coordinate) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches(c))) &&
+            !self.exclude.iter().any(|p| p.matches(c))
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // scan: the scrolls of `store` in scope; each include is scanned as a range, so narrow filters stay cheap
+    //   overlapping includes may yield a scroll more than once
+    // -------------------------------------------------------------------------------------------------------
+    pub fn scan<'a>(&'a self, store: &'a ScrollStore) -> Box<dyn Iterator<Item = (&'a phext::Coordinate, &'a String)> + 'a> {
+        let excluded = move |c: &phext::Coordinate| self.exclude.iter().any(|p| p.matches(c));
+        if self.include.is_empty() {
+            return Box::new(store.iter().filter(move |(c, _)| !excluded(c)));
+        }
+        Box::new(self.include.iter().flat_map(move |pattern| {
+            let (low, high) = pattern.bounds();
+            store.range(Bound::Included(low), Bound::Included(high))
+                .filter(move |(c, _)| pattern.matches(c) && !excluded(c))
+        }))
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // copy: a store holding just the scrolls of `store` in scope
+    // -------------------------------------------------------------------------------------------------------
+    pub fn copy(&self, store: &ScrollStore) -> ScrollStore {
+        let mut copy = ScrollStore::new();
+        for (c, scroll) in self.scan(store) {
+            copy.insert(*c, scroll.clone());
+        }
+        copy
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // restrict: the filter for scrolls in scope of both `self` and `other`
+    // -------------------------------------------------------------------------------------------------------
+    pub fn restrict(&self, other: &CoordinateFilter) -> CoordinateFilter {
+        let mut exclude = self.exclude.clone();
+        exclude.extend(other.exclude.iter().cloned());
+        let include = if self.include.is_empty() {
+            other.include.clone()
+        } else if other.include.is_empty() {
+            self.include.clone()
+        } else {
+            let overlap: Vec<CoordinatePattern> = self.include.iter()
+                .flat_map(|a| other.include.iter().filter_map(move |b| a.intersect(b)))
+                .collect();
+            if overlap.is_empty() {
+                // disjoint scopes: nothing is in scope
+                return CoordinateFilter::nothing();
+            }
+            overlap
+        };
+        CoordinateFilter { include, exclude }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------------
+// Selection: arguments for select-range / select-prefix, passed as a query string in the update slot
+//
+//   from=<coord>&to=<coord>         inclusive range (select-range); either end may be omitted
+//   prefix=<pattern>                coordinate pattern (select-prefix)
+//   after=<coord>                   pagination cursor - resume after this coordinate
+//   limit=<n>                       page size (default 100, max 10000)
+//   format=phext|json               `next=<cursor>` line then a phext fragment (default), or a JSON page
+//------------------------------------------------------------------------------------------------------------
+struct Selection {
+    low: [usize; 9],
+    high: [usize; 9],
+    pattern: Option<CoordinatePattern>,
+    after: Option<[usize; 9]>,
+    limit: usize,
+    json: bool,
+}
+
+impl Selection {
+    fn parse(command: &str, args: &str) -> Result<Selection, String> {
+        Selection::from_params(command, &crate::parse_query_string(args))
+    }
+
+    fn from_params(command: &str, params: &HashMap<String, String>) -> Result<Selection, String> {
+        let nothing = String::new();
+        let mut selection = Selection {
+            low: [1usize; 9],
+            high: [phext::COORDINATE_MAXIMUM; 9],
+            pattern: None,
+            after: None,
+            limit: DEFAULT_PAGE_SIZE,
+            json: params.get("format").map(|f| f == "json").unwrap_or(false),
+        };
+
+        if command == "select-range" {
+            // either end may be omitted for an open-ended range
+            for (name, bound) in [("from", &mut selection.low), ("to", &mut selection.high)] {
+                if let Some(value) = params.get(name).filter(|v| !v.is_empty()) {
+                    let c = phext::to_coordinate(value);
+                    if !c.validate_coordinate() {
+                        return Err(format!("Invalid {} coordinate: {}", name, value));
+                    }
+                    *bound = coord_sort_key(&c);
+                }
+            }
+        } else {
+            let pattern = params.get("prefix").unwrap_or(&nothing);
+            match CoordinatePattern::parse(pattern) {
+                Some(p) => {
+                    (selection.low, selection.high) = p.bounds();
+                    selection.pattern = Some(p);
+                }
+                None => return Err(format!("Invalid coordinate prefix: {}", pattern)),
+            }
+        }
+
+        if let Some(after) = params.get("after") {
+            let cursor = phext::to_coordinate(after);
+            if !cursor.validate_coordinate() {
+                return Err(format!("Invalid cursor: {}", after));
+            }
+            selection.after = Some(coord_sort_key(&cursor));
+        }
+
+        if let Some(limit) = params.get("limit") {
+            match limit.parse::<usize>() {
+                Ok(n) if n > 0 => selection.limit = n.min(MAX_PAGE_SIZE),
+                _ => return Err(format!("Invalid limit: {}", limit)),
+            }
+        }
+
+        Ok(selection)
+    }
+
+}
+
+//------------------------------------------------------------------------------------------------------------
+// select_page: returns one page of scrolls in hierarchy order, plus the cursor for the next page
+//------------------------------------------------------------------------------------------------------------
+fn select_page<'a>(store: &'a ScrollStore, selection: &Selection) -> (Vec<(&'a phext::Coordinate, &'a String)>, Option<phext::Coordinate>) {
+    let low = match selection.after {
+        Some(after) if after >= selection.low => Bound::Excluded(after),
+        _ => Bound::Included(selection.low),
+    };
+    let mut entries: Vec<(&phext::Coordinate, &String)> = store.range(low, Bound::Included(selection.high))
+        .filter(|(c, _)| selection.pattern.as_ref().map(|p| p.matches(c)).unwrap_or(true))
+        .take(selection.limit + 1)
+        .collect();
+
+    let next = if entries.len() > selection.limit {
+        entries.truncate(selection.limit);
+        entries.last().map(|(c, _)| **c)
+    } else {
+        None
+    };
+    (entries, next)
+}
+
+//------------------------------------------------------------------------------------------------------------
+// render_page: formats a page as JSON ({ "scrolls": [...], "next": cursor|null }) or as a phext fragment
+// behind a `next=<cursor>` line (always present; the cursor is empty on the last page)
+//------------------------------------------------------------------------------------------------------------
+fn render_page(entries: &[(&phext::Coordinate, &String)], next: Option<phext::Coordinate>, json: bool) -> String {
+    if json {
+        let scrolls: Vec<serde_json::Value> = entries.iter()
+            .map(|(c, v)| serde_json::json!({ "coord": c.to_string(), "scroll": v }))
+            .collect();
+        let page = serde_json::json!({
+            "scrolls": scrolls,
+            "next": next.map(|c| c.to_string()),
+        });
+        return page.to_string();
+    }
+
+    let mut page = format!("next={}\n", next.map(|c| c.to_string()).unwrap_or_default());
+    implode_sorted(entries.iter().copied(), &mut page);
+    page
+}
+
+//------------------------------------------------------------------------------------------------------------
+// SearchRequest: arguments for search, passed as a query string in the update slot
+//
+//   q=<query>                       words, prefix* words and "quoted phrases" - every clause must match
+//   prefix=<pattern>                only search scrolls matching a coordinate pattern such as 2.1.1/*/*
+//   after=<coord>, limit=<n>, format=json   pagination and output, as for select-range
+//------------------------------------------------------------------------------------------------------------
+struct SearchRequest {
+    query: String,
+    clauses: Vec<crate::search::Clause>,
+    selection: Selection,
+}
+
+impl SearchRequest {
+    fn parse(args: &str) -> Result<SearchRequest, String> {
+        let params = crate::parse_query_string(args);
+        let query = params.get("q").cloned().unwrap_or_default();
+        let clauses = crate::search::parse_query(&query);
+        if clauses.is_empty() {
+            return Err("Search requires a query (q=...)".to_string());
+        }
+        // an absent prefix means the whole phext; everything else is shared with select-prefix
+        let mut scoped = params.clone();
+        scoped.entry("prefix".to_string()).or_insert_with(|| "*".to_string());
+        let selection = Selection::from_params("select-prefix", &scoped)?;
+        Ok(SearchRequest { query, clauses, selection })
+    }
+}
+
+//------------------------------------------------------------------------------------------------------------
+// search_page: one page of matching scrolls in hierarchy order, with snippets and the next-page cursor
+//------------------------------------------------------------------------------------------------------------
+fn search_page(store: &ScrollStore, request: &SearchRequest) -> String {
+    let selection = &request.selection;
+    let low = match selection.after {
+        Some(after) if after >= selection.low => Bound::Excluded(after),
+        _ => Bound::Included(selection.low),
+    };
+    let mut hits: Vec<(&phext::Coordinate, &String)> = store.search(&request.clauses, low, Bound::Included(selection.high))
+        .into_iter()
+        .filter(|(c, _)| selection.pattern.as_ref().map(|p| p.matches(c)).unwrap_or(true))
+        .take(selection.limit + 1)
+        .collect();
+
+    let next = if hits.len() > selection.limit {
+        hits.truncate(selection.limit);
+        hits.last().map(|(c, _)| **c)
+    } else {
+        None
+    };
+
+    if selection.json {
+        let results: Vec<serde_json::Value> = hits.iter()
+            .map(|(c, v)| serde_json::json!({ "coord": c.to_string(), "snippet": crate::search::snippet(v, &request.clauses) }))
+            .collect();
+        let page = serde_json::json!({
+            "query": request.query,
+            "results": results,
+            "next": next.map(|c| c.to_string()),
+        });
+        return page.to_string();
+    }
+
+    let mut result = format!("next={}\n", next.map(|c| c.to_string()).unwrap_or_default());
+    for (c, v) in hits {
+        result += &format!("* {}: {}\n", c, crate::search::snippet(v, &request.clauses));
+    }
+    result
+}
+
 //------------------------------------------------------------------------------------------------------------
 // delimiters_between: computes the minimal delimiter sequence to advance from `prev` to `curr`
 //
@@ -57,25 +569,14 @@ fn coord_sort_key(c: &phext::Coordinate) -> [usize; 9] {
 // (curr[level] - prev[level]) copies of the break at the highest changed level,
 // then (curr[lower] - 1) copies for each lower level.
 //------------------------------------------------------------------------------------------------------------
-fn delimiters_between(prev: &phext::Coordinate, curr: &phext::Coordinate) -> String {
-    let p = coord_sort_key(prev);
-    let c = coord_sort_key(curr);
+fn delimiters_between(p: &[usize; 9], c: &[usize; 9], result: &mut String) {
     const DELIMS: [char; 9] = ['\x01', '\x1f', '\x1e', '\x1d', '\x1c', '\x1a', '\x19', '\x18', '\x17'];
 
     // Find the highest level that differs
-    let mut level = 9usize; // sentinel: no difference
-    for i in 0..9 {
-        if p[i] != c[i] {
-            level = i;
-            break;
-        }
-    }
-
-    if level >= 9 {
-        return String::new(); // same coordinate
-    }
-
-    let mut result = String::new();
+    let level = match (0..9).find(|&i| p[i] != c[i]) {
+        Some(level) => level,
+        None => return, // same coordinate
+    };
 
     // Emit delimiters at the changed level
     for _ in p[level]..c[level] {
@@ -88,19 +589,51 @@ fn delimiters_between(prev: &phext::Coordinate, curr: &phext::Coordinate) -> Str
             result.push(DELIMS[i]);
         }
     }
+}
 
-    result
+//------------------------------------------------------------------------------------------------------------
+// delimiter_count: number of bytes delimiters_between would emit (every delimiter is a single byte)
+//------------------------------------------------------------------------------------------------------------
+pub fn delimiter_count(p: &[usize; 9], c: &[usize; 9]) -> usize {
+    let level = match (0..9).find(|&i| p[i] != c[i]) {
+        Some(level) => level,
+        None => return 0,
+    };
+    let lower: usize = ((level + 1)..9).map(|i| c[i].saturating_sub(1)).sum();
+    c[level].saturating_sub(p[level]) + lower
+}
+
+//------------------------------------------------------------------------------------------------------------
+// implode_sorted: serializes entries that are already in hierarchy order
+//------------------------------------------------------------------------------------------------------------
+pub fn implode_sorted<'a, I>(entries: I, result: &mut String)
+where
+    I: IntoIterator<Item = (&'a phext::Coordinate, &'a String)>,
+{
+    let mut prev = coord_sort_key(&phext::Coordinate::default());
+    for (coord, content) in entries {
+        if content.is_empty() {
+            continue;
+        }
+        let curr = coord_sort_key(coord);
+        delimiters_between(&prev, &curr, result);
+        result.push_str(content);
+        prev = curr;
+    }
 }
 
 //------------------------------------------------------------------------------------------------------------
-// implode_ref: borrow-only serialization of a phext map
+// implode_ref: borrow-only serialization of a phext map (a HashMap or a ScrollStore)
 //
 // Produces the same byte sequence as phext::implode() but never clones the map.
 // Only non-empty scrolls are emitted; empty scrolls are skipped (matching libphext behavior).
 //------------------------------------------------------------------------------------------------------------
-pub fn implode_ref(map: &HashMap<phext::Coordinate, String>) -> String {
+pub fn implode_ref<'a, M>(map: M) -> String
+where
+    M: IntoIterator<Item = (&'a phext::Coordinate, &'a String)>,
+{
     // Collect non-empty entries
-    let mut entries: Vec<(&phext::Coordinate, &String)> = map.iter()
+    let mut entries: Vec<(&phext::Coordinate, &String)> = map.into_iter()
         .filter(|(_, v)| !v.is_empty())
         .collect();
 
@@ -108,42 +641,89 @@ pub fn implode_ref(map: &HashMap<phext::Coordinate, String>) -> String {
         return String::new();
     }
 
-    // Sort by coordinate hierarchy
-    entries.sort_by(|a, b| coord_sort_key(a.0).cmp(&coord_sort_key(b.0)));
+    // Sort by coordinate hierarchy (already-ordered input, e.g. a ScrollStore, is a single run)
+    entries.sort_by_key(|a| coord_sort_key(a.0));
 
     // Pre-calculate total size to avoid reallocation
     let total_size: usize = entries.iter().map(|(_, v)| v.len()).sum::<usize>()
         + entries.len() * 9; // worst-case 9 delimiters per entry
     let mut result = String::with_capacity(total_size);
-
-    let origin = phext::Coordinate::default();
-    let mut prev = &origin;
-
-    for (coord, content) in &entries {
-        let delims = delimiters_between(prev, coord);
-        result.push_str(&delims);
-        result.push_str(content);
-        prev = coord;
-    }
-
+    implode_sorted(entries, &mut result);
     result
 }
 
+//------------------------------------------------------------------------------------------------------------
+// Request: one user request for process/query
+//
+// @field connection_id
+// @field source - the loaded phext
+// @field command
+// @field coordinate
+// @field update - the command's text argument
+// @field filename - the command's file argument
+// @field algorithm - hash algorithm to use for coordinate inference
+// @field limit - minimum scroll length for XOR hashing
+//------------------------------------------------------------------------------------------------------------
+pub struct Request {
+    pub connection_id: u64,
+    pub source: String,
+    pub command: String,
+    pub coordinate: phext::Coordinate,
+    pub update: String,
+    pub filename: String,
+    pub algorithm: crate::HashAlgorithm,
+    pub limit: usize,
+}
+
 //------------------------------------------------------------------------------------------------------------
 // process: performs the command line action for a given user request
 //
-// @param connection_id
-// @param source
-// @param scroll
-// @param command
+// @param request
+// @param scroll - receives the command's output
 // @param phext_map
-// @param coordinate
-// @param update
-// @param filename
-// @param algorithm - hash algorithm to use for coordinate inference
-// @param limit - minimum scroll length for XOR hashing
 //------------------------------------------------------------------------------------------------------------
-pub fn process(connection_id: u64, source: String, scroll: &mut String, command: String, phext_map: &mut HashMap::<phext::Coordinate, String>, coordinate: phext::Coordinate, update: String, filename: String, algorithm: crate::HashAlgorithm, limit: usize) -> bool {
+pub fn process(request: &Request, scroll: &mut String, phext_map: &mut ScrollStore) -> bool {
+    let Request { command, coordinate, update, .. } = request;
+    let coordinate = *coordinate;
+    if command == "json-import" {
+        *scroll = json_import(phext_map, update.as_str());
+        return false;
+    }
+
+    if command == "insert" {
+        *scroll = format!("Inserted {} bytes", update.len());
+        let mut concatenated = String::new();
+        if phext_map.contains_key(&coordinate) {
+            let nothing = String::new();
+            concatenated = phext_map.get(&coordinate).unwrap_or(&nothing).clone()
+        }
+        concatenated.push_str(update.as_str());
+        phext_map.insert(coordinate, concatenated);
+        return false;
+    }
+
+    if command == "update" || command == "push" || command == "slurp" {
+        *scroll = format!("Updated {} bytes", update.len());
+        phext_map.insert(coordinate, update.clone());
+        return false;
+    }
+
+    if command == "delete" {
+        let old = phext_map.remove(&coordinate).unwrap_or_default();
+        *scroll = format!("Removed {} bytes", old.len());
+        return false;
+    }
+
+    query(request, scroll, phext_map)
+}
+
+//------------------------------------------------------------------------------------------------------------
+// query: performs a read-only command - safe to run concurrently against a shared store
+//   mutating commands are not handled here (see process)
+//------------------------------------------------------------------------------------------------------------
+pub fn query(request: &Request, scroll: &mut String, phext_map: &ScrollStore) -> bool {
+    let Request { connection_id, source, command, update, filename, .. } = request;
+    let (coordinate, algorithm, limit) = (request.coordinate, request.algorithm, request.limit);
     if command == "help" {
         *scroll = "
 * help: display this online help screen
@@ -158,65 +738,69 @@ pub fn process(connection_id: u64, source: String, scroll: &mut String, command:
 * push <coord> <file>: Imports a file into your phext at the given coordinate
 * pull <coord> <file>: Exports a scroll to a file of your choice
 * select <coord>: fetch a scroll of text from the loaded phext
+* select-range <from> <to> [options]: fetch every scroll between two coordinates, in hierarchy order
+* select-prefix <pattern> [options]: fetch every scroll matching a pattern such as 2.1.1/*/*
+    options: after=<coord>&limit=<n>&format=json
+* search \"query\" [options]: full-text search - words, prefix* words and \"quoted phrases\" must all match
+    options: prefix=<pattern>&after=<coord>&limit=<n>&format=json
 * insert <coord> \"text\": append text to the specified scroll
 * update <coord> \"text\": overwrite text at the specified scroll
 * delete <coord>: truncates the specified scroll
+* json-export <file>: writes every scroll to <file>.json as [{\"coord\", \"scroll\"}...]
+* json-import <file> [merge|replace]: loads a json-export file (merge by default; replace drops scrolls not in the file)
 * save <file>: dumps the contents of the loaded phext to disk
 * shutdown: terminate the phext server".to_string();
         return false;
     }
 
     if command == "version" {
-        *scroll = format!("{}", env!("CARGO_PKG_VERSION"));
+        *scroll = env!("CARGO_PKG_VERSION").to_string();
         return false;
     }
 
     if command == "status" {
-        // use implode_ref to avoid cloning the entire map just for .len()
-        let buffer = implode_ref(phext_map);
+        // both aggregates are maintained by the store - nothing is serialized here
+        let (queued, busy, workers) = crate::pool::global()
+            .map(|pool| (pool.queue_length(), pool.busy(), pool.workers()))
+            .unwrap_or((0, 0, 0));
         *scroll = format!("Hosting: {}
 Connection ID: {}
 Phext Size: {}
-Scrolls: {}", source, connection_id, buffer.len(), phext_map.iter().size_hint().0);
+Scrolls: {}
+Queue Length: {}
+Workers Busy: {}/{}", source, connection_id, phext_map.byte_size(), phext_map.len(), queued, busy, workers);
         return false;
     }
 
     if command == "json-export" {
-        let mut result = String::new();
-        result += "[\n";
-        let mut started = false;
-        for ith in phext_map.iter() {
-            if !started {
-                started = true;
-            } else { result += ","; }
-            result += &format!("   {{ \"coord\": \"{}\", \"scroll\": \"{}\" }}\n",
-                json_escape(ith.0.to_string()), 
-                json_escape(ith.1.to_string())).to_string();
-        }
-        result += "]\n";
+        let result = json_export(phext_map);
         *scroll = result.clone();
         let json_filename = format!("{}.json", filename);
-        let _ = std::fs::write(json_filename, result);
+        if let Err(e) = crate::persist::write_atomic(&json_filename, result) {
+            eprintln!("Failed to write {}: {}", json_filename, e);
+        }
         return false;
     }
 
     if command == "diff" {
-        // use implode_ref instead of cloning
-        let compare = implode_ref(phext_map);
+        let compare = phext_map.implode();
         let diff = phext::subtract(update.as_str(), compare.as_str());
         *scroll = phext::textmap(diff.as_str());
         return false;
     }
 
     if command == "toc" {
-        // use implode_ref instead of cloning
-        let buffer = implode_ref(phext_map);
-        *scroll = phext::textmap(buffer.as_str());
+        *scroll = phext_map.toc();
         return false;
     }
 
     if command == "get" {
-        let buffer: String = match std::fs::read_to_string(&filename) {
+        // the loaded phext may have mutations that only live in its write-ahead log so far
+        if filename == source {
+            *scroll = phext_map.implode();
+            return false;
+        }
+        let buffer: String = match std::fs::read_to_string(filename) {
             Ok(b) => b,
             Err(e) => {
                 *scroll = format!("Unable to open requested phext {}: {}", filename, e);
@@ -228,64 +812,94 @@ Scrolls: {}", source, connection_id, buffer.len(), phext_map.iter().size_hint().
     }
 
     if command == "checksum" {
-        // use implode_ref instead of cloning
-        let serialized = implode_ref(phext_map);
-        *scroll = phext::checksum(serialized.as_str());
+        *scroll = phext_map.checksum();
         return false;
     }
 
     if command == "delta" {
+        let filter = match CoordinateFilter::from_lines(update.as_str()) {
+            Ok(filter) => filter,
+            Err(message) => {
+                *scroll = message;
+                return false;
+            }
+        };
         let mut diff_map: HashMap<phext::Coordinate, String> = Default::default();
         let mut output:HashMap<phext::Coordinate, String> = Default::default();
         for line in update.lines() {
+            if CoordinateFilter::is_filter_line(line) { continue; }
             let parsed:Vec<&str> = line.split(": ").collect();
-            if parsed.len() == 0 { continue; }
+            if parsed.is_empty() { continue; }
             let parsed_coordinate = phext::to_coordinate(parsed[0]);
             if parsed_coordinate.validate_coordinate() && parsed.len() > 1 {
                 let parsed_hash = parsed[1];
                 diff_map.insert(parsed_coordinate, parsed_hash.to_string());
             }
         }
-        for key in phext_map.keys() {
-            let checksum = phext::checksum(phext_map[key].as_str());
-            if diff_map.contains_key(key) == false || checksum != diff_map[key] {
-                output.insert(key.clone(), phext_map[key].clone());
+        for (key, value) in filter.scan(phext_map) {
+            let checksum = phext_map.scroll_checksum(key).unwrap_or_default();
+            if !diff_map.contains_key(key) || checksum != diff_map[key] {
+                output.insert(*key, value.clone());
             }
         }
         for key in diff_map.keys() {
-            if phext_map.contains_key(key) == false {
-                output.insert(key.clone(), "---sq:Scroll-Missing---".to_string());
+            if filter.matches(key) && !phext_map.contains_key(key) {
+                output.insert(*key, MISSING_SCROLL.to_string());
             }
         }
-        *scroll = phext::implode(output);
+        *scroll = implode_ref(&output);
         return false;
     }
 
-    if command == "select" || command == "pull" {
-        if phext_map.contains_key(&coordinate) {
-            let nothing = String::new();
-            *scroll = phext_map.get(&coordinate).unwrap_or(&nothing).clone();
+    if command == "merkle" {
+        // one round of the hierarchical delta protocol (merkle.rs): the children of each requested subtree
+        let filter = match CoordinateFilter::from_lines(update.as_str()) {
+            Ok(filter) => filter,
+            Err(message) => {
+                *scroll = message;
+                return false;
+            }
+        };
+        let requests: Vec<&str> = update.lines().filter(|line| !CoordinateFilter::is_filter_line(line)).collect();
+        let tree = phext_map.merkle_scoped(&filter);
+        *scroll = if requests.is_empty() {
+            // no prefixes: the root itself, covering the whole (filtered) phext
+            let root = tree.root().unwrap_or_default();
+            format!("{}: {:032x} {}\n", CoordinatePattern::prefix(&[]), root.hash, root.count)
         } else {
-            *scroll = String::new();
+            tree.answer(requests)
+        };
+        return false;
+    }
+
+    if command == "select-range" || command == "select-prefix" {
+        match Selection::parse(command.as_str(), update.as_str()) {
+            Ok(selection) => {
+                let (entries, next) = select_page(phext_map, &selection);
+                *scroll = render_page(&entries, next, selection.json);
+            }
+            Err(message) => {
+                *scroll = message;
+            }
         }
         return false;
     }
 
-    if command == "insert" {
-        *scroll = format!("Inserted {} bytes", update.len());
-        let mut concatenated = String::new();
-        if phext_map.contains_key(&coordinate) {
-            let nothing = String::new();
-            concatenated = phext_map.get(&coordinate).unwrap_or(&nothing).clone()
+    if command == "search" {
+        match SearchRequest::parse(update.as_str()) {
+            Ok(request) => *scroll = search_page(phext_map, &request),
+            Err(message) => *scroll = message,
         }
-        concatenated.push_str(update.as_str());
-        (*phext_map).insert(coordinate, concatenated);
         return false;
     }
 
-    if command == "update" || command == "push" || command == "slurp" {
-        *scroll = format!("Updated {} bytes", update.len());
-        phext_map.insert(coordinate, update);
+    if command == "select" || command == "pull" {
+        if phext_map.contains_key(&coordinate) {
+            let nothing = String::new();
+            *scroll = phext_map.get(&coordinate).unwrap_or(&nothing).clone();
+        } else {
+            *scroll = String::new();
+        }
         return false;
     }
 
@@ -300,22 +914,20 @@ Scrolls: {}", source, connection_id, buffer.len(), phext_map.iter().size_hint().
         return false;
     }
 
-    if command == "delete" {
-        let mut old = String::new();
-        if phext_map.contains_key(&coordinate) {
-            let nothing = String::new();
-            old = phext_map.get(&coordinate).unwrap_or(&nothing).clone();
-            phext_map.remove(&coordinate);
-        }
-        *scroll = format!("Removed {} bytes", old.len());
-        return false;
-    }
-
     if command == "save" {
-        // use implode_ref instead of cloning
-        let output_buffer = implode_ref(phext_map);
-        let _ = std::fs::write(filename.clone(), output_buffer.as_str());
-        *scroll = format!("Wrote {} bytes to {}", output_buffer.len(), filename);
+        let output_buffer = phext_map.implode();
+        match crate::persist::write_atomic(filename, output_buffer.as_str()) {
+            Ok(_) => {
+                if filename == source {
+                    // the saved file now includes everything the write-ahead log was holding
+                    crate::wal::discard(filename);
+                }
+                *scroll = format!("Wrote {} bytes to {}", output_buffer.len(), filename);
+            }
+            Err(e) => {
+                *scroll = format!("Unable to save {}: {}", filename, e);
+            }
+        }
         return false;
     }
 
@@ -325,10 +937,10 @@ Scrolls: {}", source, connection_id, buffer.len(), phext_map.iter().size_hint().
     }
 
     if command == "shutdown" {
-      *scroll = format!("Shutdown Initiated.");
+      *scroll = "Shutdown Initiated.".to_string();
       return true;
     }
 
-    *scroll = format!("Unexpected command ignored.");
-    return false;
+    *scroll = "Unexpected command ignored.".to_string();
+    false
 }
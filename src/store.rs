@@ -0,0 +1,435 @@
+//------------------------------------------------------------------------------------------------------------
+// file: store.rs
+// purpose: ordered in-memory scroll store backing every loaded phext
+//
+// Scrolls are kept in a BTreeMap keyed by sq::coord_sort_key, so iteration is already in hierarchy order and
+// range/prefix scans are O(log n + k). The store also maintains the aggregates the read commands need:
+//   - serialized size (exactly what implode would produce: content + delimiters between neighbours)
+//   - scroll count
+//   - per-scroll checksums (used by delta)
+//   - the whole-phext checksum (phext::checksum of the serialized phext), computed lazily and invalidated on
+//     mutation
+//   - the hierarchical checksum tree (merkle.rs), and the trees over the subtrees mesh peers ask for; each is
+//     built on first use and then kept current on every mutation
+//   - copies of the scrolls in those subtrees, which serve a mesh peer's other reads (see read_query in
+//     main.rs); each is copied on first use and then kept current the same way
+//   - the full-text search index, built on the first search and then kept current on every mutation
+// The lazy caches are OnceLocks (and Mutexes for the scoped trees and views), so every read (including checksum and
+// search) works through &self and concurrent readers can share one store behind a read lock.
+// Empty scrolls are never stored; writing an empty scroll removes it, matching implode's behaviour.
+//------------------------------------------------------------------------------------------------------------
+
+use crate::merkle::MerkleTree;
+use crate::phext;
+use crate::search::{self, Clause, SearchIndex};
+use crate::sq::{coord_sort_key, delimiter_count, CoordinateFilter};
+use std::collections::btree_map;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+use std::sync::{Arc, Mutex, OnceLock};
+
+pub type SortKey = [usize; 9];
+
+const ORIGIN: SortKey = [1; 9];
+const SCOPES: usize = 16; // distinct filters with a cached checksum tree or view (typically one per mesh peer)
+
+struct Entry {
+    coordinate: phext::Coordinate,
+    scroll: String,
+    checksum: String,
+}
+
+#[derive(Default)]
+pub struct ScrollStore {
+    entries: BTreeMap<SortKey, Entry>,
+    content_bytes: usize,
+    delimiter_bytes: usize,
+    checksum: OnceLock<String>,
+    merkle: OnceLock<Arc<MerkleTree>>,
+    scoped: Mutex<Vec<(CoordinateFilter, Arc<MerkleTree>)>>,
+    views: Mutex<Vec<(CoordinateFilter, Arc<ScrollStore>)>>,
+    index: OnceLock<SearchIndex>,
+}
+
+pub struct Iter<'a> {
+    inner: btree_map::Values<'a, SortKey, Entry>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a phext::Coordinate, &'a String);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|e| (&e.coordinate, &e.scroll))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a> IntoIterator for &'a ScrollStore {
+    type Item = (&'a phext::Coordinate, &'a String);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+impl From<HashMap<phext::Coordinate, String>> for ScrollStore {
+    fn from(map: HashMap<phext::Coordinate, String>) -> ScrollStore {
+        let mut store = ScrollStore::new();
+        for (coordinate, scroll) in map {
+            store.insert(coordinate, scroll);
+        }
+        store
+    }
+}
+
+impl ScrollStore {
+    pub fn new() -> ScrollStore {
+        ScrollStore::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter { inner: self.entries.values() }
+    }
+
+    pub fn get(&self, coordinate: &phext::Coordinate) -> Option<&String> {
+        self.entries.get(&coord_sort_key(coordinate)).map(|e| &e.scroll)
+    }
+
+    pub fn contains_key(&self, coordinate: &phext::Coordinate) -> bool {
+        self.entries.contains_key(&coord_sort_key(coordinate))
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // scroll_checksum: cached phext::checksum of a single scroll
+    // -------------------------------------------------------------------------------------------------------
+    pub fn scroll_checksum(&self, coordinate: &phext::Coordinate) -> Option<&str> {
+        self.entries.get(&coord_sort_key(coordinate)).map(|e| e.checksum.as_str())
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // byte_size: length of the serialized phext, without serializing it
+    // -------------------------------------------------------------------------------------------------------
+    pub fn byte_size(&self) -> usize {
+        self.content_bytes + self.delimiter_bytes
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // checksum: checksum of the serialized phext, recomputed only after a mutation
+    // -------------------------------------------------------------------------------------------------------
+    pub fn checksum(&self) -> String {
+        self.checksum.get_or_init(|| phext::checksum(self.implode().as_str())).clone()
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // merkle: the hierarchical checksum tree (merkle.rs)
+    // -------------------------------------------------------------------------------------------------------
+    pub fn merkle(&self) -> Arc<MerkleTree> {
+        let tree = self.merkle.get_or_init(|| {
+            Arc::new(MerkleTree::build(self.entries.iter().map(|(key, e)| (*key, e.checksum.as_str()))))
+        });
+        Arc::clone(tree)
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // merkle_scoped: the checksum tree over just the scrolls in `filter`, cached per filter like the full tree
+    // -------------------------------------------------------------------------------------------------------
+    pub fn merkle_scoped(&self, filter: &CoordinateFilter) -> Arc<MerkleTree> {
+        if *filter == CoordinateFilter::default() {
+            return self.merkle();
+        }
+        let mut scoped = self.scoped.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some((_, tree)) = scoped.iter().find(|(f, _)| f == filter) {
+            return Arc::clone(tree);
+        }
+        let tree = Arc::new(MerkleTree::build(filter.scan(self)
+            .map(|(c, _)| (coord_sort_key(c), self.scroll_checksum(c).unwrap_or_default()))));
+        if scoped.len() >= SCOPES {
+            scoped.remove(0);
+        }
+        scoped.push((filter.clone(), Arc::clone(&tree)));
+        tree
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // view: a store holding just the scrolls in `filter`, cached per filter like the scoped checksum trees
+    // -------------------------------------------------------------------------------------------------------
+    pub fn view(&self, filter: &CoordinateFilter) -> Arc<ScrollStore> {
+        let mut views = self.views.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some((_, view)) = views.iter().find(|(f, _)| f == filter) {
+            return Arc::clone(view);
+        }
+        let view = Arc::new(filter.copy(self));
+        if views.len() >= SCOPES {
+            views.remove(0);
+        }
+        views.push((filter.clone(), Arc::clone(&view)));
+        view
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // update_views: applies a write (None: removed) to every view that covers the coordinate
+    //   a view still held by a reader is dropped instead, and copied again on its next use
+    // -------------------------------------------------------------------------------------------------------
+    fn update_views(&mut self, coordinate: &phext::Coordinate, scroll: Option<&str>) {
+        let views = self.views.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner());
+        views.retain_mut(|(filter, view)| {
+            if !filter.matches(coordinate) {
+                return true;
+            }
+            let Some(view) = Arc::get_mut(view) else {
+                return false;
+            };
+            match scroll {
+                Some(scroll) => view.insert(*coordinate, scroll.to_string()),
+                None => view.remove(coordinate),
+            };
+            true
+        });
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // update_trees: applies a scroll's new checksum (None: removed) to every checksum tree built so far
+    //   a tree still held by a reader (e.g. a sync round in progress) is copied first
+    // -------------------------------------------------------------------------------------------------------
+    fn update_trees(&mut self, key: &SortKey, coordinate: &phext::Coordinate, checksum: Option<&str>) {
+        if let Some(tree) = self.merkle.get_mut() {
+            Arc::make_mut(tree).update(key, checksum);
+        }
+        let scoped = self.scoped.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for (_, tree) in scoped.iter_mut().filter(|(filter, _)| filter.matches(coordinate)) {
+            Arc::make_mut(tree).update(key, checksum);
+        }
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // range: scrolls whose sort keys fall within the given bounds, in hierarchy order
+    // -------------------------------------------------------------------------------------------------------
+    pub fn range(&self, low: Bound<SortKey>, high: Bound<SortKey>) -> impl Iterator<Item = (&phext::Coordinate, &String)> {
+        let empty = match (low, high) {
+            (Bound::Included(l), Bound::Included(h)) => l > h,
+            (Bound::Included(l), Bound::Excluded(h)) |
+            (Bound::Excluded(l), Bound::Included(h)) |
+            (Bound::Excluded(l), Bound::Excluded(h)) => l >= h,
+            _ => false,
+        };
+        let entries = if empty { None } else { Some(self.entries.range((low, high))) };
+        entries.into_iter().flatten().map(|(_, e)| (&e.coordinate, &e.scroll))
+    }
+
+    fn neighbours(&self, key: &SortKey) -> (SortKey, Option<SortKey>) {
+        let prev = self.entries.range(..*key).next_back().map(|(k, _)| *k).unwrap_or(ORIGIN);
+        let next = self.entries.range((Bound::Excluded(*key), Bound::Unbounded)).next().map(|(k, _)| *k);
+        (prev, next)
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // insert: stores a scroll (an empty scroll removes the coordinate); returns the previous contents
+    // -------------------------------------------------------------------------------------------------------
+    pub fn insert(&mut self, coordinate: phext::Coordinate, scroll: String) -> Option<String> {
+        if scroll.is_empty() {
+            return self.remove(&coordinate);
+        }
+        let key = coord_sort_key(&coordinate);
+        self.checksum.take();
+        let checksum = phext::checksum(scroll.as_str());
+        self.update_trees(&key, &coordinate, Some(&checksum));
+        self.update_views(&coordinate, Some(&scroll));
+        self.content_bytes += scroll.len();
+
+        if let Some(entry) = self.entries.get_mut(&key) {
+            self.content_bytes -= entry.scroll.len();
+            entry.checksum = checksum;
+            if let Some(index) = self.index.get_mut() {
+                index.remove(key, &entry.scroll);
+                index.add(key, &scroll);
+            }
+            return Some(std::mem::replace(&mut entry.scroll, scroll));
+        }
+
+        let (prev, next) = self.neighbours(&key);
+        self.delimiter_bytes += delimiter_count(&prev, &key);
+        if let Some(next) = next {
+            self.delimiter_bytes = self.delimiter_bytes + delimiter_count(&key, &next) - delimiter_count(&prev, &next);
+        }
+        if let Some(index) = self.index.get_mut() {
+            index.add(key, &scroll);
+        }
+        self.entries.insert(key, Entry { coordinate, scroll, checksum });
+        None
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // remove: drops a scroll, returning its contents
+    // -------------------------------------------------------------------------------------------------------
+    pub fn remove(&mut self, coordinate: &phext::Coordinate) -> Option<String> {
+        let key = coord_sort_key(coordinate);
+        let entry = self.entries.remove(&key)?;
+        self.checksum.take();
+        self.update_trees(&key, coordinate, None);
+        self.update_views(coordinate, None);
+        self.content_bytes -= entry.scroll.len();
+        if let Some(index) = self.index.get_mut() {
+            index.remove(key, &entry.scroll);
+        }
+
+        let (prev, next) = self.neighbours(&key);
+        self.delimiter_bytes -= delimiter_count(&prev, &key);
+        if let Some(next) = next {
+            self.delimiter_bytes = self.delimiter_bytes + delimiter_count(&prev, &next) - delimiter_count(&key, &next);
+        }
+        Some(entry.scroll)
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // search: scrolls within [low, high] matching every clause, in hierarchy order
+    //   the index is built on first use so phexts that are never searched don't pay for it
+    // -------------------------------------------------------------------------------------------------------
+    pub fn search(&self, clauses: &[Clause], low: Bound<SortKey>, high: Bound<SortKey>) -> Vec<(&phext::Coordinate, &String)> {
+        let index = self.index.get_or_init(|| {
+            let mut index = SearchIndex::default();
+            for (key, entry) in &self.entries {
+                index.add(*key, &entry.scroll);
+            }
+            index
+        });
+        let candidates = index.candidates(clauses);
+        candidates.range((low, high))
+            .filter_map(|key| self.entries.get(key))
+            .filter(|e| search::verify(&e.scroll, clauses))
+            .map(|e| (&e.coordinate, &e.scroll))
+            .collect()
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // implode: serializes the store (identical bytes to phext::implode / sq::implode_ref)
+    // -------------------------------------------------------------------------------------------------------
+    pub fn implode(&self) -> String {
+        let mut result = String::with_capacity(self.byte_size());
+        crate::sq::implode_sorted(self.iter(), &mut result);
+        result
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // toc: the same listing as phext::textmap(implode), built straight from the index
+    // -------------------------------------------------------------------------------------------------------
+    pub fn toc(&self) -> String {
+        if self.is_empty() {
+            return format!("* {}: \n", phext::Coordinate::default());
+        }
+        let mut result = String::new();
+        for (coordinate, scroll) in self.iter() {
+            result += &format!("* {}: {}\n", coordinate, phext::create_summary(scroll));
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod store_tests {
+    use super::*;
+
+    fn sample() -> ScrollStore {
+        ScrollStore::from(phext::explode("hello\x17from\x18beyond\x19the\x1astars\x1cnot\x1dan\x1eevil\x1ffuzzle\x01just a warm fuzzy."))
+    }
+
+    #[test]
+    fn test_aggregates_match_serialization() {
+        let store = sample();
+        let serialized = store.implode();
+        assert_eq!(store.byte_size(), serialized.len());
+        assert_eq!(store.len(), 10);
+        assert_eq!(store.checksum(), phext::checksum(serialized.as_str()));
+        assert_eq!(store.toc(), phext::textmap(serialized.as_str()));
+    }
+
+    #[test]
+    fn test_aggregates_track_mutations() {
+        let mut store = sample();
+        // build the checksum trees first - the writes below must keep them current
+        let scope = CoordinateFilter::parse(&["1.1.1/*/*"], &[]).unwrap();
+        let before = (store.checksum(), store.merkle().root(), store.merkle_scoped(&scope).root());
+        store.insert(phext::to_coordinate("1.1.1/1.1.1/1.1.5"), "gap".to_string());
+        store.insert(phext::to_coordinate("1.1.1/1.1.1/1.1.1"), "rewritten origin".to_string());
+        store.remove(&phext::to_coordinate("1.1.1/1.1.1/2.1.1"));
+        store.insert(phext::to_coordinate("1.1.2/1.1.1/1.1.1"), String::new());
+        store.insert(phext::to_coordinate("9.9.9/9.9.9/9.9.9"), "far away".to_string());
+
+        let serialized = store.implode();
+        assert_eq!(store.byte_size(), serialized.len());
+        assert_eq!(store.len(), 10);
+        let rebuilt = ScrollStore::from(phext::explode(&serialized));
+        assert_eq!(rebuilt.implode(), serialized);
+        assert_ne!(store.checksum(), before.0);
+        assert_eq!(store.checksum(), phext::checksum(serialized.as_str()));
+        assert_ne!(store.merkle().root(), before.1);
+        assert_eq!(store.merkle().root(), rebuilt.merkle().root());
+        assert_ne!(store.merkle_scoped(&scope).root(), before.2);
+        assert_eq!(store.merkle_scoped(&scope).root(), rebuilt.merkle_scoped(&scope).root());
+    }
+
+    #[test]
+    fn test_views_track_mutations() {
+        let mut store = sample();
+        let scope = CoordinateFilter::parse(&["1.1.1/*/*"], &[]).unwrap();
+        let view = store.view(&scope);
+        assert_eq!(view.implode(), scope.copy(&store).implode());
+        assert!(Arc::ptr_eq(&view, &store.view(&scope)));
+
+        // a view a reader still holds is copied again; one nobody holds is updated in place
+        store.insert(phext::to_coordinate("1.1.1/1.1.1/1.1.5"), "gap".to_string());
+        assert!(!view.contains_key(&phext::to_coordinate("1.1.1/1.1.1/1.1.5")));
+        drop(view);
+        store.remove(&phext::to_coordinate("1.1.1/1.1.1/2.1.1"));
+        store.insert(phext::to_coordinate("9.9.9/9.9.9/9.9.9"), "out of scope".to_string());
+        let view = store.view(&scope);
+        assert!(view.contains_key(&phext::to_coordinate("1.1.1/1.1.1/1.1.5")));
+        assert_eq!(view.implode(), scope.copy(&store).implode());
+    }
+
+    #[test]
+    fn test_empty_store() {
+        let store = ScrollStore::from(phext::explode(""));
+        assert!(store.is_empty());
+        assert_eq!(store.byte_size(), 0);
+        assert_eq!(store.implode(), "");
+        assert_eq!(store.toc(), phext::textmap(""));
+        assert_eq!(store.checksum(), phext::checksum(""));
+        assert_eq!(store.merkle().root(), None);
+    }
+
+    #[test]
+    fn test_search_index_tracks_mutations() {
+        let mut store = sample();
+        assert_eq!(store.search(&search::parse_query("warm fuzzy"), Bound::Unbounded, Bound::Unbounded).len(), 1);
+
+        // the index exists now - later writes must keep it current
+        store.insert(phext::to_coordinate("1.1.1/1.1.1/1.1.1"), "a warm welcome".to_string());
+        store.remove(&phext::to_coordinate("2.1.1/1.1.1/1.1.1"));
+        assert_eq!(store.search(&search::parse_query("warm"), Bound::Unbounded, Bound::Unbounded).len(), 1);
+        assert_eq!(store.search(&search::parse_query("hello"), Bound::Unbounded, Bound::Unbounded).len(), 0);
+        assert_eq!(store.search(&search::parse_query("\"warm welcome\""), Bound::Unbounded, Bound::Unbounded).len(), 1);
+        assert_eq!(store.search(&search::parse_query("\"welcome warm\""), Bound::Unbounded, Bound::Unbounded).len(), 0);
+    }
+
+    #[test]
+    fn test_range_bounds() {
+        let store = sample();
+        let low = coord_sort_key(&phext::to_coordinate("1.1.1/1.1.1/1.2.1"));
+        let high = coord_sort_key(&phext::to_coordinate("1.1.1/1.2.1/1.1.1"));
+        let found: Vec<&String> = store.range(Bound::Included(low), Bound::Excluded(high)).map(|(_, s)| s).collect();
+        assert_eq!(found, vec!["beyond", "the", "stars"]);
+        assert_eq!(store.range(Bound::Included(high), Bound::Included(low)).count(), 0);
+    }
+}
@@ -0,0 +1,381 @@
+//------------------------------------------------------------------------------------------------------------
+// file: supervisor.rs
+// purpose: runs tenant backends (`sq host <port> --bind <loopback> --key <token> --data-dir <dir>`,
+// or `sq host --socket <path> ...`) as child processes of sq route
+//
+// The supervisor is handed the backends that should be running on every tick and makes it so:
+//   - a backend without a child is started (its data directory is created if needed)
+//   - a child that exited is restarted after a backoff that doubles from 1s up to 60s, and resets once the
+//     child has stayed up for STABLE_SECS
+//   - a child whose backend was removed or changed (token, data_dir) is stopped, then started again if needed
+// Children are stopped with SIGTERM, then killed if they haven't exited after STOP_GRACE_MS. On Linux they
+// also get SIGTERM if the router dies without stopping them. Each child's output goes to <data_dir>/sq-host.log.
+//------------------------------------------------------------------------------------------------------------
+
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+const BACKOFF_MIN_SECS: u64 = 1;
+const BACKOFF_MAX_SECS: u64 = 60;
+const STABLE_SECS: u64 = 30;
+const STOP_GRACE_MS: u64 = 5_000;
+pub const LOG_FILE: &str = "sq-host.log";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackendSpec {
+    pub name: String,
+    pub port: u16,
+    pub bind: String,           // the loopback address `port` is bound on, so only this machine can reach it
+    pub socket: Option<String>, // listens here instead of on `port`
+    pub token: String,
+    pub data_dir: String,
+}
+
+impl BackendSpec {
+    // a renamed backend keeps its child; anything that changes the command line restarts it
+    fn same_process(&self, other: &BackendSpec) -> bool {
+        self.listen() == other.listen() && self.bind == other.bind && self.token == other.token && self.data_dir == other.data_dir
+    }
+
+    // where the child listens, as shown in the log ("port 1338" or "socket /run/sq/a.sock")
+    pub fn listen(&self) -> String {
+        match self.socket {
+            Some(ref path) => format!("socket {}", path),
+            None => format!("port {}", self.port),
+        }
+    }
+}
+
+struct Supervised {
+    spec: BackendSpec,
+    child: Option<Child>,
+    started: Instant,
+    failures: u32,
+    next_start: Instant,
+}
+
+pub struct Supervisor {
+    backends: HashMap<String, Supervised>, // keyed by listen(): two children can never share a port or socket
+    command: fn(&BackendSpec) -> Result<Command, String>, // host_command, except in tests
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Supervisor { backends: HashMap::new(), command: host_command }
+    }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// backoff: the delay before restarting a child that has exited `failures` times in a row
+// -----------------------------------------------------------------------------------------------------------
+pub fn backoff(failures: u32) -> Duration {
+    let secs = BACKOFF_MIN_SECS.saturating_mul(1u64 << failures.saturating_sub(1).min(16));
+    Duration::from_secs(secs.min(BACKOFF_MAX_SECS))
+}
+
+// the `sq host` command line that serves `spec`
+fn host_command(spec: &BackendSpec) -> Result<Command, String> {
+    let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+    let mut command = Command::new(exe);
+    match spec.socket {
+        Some(ref path) => command.arg("host").arg("--socket").arg(path),
+        None => command.arg("host").arg(spec.port.to_string()).arg("--bind").arg(&spec.bind),
+    };
+    command.arg("--key").arg(&spec.token).arg("--data-dir").arg(&spec.data_dir);
+    Ok(command)
+}
+
+fn spawn(spec: &BackendSpec, command: fn(&BackendSpec) -> Result<Command, String>) -> Result<Child, String> {
+    fs::create_dir_all(&spec.data_dir).map_err(|e| format!("cannot create {}: {}", spec.data_dir, e))?;
+    let log_path = std::path::Path::new(&spec.data_dir).join(LOG_FILE);
+    let log = OpenOptions::new().create(true).append(true).open(&log_path)
+        .map_err(|e| format!("cannot open {}: {}", log_path.display(), e))?;
+    let mut command = command(spec)?;
+    command.stdin(Stdio::null())
+        .stdout(log.try_clone().map_err(|e| e.to_string())?)
+        .stderr(log);
+    #[cfg(target_os = "linux")]
+    unsafe {
+        use std::os::unix::process::CommandExt;
+        command.pre_exec(|| {
+            // don't outlive the router, even if it is killed outright
+            libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM);
+            Ok(())
+        });
+    }
+    command.spawn().map_err(|e| e.to_string())
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// stop: SIGTERM to every child at once, then SIGKILL to those still running after the grace period
+// -----------------------------------------------------------------------------------------------------------
+fn stop(mut children: Vec<Child>) {
+    for child in &children {
+        #[cfg(unix)]
+        unsafe {
+            libc::kill(child.id() as libc::pid_t, libc::SIGTERM);
+        }
+        #[cfg(not(unix))]
+        let _ = child;
+    }
+    let deadline = Instant::now() + Duration::from_millis(STOP_GRACE_MS);
+    while !children.is_empty() && Instant::now() < deadline {
+        children.retain_mut(|child| !matches!(child.try_wait(), Ok(Some(_))));
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    for mut child in children {
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+impl Supervisor {
+    // -------------------------------------------------------------------------------------------------------
+    // tick: brings the running children in line with `desired`
+    // -------------------------------------------------------------------------------------------------------
+    pub fn tick(&mut self, desired: &[BackendSpec]) {
+        let now = Instant::now();
+
+        // stop children that are no longer wanted, or wanted differently
+        let stale: Vec<String> = self.backends.iter()
+            .filter(|(_, supervised)| !desired.iter().any(|spec| spec.same_process(&supervised.spec)))
+            .map(|(listen, _)| listen.clone())
+            .collect();
+        let mut stopping = Vec::new();
+        for listen in stale {
+            if let Some(supervised) = self.backends.remove(&listen) {
+                if let Some(child) = supervised.child {
+                    println!("Stopping backend {} ({}, pid {})", supervised.spec.name, listen, child.id());
+                    stopping.push(child);
+                }
+            }
+        }
+        stop(stopping);
+
+        for spec in desired {
+            let supervised = self.backends.entry(spec.listen()).or_insert_with(|| Supervised {
+                spec: spec.clone(),
+                child: None,
+                started: now,
+                failures: 0,
+                next_start: now,
+            });
+            supervised.spec.name = spec.name.clone();
+
+            // reap a child that exited and schedule its restart
+            if let Some(ref mut child) = supervised.child {
+                match child.try_wait() {
+                    Ok(None) => {
+                        if supervised.failures > 0 && now.duration_since(supervised.started) >= Duration::from_secs(STABLE_SECS) {
+                            supervised.failures = 0;
+                        }
+                        continue;
+                    }
+                    Ok(Some(status)) => {
+                        supervised.failures += 1;
+                        let delay = backoff(supervised.failures);
+                        eprintln!("Warning: backend {} ({}) exited ({}), restarting in {}s",
+                            spec.name, spec.listen(), status, delay.as_secs());
+                        supervised.child = None;
+                        supervised.next_start = now + delay;
+                    }
+                    Err(e) => {
+                        eprintln!("Warning: cannot check backend {} ({}): {}", spec.name, spec.listen(), e);
+                        continue;
+                    }
+                }
+            }
+
+            if supervised.child.is_none() && now >= supervised.next_start {
+                match spawn(spec, self.command) {
+                    Ok(child) => {
+                        println!("Started backend {} ({}, pid {})", spec.name, spec.listen(), child.id());
+                        supervised.child = Some(child);
+                        supervised.started = now;
+                    }
+                    Err(e) => {
+                        supervised.failures += 1;
+                        let delay = backoff(supervised.failures);
+                        eprintln!("Warning: cannot start backend {} ({}): {}, retrying in {}s",
+                            spec.name, spec.listen(), e, delay.as_secs());
+                        supervised.next_start = now + delay;
+                    }
+                }
+            }
+        }
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // stop_all: stops every child (on router exit)
+    // -------------------------------------------------------------------------------------------------------
+    pub fn stop_all(&mut self) {
+        self.tick(&[]);
+    }
+
+    pub fn running(&self) -> usize {
+        self.backends.values().filter(|s| s.child.is_some()).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::TempDir;
+
+    // stand-ins for `sq host`: one that fails straight away, one that stays up until it is stopped
+    fn exits(_: &BackendSpec) -> Result<Command, String> {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("exit 1");
+        Ok(command)
+    }
+
+    fn stays_up(_: &BackendSpec) -> Result<Command, String> {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("exec sleep 60");
+        Ok(command)
+    }
+
+    fn supervisor(command: fn(&BackendSpec) -> Result<Command, String>) -> Supervisor {
+        Supervisor { backends: HashMap::new(), command }
+    }
+
+    fn spec(dir: &TempDir, port: u16) -> BackendSpec {
+        BackendSpec {
+            name: format!("tenant{}", port),
+            port,
+            bind: "127.0.0.1".to_string(),
+            socket: None,
+            token: "secret".to_string(),
+            data_dir: dir.path("data"),
+        }
+    }
+
+    fn wait_for_exit(supervisor: &mut Supervisor, listen: &str) {
+        let child = supervisor.backends.get_mut(listen).unwrap().child.as_mut().unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if let Ok(Some(status)) = child.try_wait() {
+                assert!(!status.success());
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("child did not exit");
+    }
+
+    #[cfg(unix)]
+    fn alive(pid: u32) -> bool {
+        unsafe { libc::kill(pid as libc::pid_t, 0) == 0 }
+    }
+
+    #[test]
+    fn test_backoff_doubles_and_caps() {
+        let delays: Vec<u64> = (1..=8).map(|n| backoff(n).as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 16, 32, 60, 60]);
+        assert_eq!(backoff(1000).as_secs(), 60);
+    }
+
+    #[test]
+    fn test_restarts_an_exited_child() {
+        let dir = TempDir::new("supervisor-restart");
+        let backend = spec(&dir, 41001);
+        let mut supervisor = supervisor(exits);
+        supervisor.tick(std::slice::from_ref(&backend));
+        assert_eq!(supervisor.running(), 1);
+        assert!(std::path::Path::new(&backend.data_dir).join(LOG_FILE).exists());
+
+        // the exit is noticed on the next tick, and the restart waits out the backoff
+        wait_for_exit(&mut supervisor, &backend.listen());
+        supervisor.tick(std::slice::from_ref(&backend));
+        assert_eq!(supervisor.running(), 0);
+        let supervised = &supervisor.backends[&backend.listen()];
+        assert_eq!(supervised.failures, 1);
+        assert!(supervised.next_start > Instant::now());
+
+        supervisor.backends.get_mut(&backend.listen()).unwrap().next_start = Instant::now();
+        supervisor.tick(std::slice::from_ref(&backend));
+        assert_eq!(supervisor.running(), 1);
+
+        // a second exit doubles the backoff
+        wait_for_exit(&mut supervisor, &backend.listen());
+        supervisor.tick(std::slice::from_ref(&backend));
+        let supervised = &supervisor.backends[&backend.listen()];
+        assert_eq!(supervised.failures, 2);
+        assert!(supervised.next_start > Instant::now() + backoff(1));
+
+        supervisor.stop_all();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_stops_removed_and_changed_children() {
+        let dir = TempDir::new("supervisor-removed");
+        let first = spec(&dir, 41002);
+        let second = spec(&dir, 41003);
+        let mut supervisor = supervisor(stays_up);
+        supervisor.tick(&[first.clone(), second.clone()]);
+        assert_eq!(supervisor.running(), 2);
+        let first_pid = supervisor.backends[&first.listen()].child.as_ref().unwrap().id();
+        let second_pid = supervisor.backends[&second.listen()].child.as_ref().unwrap().id();
+
+        // removing a backend stops its child and leaves the others alone
+        supervisor.tick(std::slice::from_ref(&second));
+        assert_eq!(supervisor.running(), 1);
+        assert!(!supervisor.backends.contains_key(&first.listen()));
+        assert!(!alive(first_pid));
+        assert!(alive(second_pid));
+
+        // a new token means a new child; a new name doesn't
+        let mut renamed = second.clone();
+        renamed.name = "renamed".to_string();
+        supervisor.tick(std::slice::from_ref(&renamed));
+        assert_eq!(supervisor.backends[&second.listen()].child.as_ref().unwrap().id(), second_pid);
+        let mut rekeyed = renamed.clone();
+        rekeyed.token = "rotated".to_string();
+        supervisor.tick(std::slice::from_ref(&rekeyed));
+        assert_eq!(supervisor.running(), 1);
+        assert!(!alive(second_pid));
+        assert_ne!(supervisor.backends[&second.listen()].child.as_ref().unwrap().id(), second_pid);
+
+        supervisor.stop_all();
+    }
+
+    #[test]
+    fn test_backoff_resets_once_stable() {
+        let dir = TempDir::new("supervisor-stable");
+        let backend = spec(&dir, 41004);
+        let mut supervisor = supervisor(stays_up);
+        supervisor.tick(std::slice::from_ref(&backend));
+        let supervised = supervisor.backends.get_mut(&backend.listen()).unwrap();
+        supervised.failures = 3;
+
+        // not up long enough yet
+        supervisor.tick(std::slice::from_ref(&backend));
+        assert_eq!(supervisor.backends[&backend.listen()].failures, 3);
+
+        let supervised = supervisor.backends.get_mut(&backend.listen()).unwrap();
+        supervised.started = Instant::now().checked_sub(Duration::from_secs(STABLE_SECS)).unwrap();
+        supervisor.tick(std::slice::from_ref(&backend));
+        assert_eq!(supervisor.backends[&backend.listen()].failures, 0);
+
+        supervisor.stop_all();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_stop_all() {
+        let dir = TempDir::new("supervisor-stop-all");
+        let backends = [spec(&dir, 41005), spec(&dir, 41006)];
+        let mut supervisor = supervisor(stays_up);
+        supervisor.tick(&backends);
+        assert_eq!(supervisor.running(), 2);
+        let pids: Vec<u32> = supervisor.backends.values().map(|s| s.child.as_ref().unwrap().id()).collect();
+
+        supervisor.stop_all();
+        assert_eq!(supervisor.running(), 0);
+        assert!(supervisor.backends.is_empty());
+        assert!(pids.iter().all(|pid| !alive(*pid)));
+    }
+}
@@ -5,9 +5,57 @@
 // note: You can run these tests with `cargo test`.
 //------------------------------------------------------------------------------------------------------------
 
+
 #[cfg(test)]
 use libphext::phext;
 
+#[cfg(test)]
+fn request(connection_id: u64, source: &str, command: &str, coordinate: phext::Coordinate, update: &str, filename: &str) -> crate::sq::Request {
+    crate::sq::Request {
+        connection_id,
+        source: source.to_string(),
+        command: command.to_string(),
+        coordinate,
+        update: update.to_string(),
+        filename: filename.to_string(),
+        algorithm: crate::HashAlgorithm::Xor,
+        limit: 100,
+    }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// TempDir: a scratch directory for one test, removed with everything in it when dropped (even on a failure)
+// -----------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+pub struct TempDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let dir = std::env::temp_dir().join(format!("sq-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    pub fn path(&self, file: &str) -> String {
+        self.0.join(file).to_string_lossy().to_string()
+    }
+
+    // writes `contents` to `file` in the directory and returns its path
+    pub fn write(&self, file: &str, contents: &str) -> String {
+        let path = self.path(file);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
 #[test]
 fn test_insert() {
   let mut scroll = String::new();
@@ -16,12 +64,12 @@ fn test_insert() {
   let coordinate = phext::to_coordinate("1.1.1/1.1.1/1.1.2");
   let update = "Hello World!".to_string();
   let filename = "insert.phext".to_string();
-  let mut map = phext::explode(&buffer);
-  let done = crate::sq::process(1, "memory".to_string(), &mut scroll, command, &mut map, coordinate, update, filename, crate::HashAlgorithm::Xor, 100);
-  let buffer = phext::implode(map);
+  let mut map = crate::store::ScrollStore::from(phext::explode(&buffer));
+  let done = crate::sq::process(&request(1, "memory", &command, coordinate, &update, &filename), &mut scroll, &mut map);
+  let buffer = map.implode();
 
   assert_eq!(buffer, "\x17Hello World!");
-  assert_eq!(done, false);
+  assert!(!done);
 }
 
 #[test]
@@ -32,12 +80,12 @@ fn test_select() {
   let coordinate = phext::to_coordinate("1.1.1/1.1.1/1.1.3");
   let update = "ignored text".to_string();
   let filename = "select.phext".to_string();
-  let mut map = phext::explode(&buffer);
-  let done = crate::sq::process(1, "memory".to_string(), &mut scroll, command, &mut map, coordinate, update, filename, crate::HashAlgorithm::Xor, 100);
+  let mut map = crate::store::ScrollStore::from(phext::explode(&buffer));
+  let done = crate::sq::process(&request(1, "memory", &command, coordinate, &update, &filename), &mut scroll, &mut map);
 
   assert_eq!(buffer, "\x17\x17Third Scroll Content");
   assert_eq!(scroll, "Third Scroll Content");
-  assert_eq!(done, false);
+  assert!(!done);
 }
 
 #[test]
@@ -48,13 +96,13 @@ fn test_update() {
   let coordinate = phext::to_coordinate("1.1.1/1.1.1/1.2.2");
   let update = "Full Rewrite at 1.2.2".to_string();
   let filename = "update.phext".to_string();
-  let mut map = phext::explode(&buffer);
-  let done = crate::sq::process(1, "memory".to_string(), &mut scroll, command, &mut map, coordinate, update, filename, crate::HashAlgorithm::Xor, 100);
-  let buffer = phext::implode(map);
+  let mut map = crate::store::ScrollStore::from(phext::explode(&buffer));
+  let done = crate::sq::process(&request(1, "memory", &command, coordinate, &update, &filename), &mut scroll, &mut map);
+  let buffer = map.implode();
 
   assert_eq!(buffer, "\x18\x17Full Rewrite at 1.2.2");
   assert_eq!(scroll, "Updated 21 bytes");
-  assert_eq!(done, false);
+  assert!(!done);
 }
 
 #[test]
@@ -65,13 +113,13 @@ fn test_delete() {
   let coordinate = phext::to_coordinate("1.1.1/1.1.1/1.2.2");
   let update = "".to_string();
   let filename = "delete.phext".to_string();
-  let mut map = phext::explode(&buffer);
-  let done = crate::sq::process(1, "memory".to_string(), &mut scroll, command, &mut map, coordinate, update, filename, crate::HashAlgorithm::Xor, 100);
-  let buffer = phext::implode(map);
+  let mut map = crate::store::ScrollStore::from(phext::explode(&buffer));
+  let done = crate::sq::process(&request(1, "memory", &command, coordinate, &update, &filename), &mut scroll, &mut map);
+  let buffer = map.implode();
 
   assert_eq!(buffer, "");
   assert_eq!(scroll, "Removed 21 bytes");
-  assert_eq!(done, false);
+  assert!(!done);
 }
 
 #[test]
@@ -82,13 +130,13 @@ fn test_save() {
   let coordinate = phext::to_coordinate("1.1.1/1.1.1/1.2.2");
   let update = "Save Test at 1.2.2".to_string();
   let filename = "save.phext".to_string();
-  let mut map = phext::explode(&buffer);
-  let done = crate::sq::process(1, "memory".to_string(), &mut scroll, command, &mut map, coordinate, update, filename, crate::HashAlgorithm::Xor, 100);
-  let buffer = phext::implode(map);
+  let mut map = crate::store::ScrollStore::from(phext::explode(&buffer));
+  let done = crate::sq::process(&request(1, "memory", &command, coordinate, &update, &filename), &mut scroll, &mut map);
+  let buffer = map.implode();
 
   assert_eq!(buffer, "\x18\x17Save Test");
   assert_eq!(scroll, "Wrote 11 bytes to save.phext");
-  assert_eq!(done, false);
+  assert!(!done);
 
   std::fs::remove_file("save.phext").expect("Unable to find save.phext");
 }
@@ -114,28 +162,28 @@ fn test_toc() {
 fn test_auth_valid_bearer() {
   let key = Some("pmb-v1-abc123".to_string());
   let header = "GET /api/v2/version HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer pmb-v1-abc123\r\n\r\n";
-  assert_eq!(crate::validate_auth(header, &key), true);
+  assert!(crate::validate_auth(header, &key));
 }
 
 #[test]
 fn test_auth_invalid_key() {
   let key = Some("pmb-v1-abc123".to_string());
   let header = "GET /api/v2/version HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer pmb-v1-wrong\r\n\r\n";
-  assert_eq!(crate::validate_auth(header, &key), false);
+  assert!(!crate::validate_auth(header, &key));
 }
 
 #[test]
 fn test_auth_missing_header() {
   let key = Some("pmb-v1-abc123".to_string());
   let header = "GET /api/v2/version HTTP/1.1\r\nHost: localhost\r\n\r\n";
-  assert_eq!(crate::validate_auth(header, &key), false);
+  assert!(!crate::validate_auth(header, &key));
 }
 
 #[test]
 fn test_auth_disabled() {
   let key: Option<String> = None;
   let header = "GET /api/v2/version HTTP/1.1\r\nHost: localhost\r\n\r\n";
-  assert_eq!(crate::validate_auth(header, &key), true);
+  assert!(crate::validate_auth(header, &key));
 }
 
 #[test]
@@ -178,14 +226,14 @@ fn convert_from_xml() {
 fn test_exit() {
   let mut scroll = String::new();
   let command = "shutdown".to_string();
-  let mut buffer = phext::explode("");
+  let mut buffer = crate::store::ScrollStore::from(phext::explode(""));
   let coordinate = phext::to_coordinate("1.1.1/1.1.1/1.1.1");
   let update = "Shutdown Test".to_string();
   let filename = "shutdown.phext".to_string();
 
-  let done = crate::sq::process(1, "memory".to_string(), &mut scroll, command, &mut buffer, coordinate, update, filename, crate::HashAlgorithm::Xor, 100);
+  let done = crate::sq::process(&request(1, "memory", &command, coordinate, &update, &filename), &mut scroll, &mut buffer);
 
-  assert_eq!(done, true);
+  assert!(done);
 }
 
 // =========================================================================================================
@@ -359,52 +407,140 @@ fn test_churn_insert_delete_implode_ref() {
         "implode_ref must match after insert+delete churn");
 }
 
-// Verify the connection counter tracks correctly
+// Verify a pool built with the server's limits runs at most WORKER_THREADS jobs at once and refuses
+// connections past WORKER_QUEUE_DEPTH
 #[test]
-fn test_active_connection_counter() {
-    use std::sync::atomic::Ordering;
+fn test_worker_pool_limits() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::time::{Duration, Instant};
+
+    let pool = crate::pool::WorkerPool::new(crate::WORKER_THREADS, crate::WORKER_QUEUE_DEPTH);
+    let running = Arc::new(AtomicUsize::new(0));
+    let peak = Arc::new(AtomicUsize::new(0));
+    let finished = Arc::new(AtomicUsize::new(0));
+    let release = Arc::new((Mutex::new(false), Condvar::new()));
+    let submit = |n: usize| {
+        let (running, peak, finished, release) = (Arc::clone(&running), Arc::clone(&peak), Arc::clone(&finished), Arc::clone(&release));
+        pool.try_execute(n, move |_| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            let (lock, released) = &*release;
+            let mut go = lock.lock().unwrap();
+            while !*go {
+                go = released.wait(go).unwrap();
+            }
+            drop(go);
+            running.fetch_sub(1, Ordering::SeqCst);
+            finished.fetch_add(1, Ordering::SeqCst);
+        })
+    };
+    let wait_for = |done: &dyn Fn() -> bool| {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !done() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert!(done());
+    };
 
-    let before = crate::ACTIVE_CONNECTIONS.load(Ordering::Relaxed);
-    crate::ACTIVE_CONNECTIONS.fetch_add(1, Ordering::Relaxed);
-    crate::ACTIVE_CONNECTIONS.fetch_add(1, Ordering::Relaxed);
-    let during = crate::ACTIVE_CONNECTIONS.load(Ordering::Relaxed);
-    assert_eq!(during, before + 2);
+    // fill every worker, then the whole queue
+    for n in 0..crate::WORKER_THREADS {
+        assert!(submit(n).is_ok());
+    }
+    wait_for(&|| running.load(Ordering::SeqCst) == crate::WORKER_THREADS);
+    for n in 0..crate::WORKER_QUEUE_DEPTH {
+        assert!(submit(crate::WORKER_THREADS + n).is_ok());
+    }
+    assert_eq!(pool.queue_length(), crate::WORKER_QUEUE_DEPTH);
+    std::thread::sleep(Duration::from_millis(50));
+    assert_eq!(running.load(Ordering::SeqCst), crate::WORKER_THREADS);
+
+    // one more is handed back for the listener to answer 503
+    let overflow = crate::WORKER_THREADS + crate::WORKER_QUEUE_DEPTH;
+    assert_eq!(submit(overflow), Err(overflow));
+
+    let (lock, released) = &*release;
+    *lock.lock().unwrap() = true;
+    released.notify_all();
+    wait_for(&|| finished.load(Ordering::SeqCst) == overflow);
+    assert_eq!(peak.load(Ordering::SeqCst), crate::WORKER_THREADS);
+    assert_eq!(pool.queue_length(), 0);
+}
 
-    crate::ACTIVE_CONNECTIONS.fetch_sub(1, Ordering::Relaxed);
-    crate::ACTIVE_CONNECTIONS.fetch_sub(1, Ordering::Relaxed);
-    let after = crate::ACTIVE_CONNECTIONS.load(Ordering::Relaxed);
-    assert_eq!(after, before, "Counter must return to baseline after sub");
+// Verify that a second compaction of a phext (the compactor and an eviction at once) waits for the first, so
+// the first one's older snapshot can't land over the second's and lose what was logged in between
+#[test]
+fn test_concurrent_compactions_keep_every_mutation() {
+    use std::sync::{Arc, RwLock};
+
+    let dir = TempDir::new("compaction");
+    let phext = dir.path("race.phext");
+    let first = phext::to_coordinate("1.1.1/1.1.1/1.1.1");
+    let second = phext::to_coordinate("1.1.1/1.1.1/1.1.2");
+    let state = Arc::new(RwLock::new(crate::ServerState::new()));
+    let compaction = {
+        let mut state = state.write().unwrap();
+        state.load(&phext, crate::wal::Durability::None);
+        state.loaded_map.insert(first, "first".to_string());
+        state.persist(first).unwrap();
+        Arc::clone(&state.compaction)
+    };
+
+    // the first compaction rotates and takes its snapshot, then a mutation is logged before it writes
+    let guard = compaction.lock().unwrap();
+    let snapshot = {
+        let mut state = state.write().unwrap();
+        state.wal.as_mut().unwrap().rotate().unwrap();
+        state.loaded_map.implode()
+    };
+    {
+        let mut state = state.write().unwrap();
+        state.loaded_map.insert(second, "second".to_string());
+        state.persist(second).unwrap();
+    }
+    let racing = {
+        let state = Arc::clone(&state);
+        std::thread::spawn(move || crate::compact_state(&state, true))
+    };
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    assert!(!state.read().unwrap().wal.as_ref().unwrap().is_empty(), "the second compaction must wait");
+
+    crate::persist::write_atomic(&phext, &snapshot).unwrap();
+    crate::wal::finish_compaction(&phext).unwrap();
+    drop(guard);
+    racing.join().unwrap();
+
+    let mut reloaded = crate::ServerState::new();
+    reloaded.load(&phext, crate::wal::Durability::None);
+    assert_eq!(reloaded.loaded_map.get(&second).map(|s| s.as_str()), Some("second"));
+    assert_eq!(std::fs::read_to_string(&phext).unwrap(), state.read().unwrap().loaded_map.implode());
 }
 
 // Verify MAX_BODY_SIZE is sane
 #[test]
 fn test_max_body_size_sane() {
-    assert!(crate::MAX_BODY_SIZE <= crate::MAX_BUFFER_SIZE,
-        "MAX_BODY_SIZE ({}) should not exceed MAX_BUFFER_SIZE ({})",
-        crate::MAX_BODY_SIZE, crate::MAX_BUFFER_SIZE);
-    assert!(crate::MAX_BODY_SIZE >= 1024 * 1024,
+    const _: () = assert!(crate::MAX_BODY_SIZE <= crate::MAX_BUFFER_SIZE,
+        "MAX_BODY_SIZE should not exceed MAX_BUFFER_SIZE");
+    const _: () = assert!(crate::MAX_BODY_SIZE >= 1024 * 1024,
         "MAX_BODY_SIZE should be at least 1 MB for practical use");
 }
 
 // Verify that status command uses implode_ref (no clone) by checking output format
 #[test]
 fn test_status_output_format() {
-    use std::collections::HashMap;
-    let mut map: HashMap<phext::Coordinate, String> = HashMap::new();
+    let mut map = crate::store::ScrollStore::new();
     map.insert(phext::to_coordinate("1.1.1/1.1.1/1.1.1"), "hello".to_string());
     map.insert(phext::to_coordinate("1.1.1/1.1.1/1.1.2"), "world".to_string());
 
     let mut scroll = String::new();
-    crate::sq::process(
-        42, "test.phext".to_string(), &mut scroll, "status".to_string(),
-        &mut map, phext::to_coordinate("1.1.1/1.1.1/1.1.1"),
-        String::new(), String::new(), crate::HashAlgorithm::Xor, 100,
-    );
+    crate::sq::process(&request(42, "test.phext", "status", phext::to_coordinate("1.1.1/1.1.1/1.1.1"), "", ""), &mut scroll, &mut map);
 
     assert!(scroll.contains("Connection ID: 42"));
     assert!(scroll.contains("Hosting: test.phext"));
     assert!(scroll.contains("Phext Size:"));
     assert!(scroll.contains("Scrolls:"));
+    assert!(scroll.contains("Queue Length:"));
+    assert!(scroll.contains("Workers Busy:"));
 }
 
 // Stress test: many small mutations followed by implode_ref serialization
@@ -430,3 +566,305 @@ fn test_mutation_flush_pressure() {
     assert_eq!(final_ref, final_clone,
         "After 500 mutations, implode_ref must still match implode");
 }
+
+// =========================================================================================================
+// Range and prefix selection
+// =========================================================================================================
+
+#[cfg(test)]
+fn selection_fixture() -> crate::store::ScrollStore {
+    let mut map = crate::store::ScrollStore::new();
+    map.insert(phext::to_coordinate("1.1.1/1.1.1/1.1.1"), "self".to_string());
+    map.insert(phext::to_coordinate("2.1.1/1.1.1/1.1.2"), "play-b".to_string());
+    map.insert(phext::to_coordinate("2.1.1/1.1.1/1.1.1"), "play-a".to_string());
+    map.insert(phext::to_coordinate("2.1.1/3.1.1/1.1.1"), "play-c".to_string());
+    map.insert(phext::to_coordinate("2.2.1/1.1.1/1.1.1"), "play-other".to_string());
+    map.insert(phext::to_coordinate("5.1.1/1.1.1/1.1.1"), "work".to_string());
+    map
+}
+
+#[cfg(test)]
+fn run_selection(command: &str, args: &str) -> String {
+    let mut map = selection_fixture();
+    let mut scroll = String::new();
+    crate::sq::process(&request(1, "memory", command, phext::to_coordinate("1.1.1/1.1.1/1.1.1"), args, ""), &mut scroll, &mut map);
+    scroll
+}
+
+#[test]
+fn test_coordinate_pattern_parse() {
+    let pattern = crate::sq::CoordinatePattern::parse("2.1.1/*/*").unwrap();
+    assert!(pattern.matches(&phext::to_coordinate("2.1.1/4.5.6/7.8.9")));
+    assert!(!pattern.matches(&phext::to_coordinate("2.2.1/1.1.1/1.1.1")));
+
+    let library = crate::sq::CoordinatePattern::parse("5.x.x/*/*").unwrap();
+    assert!(library.matches(&phext::to_coordinate("5.9.3/1.1.1/1.1.1")));
+    assert!(!library.matches(&phext::to_coordinate("1.9.3/1.1.1/1.1.1")));
+
+    assert!(crate::sq::CoordinatePattern::parse("0.1.1/*/*").is_none());
+    assert!(crate::sq::CoordinatePattern::parse("1.1.1.1/*/*").is_none());
+    assert!(crate::sq::CoordinatePattern::parse("a.b.c").is_none());
+}
+
+#[test]
+fn test_coordinate_filter_restrict() {
+    use crate::sq::CoordinateFilter;
+    let work = CoordinateFilter::parse(&["5.x.x/*/*"], &["5.9.x/*/*"]).unwrap();
+    assert!(work.matches(&phext::to_coordinate("5.1.1/1.1.1/1.1.1")));
+    assert!(!work.matches(&phext::to_coordinate("5.9.1/1.1.1/1.1.1")));
+    assert!(!work.matches(&phext::to_coordinate("1.1.1/1.1.1/1.1.1")));
+    assert!(CoordinateFilter::default().matches(&phext::to_coordinate("1.1.1/1.1.1/1.1.1")));
+    assert!(CoordinateFilter::parse(&["5.x"], &["nope"]).is_err());
+
+    let narrow = CoordinateFilter::parse(&["*/2.x.x/*"], &[]).unwrap().restrict(&work);
+    assert!(narrow.matches(&phext::to_coordinate("5.1.1/2.1.1/1.1.1")));
+    assert!(!narrow.matches(&phext::to_coordinate("5.1.1/1.1.1/1.1.1")));
+    assert!(!narrow.matches(&phext::to_coordinate("5.9.1/2.1.1/1.1.1")));
+    assert_eq!(CoordinateFilter::from_lines(&narrow.to_lines()).unwrap(), narrow);
+
+    let disjoint = CoordinateFilter::parse(&["1.x.x/*/*"], &[]).unwrap().restrict(&work);
+    assert!(!disjoint.matches(&phext::to_coordinate("1.1.1/1.1.1/1.1.1")));
+    assert!(!disjoint.matches(&phext::to_coordinate("5.1.1/1.1.1/1.1.1")));
+}
+
+#[test]
+fn test_coordinate_pattern_prefix() {
+    use crate::sq::CoordinatePattern;
+    let library = CoordinatePattern::prefix(&[5, 1]);
+    assert_eq!(library.to_string(), "5.1.*/*.*.*/*.*.*");
+    assert_eq!(CoordinatePattern::parse(&library.to_string()), Some(library.clone()));
+    assert_eq!(library.as_prefix(), Some(vec![5, 1]));
+    assert_eq!(CoordinatePattern::parse("*/*/*").unwrap().as_prefix(), Some(vec![]));
+    assert_eq!(CoordinatePattern::parse("5.x.2/*/*").unwrap().as_prefix(), None);
+}
+
+#[test]
+fn test_merkle_command_answers_children() {
+    let roots = crate::merkle::parse_answer(&run_selection("merkle", "*/*/*"));
+    let libraries: Vec<(usize, usize)> = roots.iter().map(|(p, s)| (p[0], s.count)).collect();
+    assert_eq!(libraries, vec![(1, 1), (2, 4), (5, 1)]);
+
+    // no prefixes: the root itself
+    let root = run_selection("merkle", "");
+    assert!(root.starts_with("*.*.*/*.*.*/*.*.*: ") && root.ends_with(" 6\n"), "{}", root);
+
+    // a filter narrows the tree the same way it narrows delta
+    let filtered = crate::merkle::parse_answer(&run_selection("merkle", "include: 2.1.x/*/*\n*/*/*\n2.*.*/*.*.*/*.*.*"));
+    let found: Vec<(Vec<usize>, usize)> = filtered.iter().map(|(p, s)| (p.clone(), s.count)).collect();
+    assert_eq!(found, vec![(vec![2], 3), (vec![2, 1], 3)]);
+}
+
+#[test]
+fn test_delta_respects_filter() {
+    let request = "include: 2.x.x/*/*\nexclude: 2.2.x/*/*\n2.1.1/1.1.1/1.1.1: stale\n9.1.1/1.1.1/1.1.1: abc";
+    let map = phext::explode(&run_selection("delta", request));
+    let mut found: Vec<&str> = map.values().filter(|v| !v.is_empty()).map(|v| v.as_str()).collect();
+    found.sort();
+    assert_eq!(found, vec!["play-a", "play-b", "play-c"]);
+}
+
+#[test]
+fn test_select_prefix_fragment() {
+    let page = run_selection("select-prefix", "prefix=2.1.1/*/*");
+    let (cursor, fragment) = page.split_once('\n').unwrap();
+    assert_eq!(cursor, "next=");
+    let map = phext::explode(fragment);
+    let found: Vec<&str> = map.values().filter(|v| !v.is_empty()).map(|v| v.as_str()).collect();
+    assert_eq!(found.len(), 3);
+    assert_eq!(map.get(&phext::to_coordinate("2.1.1/1.1.1/1.1.1")).map(|s| s.as_str()), Some("play-a"));
+    assert_eq!(map.get(&phext::to_coordinate("2.1.1/3.1.1/1.1.1")).map(|s| s.as_str()), Some("play-c"));
+}
+
+#[test]
+fn test_select_range_json_pagination() {
+    let args = "from=2.1.1/1.1.1/1.1.1&to=5.1.1/1.1.1/1.1.1&limit=2&format=json";
+    let first: serde_json::Value = serde_json::from_str(&run_selection("select-range", args)).unwrap();
+    let coords: Vec<&str> = first["scrolls"].as_array().unwrap().iter().map(|s| s["coord"].as_str().unwrap()).collect();
+    assert_eq!(coords, vec!["2.1.1/1.1.1/1.1.1", "2.1.1/1.1.1/1.1.2"]);
+    assert_eq!(first["next"], "2.1.1/1.1.1/1.1.2");
+
+    let next = format!("{}&after={}", args, first["next"].as_str().unwrap());
+    let second: serde_json::Value = serde_json::from_str(&run_selection("select-range", &next)).unwrap();
+    let coords: Vec<&str> = second["scrolls"].as_array().unwrap().iter().map(|s| s["coord"].as_str().unwrap()).collect();
+    assert_eq!(coords, vec!["2.1.1/3.1.1/1.1.1", "2.2.1/1.1.1/1.1.1"]);
+
+    let next = format!("{}&after={}", args, second["next"].as_str().unwrap());
+    let last: serde_json::Value = serde_json::from_str(&run_selection("select-range", &next)).unwrap();
+    assert_eq!(last["scrolls"][0]["scroll"], "work");
+    assert!(last["next"].is_null());
+}
+
+#[test]
+fn test_select_range_fragment_pagination() {
+    let args = "from=2.1.1/1.1.1/1.1.1&to=5.1.1/1.1.1/1.1.1&limit=3";
+    let first = run_selection("select-range", args);
+    let (cursor, fragment) = first.split_once('\n').unwrap();
+    assert_eq!(cursor, "next=2.1.1/3.1.1/1.1.1");
+    assert_eq!(phext::explode(fragment).values().filter(|v| !v.is_empty()).count(), 3);
+
+    let last = run_selection("select-range", &format!("{}&after={}", args, &cursor["next=".len()..]));
+    let (cursor, fragment) = last.split_once('\n').unwrap();
+    assert_eq!(cursor, "next=");
+    let map = phext::explode(fragment);
+    assert_eq!(map.get(&phext::to_coordinate("2.2.1/1.1.1/1.1.1")).map(|s| s.as_str()), Some("play-other"));
+    assert_eq!(map.get(&phext::to_coordinate("5.1.1/1.1.1/1.1.1")).map(|s| s.as_str()), Some("work"));
+}
+
+#[test]
+fn test_select_prefix_invalid() {
+    assert_eq!(run_selection("select-prefix", "prefix=nope"), "Invalid coordinate prefix: nope");
+}
+
+#[test]
+fn test_search_prefix_restriction() {
+    assert_eq!(run_selection("search", "q=play").lines().count(), 5);
+    let scoped = run_selection("search", "q=play&prefix=2.1.1/*/*");
+    assert_eq!(scoped, "next=\n* 2.1.1/1.1.1/1.1.1: play-a\n* 2.1.1/1.1.1/1.1.2: play-b\n* 2.1.1/3.1.1/1.1.1: play-c\n");
+
+    // the phext output carries the same cursor as select-range and select-prefix
+    let first = run_selection("search", "q=play&limit=2");
+    assert_eq!(first, "next=2.1.1/1.1.1/1.1.2\n* 2.1.1/1.1.1/1.1.1: play-a\n* 2.1.1/1.1.1/1.1.2: play-b\n");
+    assert!(run_selection("search", "q=play&limit=2&after=2.1.1/1.1.1/1.1.2").starts_with("next=\n* 2.1.1/3.1.1/1.1.1: play-c\n"));
+
+    let page: serde_json::Value = serde_json::from_str(&run_selection("search", "q=play&limit=3&format=json")).unwrap();
+    assert_eq!(page["results"].as_array().unwrap().len(), 3);
+    assert_eq!(page["next"], "2.1.1/3.1.1/1.1.1");
+}
+
+#[test]
+fn test_search_phrase_and_prefix_terms() {
+    assert_eq!(run_selection("search", "q=%22play+other%22"), "next=\n* 2.2.1/1.1.1/1.1.1: play-other\n");
+    assert_eq!(run_selection("search", "q=%22other+play%22"), "next=\n");
+    assert_eq!(run_selection("search", "q=wor*"), "next=\n* 5.1.1/1.1.1/1.1.1: work\n");
+    assert_eq!(run_selection("search", "q="), "Search requires a query (q=...)");
+}
+
+#[test]
+fn test_search_follows_updates() {
+    let mut map = selection_fixture();
+    let mut scroll = String::new();
+    let mut run = |map: &mut crate::store::ScrollStore, command: &str, coordinate: &str, update: &str| {
+        crate::sq::process(&request(1, "memory", command, phext::to_coordinate(coordinate), update, ""), &mut scroll, map);
+        scroll.clone()
+    };
+    assert_eq!(run(&mut map, "search", "1.1.1/1.1.1/1.1.1", "q=rest"), "next=\n");
+    run(&mut map, "update", "5.1.1/1.1.1/1.1.1", "time to rest");
+    assert_eq!(run(&mut map, "search", "1.1.1/1.1.1/1.1.1", "q=rest"), "next=\n* 5.1.1/1.1.1/1.1.1: time to rest\n");
+    run(&mut map, "delete", "5.1.1/1.1.1/1.1.1", "");
+    assert_eq!(run(&mut map, "search", "1.1.1/1.1.1/1.1.1", "q=rest"), "next=\n");
+}
+
+#[cfg(test)]
+fn run_json(map: &mut crate::store::ScrollStore, command: &str, update: &str, filename: &str) -> String {
+    let mut scroll = String::new();
+    crate::sq::process(&request(1, "memory", command, phext::to_coordinate("1.1.1/1.1.1/1.1.1"), update, filename), &mut scroll, map);
+    scroll
+}
+
+#[test]
+fn test_json_export_import_roundtrip() {
+    let filename = format!("/tmp/sq-json-roundtrip-{}", std::process::id());
+    let mut source = crate::store::ScrollStore::new();
+    source.insert(phext::to_coordinate("1.1.1/1.1.1/1.1.1"), "quote \" backslash \\ tab \t newline \n".to_string());
+    source.insert(phext::to_coordinate("2.1.1/1.1.1/1.1.3"), "control \x07\x1b and unicode ✓".to_string());
+    let exported = run_json(&mut source, "json-export", "", &filename);
+    assert_eq!(std::fs::read_to_string(format!("{}.json", filename)).unwrap(), exported);
+    let _ = std::fs::remove_file(format!("{}.json", filename));
+
+    let mut target = crate::store::ScrollStore::new();
+    let report: serde_json::Value = serde_json::from_str(&run_json(&mut target, "json-import", &exported, "")).unwrap();
+    assert_eq!(report["imported"], 2);
+    assert_eq!(report["errors"].as_array().unwrap().len(), 0);
+    assert_eq!(target.implode(), source.implode());
+}
+
+#[test]
+fn test_json_import_merge_reports_bad_records() {
+    let mut map = selection_fixture();
+    let document = r#"[
+        {"coord": "1.1.1/1.1.1/1.1.1", "scroll": "merged"},
+        {"coord": "0.1.1/1.1.1/1.1.1", "scroll": "zero"},
+        {"coord": "not a coordinate", "scroll": "junk"},
+        {"coord": "9.1.1/1.1.1/1.1.1"}
+    ]"#;
+    let report: serde_json::Value = serde_json::from_str(&run_json(&mut map, "json-import", document, "")).unwrap();
+    assert_eq!(report["mode"], "merge");
+    assert_eq!(report["imported"], 1);
+    let failed: Vec<u64> = report["errors"].as_array().unwrap().iter().map(|e| e["index"].as_u64().unwrap()).collect();
+    assert_eq!(failed, vec![1, 2, 3]);
+    assert_eq!(map.get(&phext::to_coordinate("1.1.1/1.1.1/1.1.1")).unwrap(), "merged");
+    assert_eq!(map.len(), 6);
+}
+
+#[test]
+fn test_json_import_replace() {
+    let mut map = selection_fixture();
+    let document = "mode=replace\n[{\"coord\": \"3.1.1/1.1.1/1.1.1\", \"scroll\": \"only\"}]";
+    let report: serde_json::Value = serde_json::from_str(&run_json(&mut map, "json-import", document, "")).unwrap();
+    assert_eq!(report["imported"], 1);
+    assert_eq!(map.len(), 1);
+
+    // a replace with any bad record leaves the store untouched
+    let document = "mode=replace\n[{\"coord\": \"4.1.1/1.1.1/1.1.1\", \"scroll\": \"new\"}, {\"coord\": \"bad\", \"scroll\": \"\"}]";
+    let report: serde_json::Value = serde_json::from_str(&run_json(&mut map, "json-import", document, "")).unwrap();
+    assert_eq!(report["imported"], 0);
+    assert_eq!(map.get(&phext::to_coordinate("3.1.1/1.1.1/1.1.1")).unwrap(), "only");
+
+    let report: serde_json::Value = serde_json::from_str(&run_json(&mut map, "json-import", "{}", "")).unwrap();
+    assert!(report["error"].is_string());
+}
+
+// Reads run under the shared lock: a held read lock must not block another reader
+#[test]
+fn test_shared_query_runs_alongside_readers() {
+    use std::sync::{Arc, RwLock};
+    let mut state = crate::ServerState::new();
+    state.loaded_phext = "shared.phext".to_string();
+    state.loaded_map = selection_fixture();
+    let state = Arc::new(RwLock::new(state));
+    let coordinate = phext::to_coordinate("1.1.1/1.1.1/1.1.1");
+
+    let held = state.read().unwrap();
+    let reader = {
+        let state = Arc::clone(&state);
+        std::thread::spawn(move || {
+            crate::shared_query(&state, &request(1, "shared.phext", "select", coordinate, "", "shared.phext"), None)
+        })
+    };
+    let output = reader.join().unwrap();
+    drop(held);
+    assert_eq!(output.as_deref(), state.read().unwrap().loaded_map.get(&coordinate).map(|s| s.as_str()));
+
+    // a phext that isn't resident has to be loaded under the write lock first
+    assert!(crate::shared_query(&state, &request(1, "other.phext", "select", coordinate, "", "other.phext"), None).is_none());
+    assert!(crate::is_read_only("search"));
+    assert!(!crate::is_read_only("json-import"));
+    assert!(!crate::is_read_only("delete"));
+}
+
+// A mesh peer's reads only ever see the scrolls in its scope
+#[test]
+fn test_read_query_limits_peers_to_scope() {
+    use crate::sq::CoordinateFilter;
+    let mut state = crate::ServerState::new();
+    state.loaded_phext = "shared.phext".to_string();
+    state.loaded_map = selection_fixture();
+    let read = |command: &str, coordinate: &str, scroll: &str, scope: Option<&CoordinateFilter>| {
+        crate::read_query(&state, &request(1, "shared.phext", command, phext::to_coordinate(coordinate), scroll, "shared.phext"), scope)
+    };
+    let plays = CoordinateFilter::parse(&["2.1.x/*/*"], &[]).unwrap();
+
+    assert_eq!(read("select", "1.1.1/1.1.1/1.1.1", "", None), "self");
+    assert_eq!(read("select", "1.1.1/1.1.1/1.1.1", "", Some(&plays)), "");
+    assert_eq!(read("select", "2.1.1/1.1.1/1.1.1", "", Some(&plays)), "play-a");
+    let everything = read("get", "1.1.1/1.1.1/1.1.1", "", Some(&plays));
+    let mut found: Vec<String> = phext::explode(&everything).into_values().filter(|v| !v.is_empty()).collect();
+    found.sort();
+    assert_eq!(found, vec!["play-a", "play-b", "play-c"]);
+    assert!(!read("select-prefix", "", "prefix=*/*/*", Some(&plays)).contains("work"));
+    // delta and merkle requests carry the scope as filter lines instead
+    let request = crate::delta_query(&std::collections::HashMap::new(), Some(&plays), "1.1.1/1.1.1/1.1.1: stale").unwrap();
+    let delta = read("delta", "", &request, Some(&plays));
+    assert!(delta.contains("play-a") && !delta.contains("self") && !delta.contains("work"));
+    assert_eq!(read("get", "1.1.1/1.1.1/1.1.1", "", Some(&CoordinateFilter::nothing())), "");
+}
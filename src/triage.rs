@@ -5,12 +5,11 @@
 // v0.6.0 - Routes prompts to cache, local ollama, or upstream API
 //------------------------------------------------------------------------------------------------------------
 
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
 
-/// Which tier handles this prompt
+/// Which tier handles a prompt that missed the cache
 #[derive(Debug, Clone, PartialEq, Serialize)]
 pub enum Tier {
-    Cache,
     Local,
     Upstream,
 }
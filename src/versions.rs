@@ -0,0 +1,287 @@
+//------------------------------------------------------------------------------------------------------------
+// file: versions.rs
+// purpose: per-scroll version metadata for mesh sync - who last wrote a scroll, when, and what it replaced
+//
+// A version is a hybrid logical clock stamp plus the id of the node that wrote it. The stamp's upper 48 bits
+// are unix milliseconds and the lower 16 a counter, so stamps follow wall time but never go backwards on a
+// node, and a node that sees a peer's stamp never issues a smaller one afterwards.
+//
+// Every scroll also carries the versions it replaced (newest first, at most HISTORY_DEPTH). A scroll pulled
+// from a peer keeps the peer's version and history, so two nodes holding the same edit agree on its version.
+// That is what conflict detection (conflict.rs) relies on: a version whose history contains ours was built
+// on top of ours; if neither side's history contains the other, both were edited concurrently. Scrolls
+// written before versioning was enabled have the default (unversioned) version.
+//
+// Versions are only tracked in mesh mode (enable). They live in `<file>.versions` next to the phext, one
+// JSON record per change, rewritten in full whenever the write-ahead log is compacted:
+//   {"c":"1.1.1/1.1.1/1.1.1","v":{"stamp":...,"node":"...","history":[...]}}
+// A local delete is stamped like any other edit and kept as a tombstone ("deleted":true), so a peer that
+// still has the scroll can't put it back unless its copy was edited after the delete. A null version (written
+// before tombstones) just forgets the scroll.
+//------------------------------------------------------------------------------------------------------------
+
+use crate::persist;
+use crate::phext;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const HISTORY_DEPTH: usize = 8;
+
+static NODE: OnceLock<String> = OnceLock::new();
+static CLOCK: Mutex<u64> = Mutex::new(0);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Version {
+    pub stamp: u64,
+    pub node: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScrollVersion {
+    #[serde(flatten)]
+    pub version: Version,
+    #[serde(default)]
+    pub history: Vec<Version>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deleted: bool,
+}
+
+impl ScrollVersion {
+    // -------------------------------------------------------------------------------------------------------
+    // descends_from: true if this version is `other` or was (transitively) written on top of it
+    // -------------------------------------------------------------------------------------------------------
+    pub fn descends_from(&self, other: &ScrollVersion) -> bool {
+        self.version == other.version || self.history.contains(&other.version)
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // successor: the version of a local edit replacing this one
+    // -------------------------------------------------------------------------------------------------------
+    pub fn successor(&self, stamp: u64, node: &str) -> ScrollVersion {
+        let mut history = vec![self.version.clone()];
+        history.extend(self.history.iter().take(HISTORY_DEPTH - 1).cloned());
+        ScrollVersion { version: Version { stamp, node: node.to_string() }, history, deleted: false }
+    }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// enable: turns on version tracking for this process, stamping local edits with `node_id`
+// -----------------------------------------------------------------------------------------------------------
+pub fn enable(node_id: &str) {
+    let _ = NODE.set(node_id.to_string());
+}
+
+pub fn node() -> Option<&'static str> {
+    NODE.get().map(|id| id.as_str())
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// tick: the next hybrid logical clock stamp for a local edit
+// -----------------------------------------------------------------------------------------------------------
+pub fn tick() -> u64 {
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+    let mut clock = CLOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    *clock = (*clock + 1).max(millis << 16);
+    *clock
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// observe: advances the clock past a stamp received from a peer
+// -----------------------------------------------------------------------------------------------------------
+pub fn observe(stamp: u64) {
+    let mut clock = CLOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    *clock = (*clock).max(stamp);
+}
+
+pub fn table_path(phext_path: &str) -> String {
+    format!("{}.versions", phext_path)
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    c: String,
+    v: Option<ScrollVersion>,
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// VersionTable: the versions of one phext's scrolls, with the open append handle for its sidecar
+// -----------------------------------------------------------------------------------------------------------
+pub struct VersionTable {
+    path: String,
+    file: Option<File>,
+    entries: HashMap<phext::Coordinate, ScrollVersion>,
+}
+
+impl VersionTable {
+    // -------------------------------------------------------------------------------------------------------
+    // open: reads `<phext>.versions` (later records win) and opens it for appending
+    // -------------------------------------------------------------------------------------------------------
+    pub fn open(phext_path: &str) -> VersionTable {
+        let path = table_path(phext_path);
+        let mut entries = HashMap::new();
+        if let Ok(content) = std::fs::read_to_string(&path) {
+            for line in content.lines() {
+                // a torn final line after a crash is skipped
+                let entry: Entry = match serde_json::from_str(line) {
+                    Ok(entry) => entry,
+                    Err(_) => continue,
+                };
+                let coordinate = phext::to_coordinate(&entry.c);
+                match entry.v {
+                    Some(version) => {
+                        observe(version.version.stamp);
+                        entries.insert(coordinate, version);
+                    }
+                    None => { entries.remove(&coordinate); }
+                }
+            }
+        }
+        let file = match OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(file) => Some(file),
+            Err(e) => {
+                eprintln!("Warning: Failed to open version table {} (versions kept in memory only): {}", path, e);
+                None
+            }
+        };
+        VersionTable { path, file, entries }
+    }
+
+    pub fn get(&self, coordinate: &phext::Coordinate) -> ScrollVersion {
+        self.entries.get(coordinate).cloned().unwrap_or_default()
+    }
+
+    fn append(&mut self, coordinate: phext::Coordinate, version: Option<&ScrollVersion>) {
+        if let Some(ref mut file) = self.file {
+            let entry = serde_json::json!({ "c": coordinate.to_string(), "v": version });
+            if let Err(e) = writeln!(file, "{}", entry) {
+                eprintln!("Warning: Failed to append to version table {}: {}", self.path, e);
+            }
+        }
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // stamp: records a local edit of `coordinate` (`exists` is false once the scroll was deleted, which leaves
+    // a tombstone)
+    // -------------------------------------------------------------------------------------------------------
+    pub fn stamp(&mut self, coordinate: phext::Coordinate, exists: bool) {
+        let mut version = self.get(&coordinate).successor(tick(), node().unwrap_or_default());
+        version.deleted = !exists;
+        self.append(coordinate, Some(&version));
+        self.entries.insert(coordinate, version);
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // adopt: records a scroll pulled from a peer, keeping the peer's version
+    // -------------------------------------------------------------------------------------------------------
+    pub fn adopt(&mut self, coordinate: phext::Coordinate, version: ScrollVersion) {
+        observe(version.version.stamp);
+        self.append(coordinate, Some(&version));
+        self.entries.insert(coordinate, version);
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // report: the versions of `coordinates` as a JSON object (served at /api/v2/mesh/versions)
+    //   unversioned scrolls are left out
+    // -------------------------------------------------------------------------------------------------------
+    pub fn report<'a, I: IntoIterator<Item = &'a phext::Coordinate>>(&self, coordinates: I) -> String {
+        let mut result = serde_json::Map::new();
+        for coordinate in coordinates {
+            if let Some(version) = self.entries.get(coordinate) {
+                result.insert(coordinate.to_string(), serde_json::to_value(version).unwrap_or_default());
+            }
+        }
+        serde_json::Value::Object(result).to_string()
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // compact: rewrites the sidecar with one record per versioned scroll
+    // -------------------------------------------------------------------------------------------------------
+    pub fn compact(&mut self) -> std::io::Result<()> {
+        let mut coordinates: Vec<&phext::Coordinate> = self.entries.keys().collect();
+        coordinates.sort();
+        let mut buffer = String::new();
+        for coordinate in coordinates {
+            let entry = serde_json::json!({ "c": coordinate.to_string(), "v": &self.entries[coordinate] });
+            buffer.push_str(&entry.to_string());
+            buffer.push('\n');
+        }
+        persist::write_atomic(&self.path, buffer)?;
+        self.file = Some(OpenOptions::new().create(true).append(true).open(&self.path)?);
+        Ok(())
+    }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// parse_report: reads a peer's /api/v2/mesh/versions answer
+// -----------------------------------------------------------------------------------------------------------
+pub fn parse_report(body: &[u8]) -> Result<HashMap<phext::Coordinate, ScrollVersion>, String> {
+    let report: HashMap<String, ScrollVersion> = serde_json::from_slice(body).map_err(|e| e.to_string())?;
+    Ok(report.into_iter().map(|(c, v)| (phext::to_coordinate(&c), v)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(stamp: u64, node: &str) -> ScrollVersion {
+        ScrollVersion { version: Version { stamp, node: node.to_string() }, history: Vec::new(), deleted: false }
+    }
+
+    #[test]
+    fn test_successor_history() {
+        let base = version(5, "a");
+        let edited = base.successor(9, "b");
+        assert!(edited.descends_from(&base));
+        assert!(!base.descends_from(&edited));
+        assert!(edited.successor(12, "b").descends_from(&base));
+
+        // a sibling edit of the same base is concurrent
+        let other = base.successor(10, "c");
+        assert!(!other.descends_from(&edited) && !edited.descends_from(&other));
+
+        let mut deep = base.clone();
+        for stamp in 0..HISTORY_DEPTH as u64 + 1 {
+            deep = deep.successor(100 + stamp, "a");
+        }
+        assert_eq!(deep.history.len(), HISTORY_DEPTH);
+        assert!(!deep.descends_from(&base));
+    }
+
+    #[test]
+    fn test_clock_is_monotonic() {
+        let first = tick();
+        observe(first + 1000);
+        assert!(tick() > first + 1000);
+    }
+
+    #[test]
+    fn test_table_round_trip() {
+        let dir = crate::tests::TempDir::new("versions");
+        let phext = dir.path("table.phext");
+        let a = phext::to_coordinate("1.1.1/1.1.1/1.1.1");
+        let b = phext::to_coordinate("1.1.1/1.1.1/1.1.2");
+        {
+            let mut table = VersionTable::open(&phext);
+            table.stamp(a, true);
+            table.adopt(b, version(42, "peer"));
+            table.stamp(b, false);
+            table.stamp(a, true);
+        }
+        let mut table = VersionTable::open(&phext);
+        assert_eq!(table.get(&a).history.len(), 2);
+        // the delete left a tombstone on top of the peer's version
+        assert!(table.get(&b).deleted && !table.get(&a).deleted);
+        assert!(table.get(&b).descends_from(&version(42, "peer")));
+        table.compact().unwrap();
+        assert_eq!(std::fs::read_to_string(table_path(&phext)).unwrap().lines().count(), 2);
+
+        let report = parse_report(table.report([&a, &b]).as_bytes()).unwrap();
+        assert_eq!(report.len(), 2);
+        assert_eq!(report[&a], table.get(&a));
+        assert_eq!(report[&b], table.get(&b));
+    }
+}
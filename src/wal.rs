@@ -0,0 +1,333 @@
+//------------------------------------------------------------------------------------------------------------
+// file: wal.rs
+// purpose: append-only write-ahead log for REST mutations
+//
+// Every hosted phext gets a sibling `<file>.wal`. Mutations append one record per touched scroll instead of
+// rewriting the whole phext; fetch_source replays the log on load and the background compactor folds it back
+// into the main file.
+//
+// record layout (text header + raw payload):
+//   <op> <coordinate> <payload bytes>\n<payload>\n
+//
+//   U  scroll now holds <payload>
+//   D  scroll was removed (payload is empty)
+//
+// Records always carry the resulting scroll (inserts are logged as the concatenated text), so replaying a
+// record twice is harmless. That lets compaction rotate the log into `<file>.wal.compacting`, write the main
+// file without holding the state lock, and only then drop the rotated records.
+//------------------------------------------------------------------------------------------------------------
+
+use crate::phext;
+use crate::store::ScrollStore;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+
+// -----------------------------------------------------------------------------------------------------------
+// Durability: when appended records are forced to stable storage
+//   none   - never fsync; the OS flushes whenever it likes
+//   batch  - fsync on the compactor tick (at most ~1 second of mutations at risk)
+//   always - fsync every record before the request is acknowledged
+// -----------------------------------------------------------------------------------------------------------
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Durability {
+    None,
+    Batch,
+    Always,
+}
+
+impl Durability {
+    pub fn parse(value: &str) -> Option<Durability> {
+        match value.to_lowercase().as_str() {
+            "none" => Some(Durability::None),
+            "batch" => Some(Durability::Batch),
+            "always" => Some(Durability::Always),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Durability::None => "none",
+            Durability::Batch => "batch",
+            Durability::Always => "always",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Op {
+    Update,
+    Delete,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Record {
+    pub coordinate: phext::Coordinate,
+    pub op: Op,
+    pub payload: String,
+}
+
+pub fn log_path(phext_path: &str) -> String {
+    format!("{}.wal", phext_path)
+}
+
+pub fn compacting_path(phext_path: &str) -> String {
+    format!("{}.wal.compacting", phext_path)
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// encode: serializes a single record
+// -----------------------------------------------------------------------------------------------------------
+pub fn encode(record: &Record) -> Vec<u8> {
+    let op = match record.op {
+        Op::Update => 'U',
+        Op::Delete => 'D',
+    };
+    let mut bytes = format!("{} {} {}\n", op, record.coordinate, record.payload.len()).into_bytes();
+    bytes.extend_from_slice(record.payload.as_bytes());
+    bytes.push(b'\n');
+    bytes
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// decode: parses every complete record in a log buffer
+//   stops at the first torn or malformed record - anything after a crash mid-append is discarded
+// -----------------------------------------------------------------------------------------------------------
+pub fn decode(bytes: &[u8]) -> Vec<Record> {
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let header_end = match bytes[offset..].iter().position(|b| *b == b'\n') {
+            Some(pos) => offset + pos,
+            None => break,
+        };
+        let header = String::from_utf8_lossy(&bytes[offset..header_end]).to_string();
+        let parts: Vec<&str> = header.split(' ').collect();
+        if parts.len() != 3 {
+            break;
+        }
+        let op = match parts[0] {
+            "U" => Op::Update,
+            "D" => Op::Delete,
+            _ => break,
+        };
+        let coordinate = phext::to_coordinate(parts[1]);
+        let length: usize = match parts[2].parse() {
+            Ok(n) => n,
+            Err(_) => break,
+        };
+        let payload_start = header_end + 1;
+        let payload_end = payload_start + length;
+        if payload_end >= bytes.len() || bytes[payload_end] != b'\n' {
+            break;
+        }
+        let payload = String::from_utf8_lossy(&bytes[payload_start..payload_end]).to_string();
+        records.push(Record { coordinate, op, payload });
+        offset = payload_end + 1;
+    }
+    records
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// apply: plays a record onto an exploded phext
+// -----------------------------------------------------------------------------------------------------------
+pub fn apply(record: Record, map: &mut ScrollStore) {
+    match record.op {
+        Op::Update => { map.insert(record.coordinate, record.payload); }
+        Op::Delete => { map.remove(&record.coordinate); }
+    }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// replay: applies any pending log records for `phext_path` onto `map`
+//   records left behind by an interrupted compaction are applied first
+// -----------------------------------------------------------------------------------------------------------
+pub fn replay(phext_path: &str, map: &mut ScrollStore) -> usize {
+    let mut applied = 0;
+    for path in [compacting_path(phext_path), log_path(phext_path)] {
+        let bytes = match std::fs::read(&path) {
+            Ok(b) => b,
+            Err(_) => continue,
+        };
+        for record in decode(&bytes) {
+            apply(record, map);
+            applied += 1;
+        }
+    }
+    applied
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// discard: drops every pending record for `phext_path` (used once the main file is known to be current)
+// -----------------------------------------------------------------------------------------------------------
+pub fn discard(phext_path: &str) {
+    let _ = std::fs::remove_file(compacting_path(phext_path));
+    let _ = std::fs::remove_file(log_path(phext_path));
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// finish_compaction: the main file now holds every rotated record, so the rotated log can go
+// -----------------------------------------------------------------------------------------------------------
+pub fn finish_compaction(phext_path: &str) -> std::io::Result<()> {
+    match std::fs::remove_file(compacting_path(phext_path)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+// -----------------------------------------------------------------------------------------------------------
+// WriteAheadLog: the open append handle for one phext
+// -----------------------------------------------------------------------------------------------------------
+pub struct WriteAheadLog {
+    phext_path: String,
+    file: File,
+    durability: Durability,
+    size: u64,
+    dirty: bool,
+}
+
+impl WriteAheadLog {
+    pub fn open(phext_path: &str, durability: Durability) -> std::io::Result<WriteAheadLog> {
+        let file = OpenOptions::new().create(true).append(true).open(log_path(phext_path))?;
+        let size = file.metadata()?.len();
+        Ok(WriteAheadLog {
+            phext_path: phext_path.to_string(),
+            file,
+            durability,
+            size,
+            dirty: false,
+        })
+    }
+
+    pub fn len(&self) -> u64 {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // append: logs the current state of one scroll (None = removed)
+    // -------------------------------------------------------------------------------------------------------
+    pub fn append(&mut self, coordinate: phext::Coordinate, scroll: Option<&str>) -> std::io::Result<()> {
+        let record = match scroll {
+            Some(text) => Record { coordinate, op: Op::Update, payload: text.to_string() },
+            None => Record { coordinate, op: Op::Delete, payload: String::new() },
+        };
+        let bytes = encode(&record);
+        self.file.write_all(&bytes)?;
+        self.size += bytes.len() as u64;
+        self.dirty = true;
+        if self.durability == Durability::Always {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // sync: forces appended records to disk (no-op when nothing changed or durability is none)
+    // -------------------------------------------------------------------------------------------------------
+    pub fn sync(&mut self) -> std::io::Result<()> {
+        if !self.dirty || self.durability == Durability::None {
+            return Ok(());
+        }
+        self.file.sync_data()?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    // -------------------------------------------------------------------------------------------------------
+    // rotate: moves the current records into <file>.wal.compacting and starts an empty log
+    //   if an earlier compaction never finished, the new records are appended to its leftovers instead
+    // -------------------------------------------------------------------------------------------------------
+    pub fn rotate(&mut self) -> std::io::Result<()> {
+        self.sync()?;
+        let current = log_path(&self.phext_path);
+        let rotated = compacting_path(&self.phext_path);
+        if std::path::Path::new(&rotated).exists() {
+            let pending = std::fs::read(&current)?;
+            let mut leftovers = OpenOptions::new().append(true).open(&rotated)?;
+            leftovers.write_all(&pending)?;
+            if self.durability != Durability::None {
+                leftovers.sync_data()?;
+            }
+            self.file.set_len(0)?;
+        } else {
+            std::fs::rename(&current, &rotated)?;
+            self.file = OpenOptions::new().create(true).append(true).open(&current)?;
+        }
+        self.size = 0;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod wal_tests {
+    use super::*;
+    use crate::tests::TempDir;
+
+    #[test]
+    fn test_encode_decode_roundtrip() {
+        let records = vec![
+            Record { coordinate: phext::to_coordinate("1.1.1/1.1.1/1.1.2"), op: Op::Update, payload: "a\nb\x17c".to_string() },
+            Record { coordinate: phext::to_coordinate("2.1.1/1.1.1/1.1.1"), op: Op::Delete, payload: String::new() },
+        ];
+        let mut bytes = Vec::new();
+        for record in &records {
+            bytes.extend_from_slice(&encode(record));
+        }
+        assert_eq!(decode(&bytes), records);
+    }
+
+    #[test]
+    fn test_decode_ignores_torn_tail() {
+        let record = Record { coordinate: phext::to_coordinate("1.1.1/1.1.1/1.1.1"), op: Op::Update, payload: "hello".to_string() };
+        let mut bytes = encode(&record);
+        bytes.extend_from_slice(b"U 1.1.1/1.1.1/1.1.2 100\npartial");
+        assert_eq!(decode(&bytes), vec![record]);
+    }
+
+    #[test]
+    fn test_append_and_replay() {
+        let dir = TempDir::new("wal-replay");
+        let path = dir.path("world.phext");
+        let first = phext::to_coordinate("1.1.1/1.1.1/1.1.1");
+        let second = phext::to_coordinate("1.1.1/1.1.1/1.1.2");
+        {
+            let mut wal = WriteAheadLog::open(&path, Durability::Always).unwrap();
+            wal.append(first, Some("one")).unwrap();
+            wal.append(second, Some("two")).unwrap();
+            wal.append(first, None).unwrap();
+            assert!(!wal.is_empty());
+        }
+
+        let mut map = ScrollStore::new();
+        assert_eq!(replay(&path, &mut map), 3);
+        assert_eq!(map.get(&first), None);
+        assert_eq!(map.get(&second), Some(&"two".to_string()));
+    }
+
+    #[test]
+    fn test_rotate_keeps_records_until_finished() {
+        let dir = TempDir::new("wal-rotate");
+        let path = dir.path("world.phext");
+        let coord = phext::to_coordinate("1.1.1/1.1.1/1.1.3");
+        let mut wal = WriteAheadLog::open(&path, Durability::Batch).unwrap();
+        wal.append(coord, Some("before")).unwrap();
+        wal.rotate().unwrap();
+        assert!(wal.is_empty());
+        wal.append(coord, Some("after")).unwrap();
+        wal.sync().unwrap();
+
+        // compaction interrupted: both generations replay, newest last
+        let mut map = ScrollStore::new();
+        assert_eq!(replay(&path, &mut map), 2);
+        assert_eq!(map.get(&coord), Some(&"after".to_string()));
+
+        finish_compaction(&path).unwrap();
+        let mut map = ScrollStore::new();
+        assert_eq!(replay(&path, &mut map), 1);
+    }
+}